@@ -0,0 +1,183 @@
+use embedded_hal::blocking::i2c::Read;
+use futures::stream::StreamExt;
+use linux_embedded_hal::gpio_cdev::Chip;
+use linux_embedded_hal::{I2cdev, Spidev};
+
+use std::{error::Error, net::TcpListener, path::Path};
+
+use tokio::time;
+
+use crate::cli::Args;
+use crate::config::{Config, DisplayConfig, Driver, Interface, MetricsConfig, SerialConfig};
+use crate::display::Animation;
+use crate::protocol::MachineProtocol;
+use crate::{counter, source};
+
+// How long to wait for the Mara X to send a status line.
+const SERIAL_PROBE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+fn check_display(config: &DisplayConfig) -> Result<String, Box<dyn Error>> {
+    if !config.enabled {
+        return Ok("disabled".to_string());
+    }
+
+    if config.driver == Driver::Tm1637 {
+        // Talking to the TM1637 would change what it shows, so just check
+        // that the GPIO chip exists.
+        Chip::new(&config.gpio_chip)
+            .map_err(|e| format!("failed to open {}: {}", config.gpio_chip, e))?;
+        return Ok(format!(
+            "{} is available (TM1637 displays can't be probed)",
+            config.gpio_chip
+        ));
+    }
+
+    if config.interface == Interface::Spi {
+        // SPI displays can't be read from, so the best we can do is to check
+        // that the device exists.
+        Spidev::open(&config.spi_device)
+            .map_err(|e| format!("failed to open {}: {}", config.spi_device, e))?;
+        return Ok(format!(
+            "{} is available (SPI displays can't be probed)",
+            config.spi_device
+        ));
+    }
+
+    let mut i2c = I2cdev::new(&config.i2c_bus)
+        .map_err(|e| format!("failed to open {}: {}", config.i2c_bus, e))?;
+
+    // The SSD1306 answers a read with its status byte, which doesn't change
+    // anything on the display.
+    let mut status = [0u8; 1];
+    i2c.read(config.i2c_address, &mut status).map_err(|e| {
+        format!(
+            "no device responding at {:#04x} on {}: {}",
+            config.i2c_address, config.i2c_bus, e
+        )
+    })?;
+
+    Ok(format!(
+        "found device at {:#04x} on {}",
+        config.i2c_address, config.i2c_bus
+    ))
+}
+
+fn check_animation(config: &DisplayConfig) -> Result<String, Box<dyn Error>> {
+    let animation = Animation::load(config)
+        .map_err(|e| e.to_string())?
+        .ok_or("no animation directory")?;
+    Ok(format!(
+        "{} frames of {}x{} pixels",
+        animation.frame_count(),
+        config.animation_width,
+        config.animation_height
+    ))
+}
+
+async fn check_serial(
+    config: &SerialConfig,
+    protocol: &dyn MachineProtocol,
+) -> Result<String, Box<dyn Error>> {
+    if config.device == source::AUTO_DEVICE {
+        let device = source::discover(config, protocol)
+            .await
+            .map_err(|e| e.to_string())?;
+        return Ok(format!("found the Mara X at {}", device));
+    }
+
+    let mut lines = source::open(config, protocol).await?;
+
+    let deadline = time::Instant::now() + SERIAL_PROBE_TIMEOUT;
+    let mut last_line = None;
+
+    while let Ok(Some(line)) = time::timeout_at(deadline, lines.next()).await {
+        let line = line?;
+        if protocol.parse(&line).is_ok() {
+            return Ok(format!(
+                "{} at {} baud, received \"{}\"",
+                config.device,
+                config.baud,
+                line.trim_end()
+            ));
+        }
+        last_line = Some(line);
+    }
+
+    match last_line {
+        Some(line) => Err(format!(
+            "{} is open, but the data doesn't look like Mara X status lines: \"{}\"",
+            config.device,
+            line.trim_end()
+        )
+        .into()),
+        None => Err(format!(
+            "{} is open, but nothing was received in {} seconds",
+            config.device,
+            SERIAL_PROBE_TIMEOUT.as_secs()
+        )
+        .into()),
+    }
+}
+
+fn check_metrics(config: &MetricsConfig) -> Result<String, Box<dyn Error>> {
+    TcpListener::bind(config.address)
+        .map_err(|e| format!("can't listen on {}: {}", config.address, e))?;
+    Ok(format!("{} is available", config.address))
+}
+
+fn report(name: &str, result: Result<String, Box<dyn Error>>) -> bool {
+    match result {
+        Ok(msg) => {
+            println!("[ OK ] {}: {}", name, msg);
+            true
+        }
+        Err(e) => {
+            println!("[FAIL] {}: {}", name, e);
+            false
+        }
+    }
+}
+
+/// Validate the configuration and probe the hardware it refers to. Returns
+/// true if everything was found.
+pub async fn check_config(args: &Args) -> bool {
+    let config = match Config::load(args) {
+        Ok(config) => {
+            report("config", Ok("valid".to_string()));
+            config
+        }
+        Err(e) => {
+            report("config", Err(e));
+            return false;
+        }
+    };
+
+    let mut ok = report("display", check_display(&config.display));
+    for (i, display) in config.displays.iter().enumerate() {
+        ok &= report(&format!("display {}", i + 2), check_display(display));
+    }
+    if config.display.animation_dir.is_some() {
+        ok &= report("animation", check_animation(&config.display));
+    }
+    if let Some(path) = &config.timer.counter_file {
+        let count = counter::load(Path::new(path)).map(|count| format!("{} shots", count));
+        ok &= report("shot count", count);
+    }
+    ok &= report(
+        "serial",
+        check_serial(&config.serial, config.profile.protocol()).await,
+    );
+    for machine in &config.machines {
+        ok &= report(
+            &format!("display ({})", machine.label),
+            check_display(&machine.display),
+        );
+        ok &= report(
+            &format!("serial ({})", machine.label),
+            check_serial(&machine.serial, machine.profile.protocol()).await,
+        );
+    }
+    ok &= report("metrics", check_metrics(&config.metrics));
+
+    ok
+}
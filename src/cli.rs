@@ -0,0 +1,71 @@
+use clap::{Parser, Subcommand};
+
+use std::{net::SocketAddr, path::PathBuf};
+
+/// Lelit Mara X shot timer and Prometheus exporter.
+///
+/// Command line options override the environment variables, which in turn
+/// override the values read from the configuration file.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Configuration file in TOML format.
+    #[arg(long, global = true, env = "MARAX_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Serial device connected to the Mara X [default: /dev/ttyS0].
+    #[arg(long, global = true)]
+    pub serial: Option<String>,
+
+    /// Serial port baud rate [default: 9600].
+    #[arg(long, global = true)]
+    pub baud: Option<u32>,
+
+    /// I2C bus device where the SSD1306 display is connected [default: /dev/i2c-1].
+    #[arg(long, global = true)]
+    pub i2c_bus: Option<String>,
+
+    /// Run without a display, only exporting the metrics.
+    #[arg(long, global = true)]
+    pub no_display: bool,
+
+    /// Address for the Prometheus metrics endpoint [default: 0.0.0.0:8081].
+    #[arg(long, global = true)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Append every status line with the time it was received to this file,
+    /// for replaying later.
+    #[arg(long, global = true)]
+    pub record: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Read the Mara X status from the serial port (the default).
+    Run,
+    /// Generate the status lines of a pretend Mara X instead of reading the
+    /// serial port.
+    Simulate,
+    /// Feed a recorded serial log instead of reading the serial port.
+    Replay {
+        /// File with one Mara X status line per line, optionally after the
+        /// time it was received as written by --record.
+        file: PathBuf,
+
+        /// Delay between the replayed lines without times in milliseconds.
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+
+        /// How many times faster than real time to replay.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+    /// Cycle through the digits on the display to verify the wiring.
+    TestDisplay,
+    /// Validate the configuration and probe the display, serial port and
+    /// metrics address.
+    CheckConfig,
+}
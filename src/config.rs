@@ -0,0 +1,1594 @@
+use serde::Deserialize;
+
+use std::{
+    collections::BTreeMap, convert::TryFrom, env, error::Error, fmt::Display, fs, net::SocketAddr,
+    path::Path, str::FromStr,
+};
+
+use crate::cli::Args;
+use crate::lang::Language;
+use crate::profile::Profile;
+
+// Location of the tz database, for checking the time zone names.
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    /// Serial device connected to the Mara X.
+    pub device: String,
+    /// Serial port baud rate.
+    pub baud: u32,
+    /// Bits in a character, from 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// Stop bits, 1 or 2.
+    pub stop_bits: u8,
+    pub flow_control: FlowControl,
+    /// The data is stale, and a running shot is stopped, when no line has
+    /// arrived for this many seconds.
+    pub stale_seconds: u32,
+    /// Send `keepalive_data` to the machine this often, or never if 0. Some
+    /// firmware stops sending the status without it.
+    pub keepalive_seconds: u32,
+    pub keepalive_data: String,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            device: "/dev/ttyS0".to_string(),
+            baud: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            flow_control: FlowControl::None,
+            stale_seconds: 5,
+            keepalive_seconds: 0,
+            keepalive_data: "\r\n".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+impl FromStr for Parity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Parity::None),
+            "odd" => Ok(Parity::Odd),
+            "even" => Ok(Parity::Even),
+            _ => Err(format!("unknown parity \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    #[default]
+    None,
+    /// XON/XOFF.
+    Software,
+    /// RTS/CTS.
+    Hardware,
+}
+
+impl FromStr for FlowControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FlowControl::None),
+            "software" => Ok(FlowControl::Software),
+            "hardware" => Ok(FlowControl::Hardware),
+            _ => Err(format!("unknown flow control \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Brightness {
+    Dimmest,
+    Dim,
+    #[default]
+    Normal,
+    Bright,
+    Brightest,
+}
+
+impl FromStr for Brightness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dimmest" => Ok(Brightness::Dimmest),
+            "dim" => Ok(Brightness::Dim),
+            "normal" => Ok(Brightness::Normal),
+            "bright" => Ok(Brightness::Bright),
+            "brightest" => Ok(Brightness::Brightest),
+            _ => Err(format!("unknown brightness level \"{}\"", s)),
+        }
+    }
+}
+
+impl Brightness {
+    /// Name of the level in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Brightness::Dimmest => "dimmest",
+            Brightness::Dim => "dim",
+            Brightness::Normal => "normal",
+            Brightness::Bright => "bright",
+            Brightness::Brightest => "brightest",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Driver {
+    #[default]
+    Ssd1306,
+    Sh1106,
+    Ssd1309,
+    Max7219,
+    Tm1637,
+    Hd44780,
+    St7789,
+    St7735,
+}
+
+impl FromStr for Driver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssd1306" => Ok(Driver::Ssd1306),
+            "sh1106" => Ok(Driver::Sh1106),
+            "ssd1309" => Ok(Driver::Ssd1309),
+            "max7219" => Ok(Driver::Max7219),
+            "tm1637" => Ok(Driver::Tm1637),
+            "hd44780" => Ok(Driver::Hd44780),
+            "st7789" => Ok(Driver::St7789),
+            "st7735" => Ok(Driver::St7735),
+            _ => Err(format!("unknown display driver \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interface {
+    #[default]
+    I2c,
+    Spi,
+}
+
+impl FromStr for Interface {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "i2c" => Ok(Interface::I2c),
+            "spi" => Ok(Interface::Spi),
+            _ => Err(format!("unknown display interface \"{}\"", s)),
+        }
+    }
+}
+
+/// Arrangement of the screen contents.
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// The time or temperature with labels and the target values.
+    #[default]
+    Normal,
+    /// Just the digits, filling the whole screen.
+    Jumbo,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(Layout::Normal),
+            "jumbo" => Ok(Layout::Jumbo),
+            _ => Err(format!("unknown display layout \"{}\"", s)),
+        }
+    }
+}
+
+/// What the display does when a shot ends.
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AfterShot {
+    /// Clear the display right away.
+    Clear,
+    /// Keep the final time for `timer.hold_seconds`, followed by the summary
+    /// if there is one.
+    #[default]
+    Hold,
+    /// Keep the final time until the next shot starts.
+    HoldUntilNext,
+    /// Go back to the idle screen right away.
+    Idle,
+}
+
+impl FromStr for AfterShot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clear" => Ok(AfterShot::Clear),
+            "hold" => Ok(AfterShot::Hold),
+            "hold-until-next" => Ok(AfterShot::HoldUntilNext),
+            "idle" => Ok(AfterShot::Idle),
+            _ => Err(format!("unknown after shot behavior \"{}\"", s)),
+        }
+    }
+}
+
+/// Screen shown in turn with the others while the machine is idle.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Page {
+    /// The heat exchanger and steam boiler temperatures.
+    Temperatures,
+    /// Duration of the last shot.
+    LastShot,
+    /// Number of shots pulled today.
+    ShotCount,
+    /// Time of day.
+    Clock,
+    /// Difference of the steam boiler temperature from its target.
+    SteamDelta,
+    /// Number of shots pulled over the lifetime of the machine.
+    TotalShots,
+    /// Average time of the shots in the history.
+    AverageShot,
+    /// Median time of the shots in the history.
+    MedianShot,
+    /// The animation from `display.animation_dir`.
+    Animation,
+}
+
+impl FromStr for Page {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperatures" => Ok(Page::Temperatures),
+            "last-shot" => Ok(Page::LastShot),
+            "shot-count" => Ok(Page::ShotCount),
+            "clock" => Ok(Page::Clock),
+            "steam-delta" => Ok(Page::SteamDelta),
+            "total-shots" => Ok(Page::TotalShots),
+            "average-shot" => Ok(Page::AverageShot),
+            "median-shot" => Ok(Page::MedianShot),
+            "animation" => Ok(Page::Animation),
+            _ => Err(format!("unknown display page \"{}\"", s)),
+        }
+    }
+}
+
+impl Page {
+    /// Name of the page in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Page::Temperatures => "temperatures",
+            Page::LastShot => "last-shot",
+            Page::ShotCount => "shot-count",
+            Page::Clock => "clock",
+            Page::SteamDelta => "steam-delta",
+            Page::TotalShots => "total-shots",
+            Page::AverageShot => "average-shot",
+            Page::MedianShot => "median-shot",
+            Page::Animation => "animation",
+        }
+    }
+}
+
+/// Kind of screen a display can be set to show.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Screen {
+    /// The running shot timer and the final time after the shot.
+    Timer,
+    /// The idle screen with the temperatures and the other pages.
+    Idle,
+    /// The summary after a shot.
+    Summary,
+}
+
+impl FromStr for Screen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timer" => Ok(Screen::Timer),
+            "idle" => Ok(Screen::Idle),
+            "summary" => Ok(Screen::Summary),
+            _ => Err(format!("unknown display screen \"{}\"", s)),
+        }
+    }
+}
+
+/// Value shown by an element of a custom layout.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ElementValue {
+    /// Elapsed shot time, with tenths if they are enabled.
+    Elapsed,
+    /// Target shot time, if one is set.
+    TargetTime,
+    HxTemperature,
+    SteamTemperature,
+    TargetSteamTemperature,
+    /// Countdown of the boost mode while it's on.
+    BoostCountdown,
+    /// Flame icon while the heating element is on.
+    Heating,
+    /// Cup or steam icon for the machine mode.
+    Mode,
+    /// The fixed `text` of the element.
+    Text,
+}
+
+/// Font of an element of a custom layout.
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ElementFont {
+    /// The big digit font.
+    Digits,
+    /// 24x32 pixel text.
+    Large,
+    /// 6x8 pixel text.
+    #[default]
+    Small,
+}
+
+/// Element of a custom screen layout: a value drawn with its top left
+/// corner at the given position.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Element {
+    /// Either the timer or the idle screen.
+    pub screen: Screen,
+    pub value: ElementValue,
+    pub x: i32,
+    pub y: i32,
+    #[serde(default)]
+    pub font: ElementFont,
+    /// Text of the text elements.
+    #[serde(default)]
+    pub text: String,
+}
+
+/// Time of day as minutes since midnight, written as "HH:MM".
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(u32);
+
+impl TimeOfDay {
+    pub fn new(hour: u32, minute: u32) -> Self {
+        TimeOfDay(hour * 60 + minute)
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time of day \"{}\", expected HH:MM", s);
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        let hour: u32 = hour.parse().map_err(|_| invalid())?;
+        let minute: u32 = minute.parse().map_err(|_| invalid())?;
+        if hour > 23 || minute > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay::new(hour, minute))
+    }
+}
+
+/// Clockwise rotation of the display contents.
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(try_from = "u32")]
+pub enum Rotation {
+    #[default]
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    /// Returns true if the rotation swaps the width and the height.
+    pub fn is_quarter_turn(&self) -> bool {
+        matches!(self, Rotation::Rotate90 | Rotation::Rotate270)
+    }
+}
+
+impl TryFrom<u32> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u32) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::Rotate0),
+            90 => Ok(Rotation::Rotate90),
+            180 => Ok(Rotation::Rotate180),
+            270 => Ok(Rotation::Rotate270),
+            _ => Err(format!(
+                "invalid rotation {}, must be 0, 90, 180 or 270",
+                degrees
+            )),
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let degrees: u32 = s
+            .parse()
+            .map_err(|_| format!("invalid rotation \"{}\"", s))?;
+        Rotation::try_from(degrees)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// Set to false to run without a display.
+    pub enabled: bool,
+    /// Display controller chip.
+    pub driver: Driver,
+    /// Bus the display is connected to.
+    pub interface: Interface,
+    /// I2C bus device where the display is connected.
+    pub i2c_bus: String,
+    /// I2C address of the display.
+    pub i2c_address: u8,
+    /// SPI device where the display is connected.
+    pub spi_device: String,
+    /// SPI clock speed in Hz.
+    pub spi_speed_hz: u32,
+    /// GPIO chip device for the display control pins.
+    pub gpio_chip: String,
+    /// GPIO line of the data/command pin of an SPI display.
+    pub dc_pin: u32,
+    /// GPIO line of the reset pin of an SPI display, if connected.
+    pub reset_pin: Option<u32>,
+    /// GPIO line of the clock pin of a TM1637 display.
+    pub clk_pin: u32,
+    /// GPIO line of the data pin of a TM1637 display.
+    pub dio_pin: u32,
+    /// Width of a color display in pixels or a character display in
+    /// characters, if it differs from the default of the driver.
+    pub width: Option<u32>,
+    /// Height of a color display in pixels or a character display in rows,
+    /// if it differs from the default of the driver.
+    pub height: Option<u32>,
+    /// Clockwise rotation of the display in degrees.
+    pub rotation: Rotation,
+    /// Number of chained MAX7219 modules.
+    pub modules: u32,
+    /// Screen layout of the pixel based displays.
+    pub layout: Layout,
+    /// Display brightness level.
+    pub brightness: Brightness,
+    /// Brightness level during the night, if the display should be dimmed.
+    pub night_brightness: Option<Brightness>,
+    /// Start of the night.
+    pub night_start: TimeOfDay,
+    /// End of the night.
+    pub night_end: TimeOfDay,
+    /// Show the heat exchanger temperature when no shot is running.
+    pub idle_screen: bool,
+    /// Show also the steam boiler temperature on the idle screen.
+    pub idle_steam: bool,
+    /// Pages the idle screen goes through, in order.
+    pub pages: Vec<Page>,
+    /// How long each page is shown, in seconds.
+    pub page_seconds: u32,
+    /// Blank the display after this many minutes without changes in the
+    /// machine status, or 0 to never blank it.
+    pub blank_minutes: u32,
+    /// GPIO line of a button which wakes up the blanked display, if
+    /// connected.
+    pub wake_button_pin: Option<u32>,
+    /// Move the contents of OLED displays around and hide the static labels
+    /// now and then to avoid burning them in.
+    pub burn_in_protection: bool,
+    /// Show the heat exchanger temperature next to the running timer on
+    /// displays which have room for it.
+    pub timer_hx: bool,
+    /// Flash the display for a couple of seconds when the target shot time
+    /// is reached.
+    pub target_flash: bool,
+    /// Draw a line around the edges of the screen on the 64 pixel tall OLED
+    /// displays, filling up towards the target shot time.
+    pub progress_ring: bool,
+    /// Show a READY banner on the idle screen once the machine has settled
+    /// at the target temperature.
+    pub ready_banner: bool,
+    /// Time zone of the clock page, the night dimming and the daily shot
+    /// count, as a tz database name like "Europe/Helsinki". The system time
+    /// zone is used by default.
+    pub timezone: Option<String>,
+    /// Screens this display shows. The others leave the display as it is,
+    /// so that another display can show them. Messages are always shown.
+    pub screens: Vec<Screen>,
+    /// Custom layout of the timer and idle screens of the OLED displays.
+    /// The built-in layout is used for the screens without any elements.
+    pub elements: Vec<Element>,
+    /// Raw 1 bit per pixel image of the digits 0-9 to use instead of the
+    /// built-in seven segment font.
+    pub font_path: Option<String>,
+    /// Width of a digit in the font image in pixels.
+    pub font_width: u32,
+    /// Height of a digit in the font image in pixels.
+    pub font_height: u32,
+    /// Space between the digits on the screen in pixels.
+    pub font_spacing: u32,
+    /// Width of the whole font image in pixels, if the digits aren't on a
+    /// single row.
+    pub font_image_width: Option<u32>,
+    /// Directory of raw 1 bit per pixel frames for the animation page,
+    /// played in the order of the file names.
+    pub animation_dir: Option<String>,
+    /// Width of an animation frame in pixels.
+    pub animation_width: u32,
+    /// Height of an animation frame in pixels.
+    pub animation_height: u32,
+    /// Frames shown per second.
+    pub animation_fps: u32,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            driver: Driver::default(),
+            interface: Interface::default(),
+            i2c_bus: "/dev/i2c-1".to_string(),
+            i2c_address: 0x3c,
+            spi_device: "/dev/spidev0.0".to_string(),
+            spi_speed_hz: 8_000_000,
+            gpio_chip: "/dev/gpiochip0".to_string(),
+            dc_pin: 24,
+            reset_pin: Some(25),
+            clk_pin: 23,
+            dio_pin: 24,
+            width: None,
+            height: None,
+            rotation: Rotation::default(),
+            modules: 1,
+            layout: Layout::default(),
+            brightness: Brightness::default(),
+            night_brightness: None,
+            night_start: TimeOfDay::new(22, 0),
+            night_end: TimeOfDay::new(6, 0),
+            idle_screen: true,
+            idle_steam: true,
+            pages: vec![Page::Temperatures],
+            page_seconds: 5,
+            blank_minutes: 0,
+            wake_button_pin: None,
+            burn_in_protection: false,
+            timer_hx: true,
+            target_flash: true,
+            progress_ring: false,
+            ready_banner: false,
+            timezone: None,
+            screens: vec![Screen::Timer, Screen::Idle, Screen::Summary],
+            elements: Vec::new(),
+            font_path: None,
+            font_width: 22,
+            font_height: 40,
+            font_spacing: 4,
+            font_image_width: None,
+            animation_dir: None,
+            animation_width: 32,
+            animation_height: 32,
+            animation_fps: 5,
+        }
+    }
+}
+
+impl DisplayConfig {
+    /// Size of the display in pixels, or in characters for the segment and
+    /// character displays.
+    pub fn size(&self) -> (u32, u32) {
+        let (width, height) = match self.driver {
+            Driver::Ssd1306 | Driver::Sh1106 | Driver::Ssd1309 => (128, 64),
+            Driver::St7789 => (240, 240),
+            Driver::St7735 => (128, 160),
+            // Digits and rows of seven-segment displays.
+            Driver::Max7219 => (8 * self.modules, 1),
+            Driver::Tm1637 => (4, 1),
+            // Columns and rows of character displays.
+            Driver::Hd44780 => (16, 2),
+        };
+        (self.width.unwrap_or(width), self.height.unwrap_or(height))
+    }
+
+    /// Brightness level at the given time of day.
+    pub fn brightness_at(&self, time: TimeOfDay) -> Brightness {
+        let (start, end) = (self.night_start, self.night_end);
+        let night = if start <= end {
+            start <= time && time < end
+        } else {
+            // The night continues over midnight.
+            time >= start || time < end
+        };
+
+        match self.night_brightness {
+            Some(brightness) if night => brightness,
+            _ => self.brightness,
+        }
+    }
+
+    /// Make the local time follow the configured time zone, if there is one.
+    /// Changing the environment isn't safe while other threads may read it,
+    /// so this is done only before the runtime is started.
+    pub fn apply_timezone(&self) {
+        if let Some(timezone) = &self.timezone {
+            env::set_var("TZ", timezone);
+        }
+    }
+
+    /// Returns true if the configurations differ only in settings which can
+    /// be changed at runtime.
+    fn same_hardware(&self, other: &DisplayConfig) -> bool {
+        let with_runtime_settings = DisplayConfig {
+            layout: other.layout,
+            brightness: other.brightness,
+            night_brightness: other.night_brightness,
+            night_start: other.night_start,
+            night_end: other.night_end,
+            idle_screen: other.idle_screen,
+            idle_steam: other.idle_steam,
+            pages: other.pages.clone(),
+            page_seconds: other.page_seconds,
+            blank_minutes: other.blank_minutes,
+            burn_in_protection: other.burn_in_protection,
+            timer_hx: other.timer_hx,
+            target_flash: other.target_flash,
+            progress_ring: other.progress_ring,
+            ready_banner: other.ready_banner,
+            animation_fps: other.animation_fps,
+            elements: other.elements.clone(),
+            ..self.clone()
+        };
+        with_runtime_settings == *other
+    }
+}
+
+/// How the metrics are named.
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricNames {
+    /// The original names, like "SteamTemperature".
+    #[default]
+    Classic,
+    /// Names following the Prometheus conventions, like
+    /// "marax_steam_temperature_celsius".
+    Prometheus,
+}
+
+impl FromStr for MetricNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(MetricNames::Classic),
+            "prometheus" => Ok(MetricNames::Prometheus),
+            _ => Err(format!("unknown metric names \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address for the Prometheus metrics endpoint.
+    pub address: SocketAddr,
+    pub names: MetricNames,
+    /// Prefix of the Prometheus style names.
+    pub namespace: String,
+    /// Labels added to all the metrics, like the location of the machine.
+    pub labels: BTreeMap<String, String>,
+    /// Export the memory and CPU use of the process and the SoC
+    /// temperature.
+    pub host: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([0; 4], 8081)),
+            names: MetricNames::default(),
+            namespace: "marax".to_string(),
+            labels: BTreeMap::new(),
+            host: false,
+        }
+    }
+}
+
+/// Pushing the metrics to an OpenTelemetry collector.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// OTLP/HTTP metrics endpoint, like
+    /// "http://localhost:4318/v1/metrics". Nothing is pushed without one.
+    pub endpoint: Option<String>,
+    /// How often the metrics are pushed.
+    pub interval_seconds: u32,
+    /// HTTP headers sent with the metrics, like an authorization header.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            interval_seconds: 60,
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+/// Writing the temperatures and the shots to InfluxDB.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxDbConfig {
+    /// Address of the InfluxDB server, like "http://localhost:8086". Nothing
+    /// is written without one, and HTTPS isn't supported.
+    pub url: Option<String>,
+    pub org: String,
+    pub bucket: String,
+    /// API token with write access to the bucket.
+    pub token: String,
+    /// How often the collected points are written.
+    pub interval_seconds: u32,
+}
+
+impl Default for InfluxDbConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            org: String::new(),
+            bucket: String::new(),
+            token: String::new(),
+            interval_seconds: 10,
+        }
+    }
+}
+
+/// Publishing the status and the shots to an MQTT broker.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Address of the broker, like "localhost:1883". Nothing is published
+    /// without one.
+    pub broker: Option<String>,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix of the topics. The topics of a machine with a label are under
+    /// the label.
+    pub topic: String,
+    /// How often the latest status is published.
+    pub interval_seconds: u32,
+    /// Announce the sensors to Home Assistant.
+    pub discovery: bool,
+    /// Where Home Assistant looks for the announcements.
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            broker: None,
+            client_id: "marax-shot-timer".to_string(),
+            username: None,
+            password: None,
+            topic: "marax".to_string(),
+            interval_seconds: 5,
+            discovery: false,
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+/// Whether `name` is fit for a Prometheus metric or label name.
+fn valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimerConfig {
+    /// The timer stops counting after this many seconds.
+    pub max_seconds: u32,
+    /// Target extraction time in seconds, or 0 for no target.
+    pub target_seconds: u32,
+    /// Show the elapsed time with tenths of a second.
+    pub tenths: bool,
+    /// How long the final shot time is kept on the display after the pump
+    /// stops, in seconds.
+    pub hold_seconds: u32,
+    /// What the display does when the shot ends.
+    pub after_shot: AfterShot,
+    /// How long a summary of the shot is shown after the final time, in
+    /// seconds. 0 clears the screen right after the final time.
+    pub summary_seconds: u32,
+    /// Pump runs shorter than this many seconds are cooling flushes, which
+    /// aren't counted as shots. 0 counts every pump run.
+    pub min_shot_seconds: u32,
+    /// The pump has to read off on this many status lines in a row before
+    /// the shot stops.
+    pub pump_off_samples: u32,
+    /// Pump runs shorter than this many seconds, during which the heat
+    /// exchanger temperature drops less than `autofill_hx_drop` degrees, are
+    /// the steam boiler filling up. They are neither shown nor counted as
+    /// shots. 0 disables the detection.
+    pub autofill_max_seconds: u32,
+    /// Smallest heat exchanger temperature drop, in degrees, during a pump
+    /// run which isn't the steam boiler filling up.
+    pub autofill_hx_drop: u32,
+    /// Count down from the target time to zero, and then up again while
+    /// blinking, instead of showing the elapsed time.
+    pub countdown: bool,
+    /// The pre-infusion is taken to end this many seconds after the pump
+    /// starts, unless it's marked earlier with the button. 0 disables it.
+    pub preinfusion_seconds: u32,
+    /// GPIO line of a button which marks the end of the pre-infusion, on the
+    /// display GPIO chip.
+    pub preinfusion_pin: Option<u32>,
+    /// GPIO line of a button which marks the first drip from the
+    /// portafilter, on the display GPIO chip.
+    pub first_drip_pin: Option<u32>,
+    /// GPIO line of a button which starts and stops the timer without the
+    /// pump, on the display GPIO chip.
+    pub manual_pin: Option<u32>,
+    /// How many of the last shots and flushes are kept for the HTTP API, at
+    /// least one.
+    pub history_size: u32,
+    /// File where the number of shots pulled over the lifetime of the
+    /// machine is kept across restarts.
+    pub counter_file: Option<String>,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        Self {
+            max_seconds: 99,
+            target_seconds: 0,
+            tenths: true,
+            hold_seconds: 60,
+            after_shot: AfterShot::Hold,
+            summary_seconds: 0,
+            min_shot_seconds: 0,
+            pump_off_samples: 1,
+            autofill_max_seconds: 0,
+            autofill_hx_drop: 2,
+            countdown: false,
+            preinfusion_seconds: 0,
+            preinfusion_pin: None,
+            first_drip_pin: None,
+            manual_pin: None,
+            history_size: 50,
+            counter_file: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReadyConfig {
+    /// The steam boiler temperature has to stay within this many degrees
+    /// of its target.
+    pub band_degrees: u32,
+    /// How long the temperature has to stay within the band before the
+    /// machine is ready, in seconds.
+    pub stable_seconds: u32,
+    /// Highest share of the stable period the heating element may be on,
+    /// in percent.
+    pub max_heating_percent: u32,
+}
+
+impl Default for ReadyConfig {
+    fn default() -> Self {
+        Self {
+            band_degrees: 2,
+            stable_seconds: 60,
+            max_heating_percent: 50,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MenuConfig {
+    /// GPIO line of the button which opens the menu and selects the items,
+    /// or None to disable the menu. The display GPIO chip is used for all
+    /// the menu pins.
+    pub select_pin: Option<u32>,
+    /// GPIO line of the button which moves up in the menu.
+    pub up_pin: Option<u32>,
+    /// GPIO line of the button which moves down in the menu.
+    pub down_pin: Option<u32>,
+    /// GPIO line of the A output of a rotary encoder, which can be used
+    /// instead of the up and down buttons.
+    pub encoder_a_pin: Option<u32>,
+    /// GPIO line of the B output of the rotary encoder.
+    pub encoder_b_pin: Option<u32>,
+    /// The menu closes after this many seconds without button presses.
+    pub timeout_seconds: u32,
+}
+
+impl Default for MenuConfig {
+    fn default() -> Self {
+        Self {
+            select_pin: None,
+            up_pin: None,
+            down_pin: None,
+            encoder_a_pin: None,
+            encoder_b_pin: None,
+            timeout_seconds: 30,
+        }
+    }
+}
+
+impl MenuConfig {
+    /// Returns true if the configurations differ only in the timeout, which
+    /// can be changed at runtime.
+    fn same_pins(&self, other: &MenuConfig) -> bool {
+        let with_runtime_settings = MenuConfig {
+            timeout_seconds: other.timeout_seconds,
+            ..self.clone()
+        };
+        with_runtime_settings == *other
+    }
+}
+
+/// Another machine read by the same program, with a serial port and a
+/// display of its own. The rest of the settings are shared with the main
+/// machine.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    /// Name of the machine in the `machine` label of its metrics.
+    pub label: String,
+    #[serde(default)]
+    pub profile: Profile,
+    #[serde(default)]
+    pub serial: SerialConfig,
+    /// The display of the machine. None by default, as the main display
+    /// settings are for the main machine.
+    #[serde(default = "no_display")]
+    pub display: DisplayConfig,
+}
+
+fn no_display() -> DisplayConfig {
+    DisplayConfig {
+        enabled: false,
+        ..DisplayConfig::default()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Name of the main machine in the `machine` label of the metrics, which
+    /// are left without the label if there is no name.
+    pub label: Option<String>,
+    /// Machine model, which selects the status line format.
+    pub profile: Profile,
+    /// Language of the texts on the display.
+    pub lang: Language,
+    pub serial: SerialConfig,
+    pub display: DisplayConfig,
+    /// More displays driven at the same time as the main one. Their
+    /// hardware settings and screens are their own, but the contents of the
+    /// screens follow the main display settings.
+    pub displays: Vec<DisplayConfig>,
+    pub metrics: MetricsConfig,
+    pub otlp: OtlpConfig,
+    pub influxdb: InfluxDbConfig,
+    pub mqtt: MqttConfig,
+    pub timer: TimerConfig,
+    /// When the machine is considered ready for a shot.
+    pub ready: ReadyConfig,
+    /// Buttons for changing the settings at the machine.
+    pub menu: MenuConfig,
+    /// More machines read at the same time.
+    pub machines: Vec<MachineConfig>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load the configuration file (if any) and apply the environment
+    /// variable and command line overrides on top of it, in that order.
+    pub fn load(args: &Args) -> Result<Self, Box<dyn Error>> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), Box<dyn Error>> {
+        env_override_with("MARAX_LABEL", &mut self.label, parse_opt)?;
+        env_override("MARAX_PROFILE", &mut self.profile)?;
+        env_override("MARAX_LANG", &mut self.lang)?;
+        env_override("MARAX_SERIAL", &mut self.serial.device)?;
+        env_override("MARAX_SERIAL_STALE_SECONDS", &mut self.serial.stale_seconds)?;
+        env_override("MARAX_BAUD", &mut self.serial.baud)?;
+        env_override("MARAX_SERIAL_DATA_BITS", &mut self.serial.data_bits)?;
+        env_override("MARAX_SERIAL_PARITY", &mut self.serial.parity)?;
+        env_override("MARAX_SERIAL_STOP_BITS", &mut self.serial.stop_bits)?;
+        env_override("MARAX_SERIAL_FLOW_CONTROL", &mut self.serial.flow_control)?;
+        env_override(
+            "MARAX_SERIAL_KEEPALIVE_SECONDS",
+            &mut self.serial.keepalive_seconds,
+        )?;
+        env_override(
+            "MARAX_SERIAL_KEEPALIVE_DATA",
+            &mut self.serial.keepalive_data,
+        )?;
+        env_override("MARAX_DISPLAY_ENABLED", &mut self.display.enabled)?;
+        env_override("MARAX_DISPLAY_DRIVER", &mut self.display.driver)?;
+        env_override("MARAX_DISPLAY_INTERFACE", &mut self.display.interface)?;
+        env_override("MARAX_I2C", &mut self.display.i2c_bus)?;
+        env_override_with("MARAX_I2C_ADDRESS", &mut self.display.i2c_address, parse_u8)?;
+        env_override("MARAX_SPI", &mut self.display.spi_device)?;
+        env_override("MARAX_SPI_SPEED_HZ", &mut self.display.spi_speed_hz)?;
+        env_override("MARAX_GPIO_CHIP", &mut self.display.gpio_chip)?;
+        env_override("MARAX_DISPLAY_DC_PIN", &mut self.display.dc_pin)?;
+        env_override_with(
+            "MARAX_DISPLAY_RESET_PIN",
+            &mut self.display.reset_pin,
+            parse_opt,
+        )?;
+        env_override("MARAX_DISPLAY_CLK_PIN", &mut self.display.clk_pin)?;
+        env_override("MARAX_DISPLAY_DIO_PIN", &mut self.display.dio_pin)?;
+        env_override_with("MARAX_DISPLAY_WIDTH", &mut self.display.width, parse_opt)?;
+        env_override_with("MARAX_DISPLAY_HEIGHT", &mut self.display.height, parse_opt)?;
+        env_override("MARAX_DISPLAY_ROTATION", &mut self.display.rotation)?;
+        env_override("MARAX_DISPLAY_MODULES", &mut self.display.modules)?;
+        env_override("MARAX_DISPLAY_LAYOUT", &mut self.display.layout)?;
+        env_override("MARAX_BRIGHTNESS", &mut self.display.brightness)?;
+        env_override_with(
+            "MARAX_DISPLAY_NIGHT_BRIGHTNESS",
+            &mut self.display.night_brightness,
+            parse_opt,
+        )?;
+        env_override("MARAX_DISPLAY_NIGHT_START", &mut self.display.night_start)?;
+        env_override("MARAX_DISPLAY_NIGHT_END", &mut self.display.night_end)?;
+        env_override("MARAX_DISPLAY_IDLE_SCREEN", &mut self.display.idle_screen)?;
+        env_override("MARAX_DISPLAY_IDLE_STEAM", &mut self.display.idle_steam)?;
+        env_override_with("MARAX_DISPLAY_PAGES", &mut self.display.pages, parse_list)?;
+        env_override("MARAX_DISPLAY_PAGE_SECONDS", &mut self.display.page_seconds)?;
+        env_override(
+            "MARAX_DISPLAY_BLANK_MINUTES",
+            &mut self.display.blank_minutes,
+        )?;
+        env_override_with(
+            "MARAX_DISPLAY_WAKE_BUTTON_PIN",
+            &mut self.display.wake_button_pin,
+            parse_opt,
+        )?;
+        env_override(
+            "MARAX_DISPLAY_BURN_IN_PROTECTION",
+            &mut self.display.burn_in_protection,
+        )?;
+        env_override("MARAX_DISPLAY_TIMER_HX", &mut self.display.timer_hx)?;
+        env_override("MARAX_DISPLAY_TARGET_FLASH", &mut self.display.target_flash)?;
+        env_override(
+            "MARAX_DISPLAY_PROGRESS_RING",
+            &mut self.display.progress_ring,
+        )?;
+        env_override("MARAX_DISPLAY_READY_BANNER", &mut self.display.ready_banner)?;
+        env_override_with("MARAX_TIMEZONE", &mut self.display.timezone, parse_opt)?;
+        env_override_with(
+            "MARAX_DISPLAY_SCREENS",
+            &mut self.display.screens,
+            parse_list,
+        )?;
+        env_override_with("MARAX_DISPLAY_FONT", &mut self.display.font_path, parse_opt)?;
+        env_override("MARAX_DISPLAY_FONT_WIDTH", &mut self.display.font_width)?;
+        env_override("MARAX_DISPLAY_FONT_HEIGHT", &mut self.display.font_height)?;
+        env_override("MARAX_DISPLAY_FONT_SPACING", &mut self.display.font_spacing)?;
+        env_override_with(
+            "MARAX_DISPLAY_FONT_IMAGE_WIDTH",
+            &mut self.display.font_image_width,
+            parse_opt,
+        )?;
+        env_override_with(
+            "MARAX_DISPLAY_ANIMATION_DIR",
+            &mut self.display.animation_dir,
+            parse_opt,
+        )?;
+        env_override(
+            "MARAX_DISPLAY_ANIMATION_WIDTH",
+            &mut self.display.animation_width,
+        )?;
+        env_override(
+            "MARAX_DISPLAY_ANIMATION_HEIGHT",
+            &mut self.display.animation_height,
+        )?;
+        env_override(
+            "MARAX_DISPLAY_ANIMATION_FPS",
+            &mut self.display.animation_fps,
+        )?;
+        env_override("MARAX_METRICS_ADDR", &mut self.metrics.address)?;
+        env_override("MARAX_METRICS_NAMES", &mut self.metrics.names)?;
+        env_override("MARAX_METRICS_NAMESPACE", &mut self.metrics.namespace)?;
+        env_override("MARAX_METRICS_HOST", &mut self.metrics.host)?;
+        env_override_with("MARAX_OTLP_ENDPOINT", &mut self.otlp.endpoint, parse_opt)?;
+        env_override(
+            "MARAX_OTLP_INTERVAL_SECONDS",
+            &mut self.otlp.interval_seconds,
+        )?;
+        env_override_with("MARAX_INFLUXDB_URL", &mut self.influxdb.url, parse_opt)?;
+        env_override("MARAX_INFLUXDB_ORG", &mut self.influxdb.org)?;
+        env_override("MARAX_INFLUXDB_BUCKET", &mut self.influxdb.bucket)?;
+        env_override("MARAX_INFLUXDB_TOKEN", &mut self.influxdb.token)?;
+        env_override(
+            "MARAX_INFLUXDB_INTERVAL_SECONDS",
+            &mut self.influxdb.interval_seconds,
+        )?;
+        env_override_with("MARAX_MQTT_BROKER", &mut self.mqtt.broker, parse_opt)?;
+        env_override("MARAX_MQTT_CLIENT_ID", &mut self.mqtt.client_id)?;
+        env_override_with("MARAX_MQTT_USERNAME", &mut self.mqtt.username, parse_opt)?;
+        env_override_with("MARAX_MQTT_PASSWORD", &mut self.mqtt.password, parse_opt)?;
+        env_override("MARAX_MQTT_TOPIC", &mut self.mqtt.topic)?;
+        env_override(
+            "MARAX_MQTT_INTERVAL_SECONDS",
+            &mut self.mqtt.interval_seconds,
+        )?;
+        env_override("MARAX_MQTT_DISCOVERY", &mut self.mqtt.discovery)?;
+        env_override(
+            "MARAX_MQTT_DISCOVERY_PREFIX",
+            &mut self.mqtt.discovery_prefix,
+        )?;
+        env_override("MARAX_TIMER_MAX_SECONDS", &mut self.timer.max_seconds)?;
+        env_override("MARAX_TIMER_TARGET_SECONDS", &mut self.timer.target_seconds)?;
+        env_override("MARAX_TIMER_TENTHS", &mut self.timer.tenths)?;
+        env_override("MARAX_TIMER_HOLD_SECONDS", &mut self.timer.hold_seconds)?;
+        env_override("MARAX_TIMER_AFTER_SHOT", &mut self.timer.after_shot)?;
+        env_override(
+            "MARAX_TIMER_SUMMARY_SECONDS",
+            &mut self.timer.summary_seconds,
+        )?;
+        env_override(
+            "MARAX_TIMER_MIN_SHOT_SECONDS",
+            &mut self.timer.min_shot_seconds,
+        )?;
+        env_override(
+            "MARAX_TIMER_PUMP_OFF_SAMPLES",
+            &mut self.timer.pump_off_samples,
+        )?;
+        env_override(
+            "MARAX_TIMER_AUTOFILL_MAX_SECONDS",
+            &mut self.timer.autofill_max_seconds,
+        )?;
+        env_override(
+            "MARAX_TIMER_AUTOFILL_HX_DROP",
+            &mut self.timer.autofill_hx_drop,
+        )?;
+        env_override("MARAX_TIMER_COUNTDOWN", &mut self.timer.countdown)?;
+        env_override(
+            "MARAX_TIMER_PREINFUSION_SECONDS",
+            &mut self.timer.preinfusion_seconds,
+        )?;
+        env_override_with(
+            "MARAX_TIMER_PREINFUSION_PIN",
+            &mut self.timer.preinfusion_pin,
+            parse_opt,
+        )?;
+        env_override_with(
+            "MARAX_TIMER_FIRST_DRIP_PIN",
+            &mut self.timer.first_drip_pin,
+            parse_opt,
+        )?;
+        env_override_with(
+            "MARAX_TIMER_MANUAL_PIN",
+            &mut self.timer.manual_pin,
+            parse_opt,
+        )?;
+        env_override("MARAX_TIMER_HISTORY_SIZE", &mut self.timer.history_size)?;
+        env_override_with(
+            "MARAX_TIMER_COUNTER_FILE",
+            &mut self.timer.counter_file,
+            parse_opt,
+        )?;
+        env_override("MARAX_READY_BAND_DEGREES", &mut self.ready.band_degrees)?;
+        env_override("MARAX_READY_STABLE_SECONDS", &mut self.ready.stable_seconds)?;
+        env_override(
+            "MARAX_READY_MAX_HEATING_PERCENT",
+            &mut self.ready.max_heating_percent,
+        )?;
+        env_override_with(
+            "MARAX_MENU_SELECT_PIN",
+            &mut self.menu.select_pin,
+            parse_opt,
+        )?;
+        env_override_with("MARAX_MENU_UP_PIN", &mut self.menu.up_pin, parse_opt)?;
+        env_override_with("MARAX_MENU_DOWN_PIN", &mut self.menu.down_pin, parse_opt)?;
+        env_override_with(
+            "MARAX_MENU_ENCODER_A_PIN",
+            &mut self.menu.encoder_a_pin,
+            parse_opt,
+        )?;
+        env_override_with(
+            "MARAX_MENU_ENCODER_B_PIN",
+            &mut self.menu.encoder_b_pin,
+            parse_opt,
+        )?;
+        env_override("MARAX_MENU_TIMEOUT_SECONDS", &mut self.menu.timeout_seconds)?;
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: &Args) {
+        if let Some(serial) = &args.serial {
+            self.serial.device = serial.clone();
+        }
+        if let Some(baud) = args.baud {
+            self.serial.baud = baud;
+        }
+        if args.no_display {
+            self.display.enabled = false;
+        }
+        if let Some(i2c_bus) = &args.i2c_bus {
+            self.display.i2c_bus = i2c_bus.clone();
+        }
+        if let Some(metrics_addr) = args.metrics_addr {
+            self.metrics.address = metrics_addr;
+        }
+    }
+
+    /// Returns true if switching from this configuration to `other` can't be
+    /// done at runtime and needs a restart.
+    pub fn requires_restart(&self, other: &Config) -> bool {
+        self.profile != other.profile
+            || self.serial != other.serial
+            || self.metrics != other.metrics
+            || self.otlp != other.otlp
+            || self.influxdb != other.influxdb
+            || self.mqtt != other.mqtt
+            || self.timer.preinfusion_pin != other.timer.preinfusion_pin
+            || self.timer.first_drip_pin != other.timer.first_drip_pin
+            || self.timer.manual_pin != other.timer.manual_pin
+            || self.label != other.label
+            || self.machines != other.machines
+            || !self.menu.same_pins(&other.menu)
+            || !self.display.same_hardware(&other.display)
+            || self.displays.len() != other.displays.len()
+            || !self
+                .displays
+                .iter()
+                .zip(&other.displays)
+                .all(|(a, b)| a.same_hardware(b))
+    }
+
+    /// The configuration of one of the additional machines: its own serial
+    /// port and display, and the shared settings without the buttons, which
+    /// belong to the main machine. The shot count is kept in a file of its
+    /// own.
+    pub fn for_machine(&self, machine: &MachineConfig) -> Config {
+        let mut config = Config {
+            label: Some(machine.label.clone()),
+            profile: machine.profile,
+            serial: machine.serial.clone(),
+            display: machine.display.clone(),
+            displays: Vec::new(),
+            menu: MenuConfig {
+                select_pin: None,
+                up_pin: None,
+                down_pin: None,
+                encoder_a_pin: None,
+                encoder_b_pin: None,
+                ..self.menu.clone()
+            },
+            machines: Vec::new(),
+            ..self.clone()
+        };
+        config.timer.preinfusion_pin = None;
+        config.timer.first_drip_pin = None;
+        config.timer.manual_pin = None;
+        config.timer.counter_file = self
+            .timer
+            .counter_file
+            .as_ref()
+            .map(|path| format!("{}.{}", path, machine.label));
+        config
+    }
+
+    /// The main display followed by the additional ones.
+    pub fn all_displays(&self) -> impl Iterator<Item = &DisplayConfig> {
+        std::iter::once(&self.display).chain(&self.displays)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !self.machines.is_empty() && self.label.is_none() {
+            return Err("config error: label is needed with more than one machine".into());
+        }
+        for (i, machine) in self.machines.iter().enumerate() {
+            let duplicate = self.label.as_ref() == Some(&machine.label)
+                || self.machines[..i].iter().any(|m| m.label == machine.label);
+            if duplicate {
+                return Err(format!(
+                    "config error: machine label \"{}\" is used twice",
+                    machine.label
+                )
+                .into());
+            }
+            self.for_machine(machine)
+                .validate()
+                .map_err(|e| format!("{} (machine \"{}\")", e, machine.label))?;
+        }
+        if !self.metrics.namespace.is_empty() && !valid_metric_name(&self.metrics.namespace) {
+            return Err(format!(
+                "config error: bad metrics.namespace \"{}\"",
+                self.metrics.namespace
+            )
+            .into());
+        }
+        for name in self.metrics.labels.keys() {
+            // These are set by the program.
+            let reserved = ["machine", "kind", "version", "le"];
+            if !valid_metric_name(name)
+                || name.starts_with("__")
+                || reserved.contains(&name.as_str())
+            {
+                return Err(format!("config error: bad metrics.labels name \"{}\"", name).into());
+            }
+        }
+        if let Some(endpoint) = &self.otlp.endpoint {
+            let uri: hyper::Uri = endpoint
+                .parse()
+                .map_err(|e| format!("config error: bad otlp.endpoint \"{}\": {}", endpoint, e))?;
+            if uri.scheme_str() != Some("http") || uri.host().is_none() {
+                return Err(format!(
+                    "config error: otlp.endpoint \"{}\" is not an http:// URL, push over \
+                     HTTPS through a local OpenTelemetry collector",
+                    endpoint
+                )
+                .into());
+            }
+        }
+        if self.otlp.interval_seconds == 0 {
+            return Err("config error: otlp.interval_seconds must be at least 1".into());
+        }
+        for (name, value) in &self.otlp.headers {
+            if hyper::header::HeaderName::try_from(name.as_str()).is_err()
+                || hyper::header::HeaderValue::try_from(value.as_str()).is_err()
+            {
+                return Err(format!("config error: bad otlp.headers entry \"{}\"", name).into());
+            }
+        }
+        if let Some(url) = &self.influxdb.url {
+            let uri: hyper::Uri = url
+                .parse()
+                .map_err(|e| format!("config error: bad influxdb.url \"{}\": {}", url, e))?;
+            if uri.scheme_str() != Some("http") || uri.host().is_none() {
+                return Err(format!(
+                    "config error: influxdb.url \"{}\" is not an http:// URL, HTTPS servers \
+                     like InfluxDB Cloud aren't supported",
+                    url
+                )
+                .into());
+            }
+            if self.influxdb.org.is_empty() || self.influxdb.bucket.is_empty() {
+                return Err("config error: influxdb.org and influxdb.bucket must be set".into());
+            }
+        }
+        if self.influxdb.interval_seconds == 0 {
+            return Err("config error: influxdb.interval_seconds must be at least 1".into());
+        }
+        if self
+            .mqtt
+            .broker
+            .as_ref()
+            .is_some_and(|broker| broker.is_empty())
+        {
+            return Err("config error: mqtt.broker is empty".into());
+        }
+        if self.mqtt.password.is_some() && self.mqtt.username.is_none() {
+            return Err("config error: mqtt.password needs mqtt.username".into());
+        }
+        if self.mqtt.client_id.is_empty() {
+            return Err("config error: mqtt.client_id is empty".into());
+        }
+        if self.mqtt.topic.is_empty()
+            || self.mqtt.topic.ends_with('/')
+            || self.mqtt.topic.contains(['+', '#'])
+        {
+            return Err(format!("config error: bad mqtt.topic \"{}\"", self.mqtt.topic).into());
+        }
+        if self.mqtt.discovery_prefix.is_empty()
+            || self.mqtt.discovery_prefix.ends_with('/')
+            || self.mqtt.discovery_prefix.contains(['+', '#'])
+        {
+            return Err(format!(
+                "config error: bad mqtt.discovery_prefix \"{}\"",
+                self.mqtt.discovery_prefix
+            )
+            .into());
+        }
+        if self.mqtt.interval_seconds == 0 {
+            return Err("config error: mqtt.interval_seconds must be at least 1".into());
+        }
+        if self.serial.stale_seconds == 0 {
+            return Err("config error: serial.stale_seconds must be at least 1".into());
+        }
+        if !(5..=8).contains(&self.serial.data_bits) {
+            return Err("config error: serial.data_bits must be between 5 and 8".into());
+        }
+        if !(1..=2).contains(&self.serial.stop_bits) {
+            return Err("config error: serial.stop_bits must be 1 or 2".into());
+        }
+        if self.timer.max_seconds == 0 || self.timer.max_seconds > 999 {
+            return Err("config error: timer.max_seconds must be between 1 and 999".into());
+        }
+        if self.timer.pump_off_samples == 0 {
+            return Err("config error: timer.pump_off_samples must be at least 1".into());
+        }
+        if self.timer.history_size == 0 {
+            return Err("config error: timer.history_size must be at least 1".into());
+        }
+        if self.timer.target_seconds > self.timer.max_seconds {
+            return Err("config error: timer.target_seconds can't exceed timer.max_seconds".into());
+        }
+        if self.timer.min_shot_seconds > self.timer.max_seconds {
+            return Err(
+                "config error: timer.min_shot_seconds can't exceed timer.max_seconds".into(),
+            );
+        }
+        if self.timer.preinfusion_seconds > self.timer.max_seconds {
+            return Err(
+                "config error: timer.preinfusion_seconds can't exceed timer.max_seconds".into(),
+            );
+        }
+        if self.display.pages.is_empty() {
+            return Err("config error: display.pages can't be empty".into());
+        }
+        if self.display.page_seconds == 0 {
+            return Err("config error: display.page_seconds must be at least 1".into());
+        }
+        if self.display.pages.contains(&Page::Animation) && self.display.animation_dir.is_none() {
+            return Err("config error: the animation page needs display.animation_dir".into());
+        }
+        if self.display.animation_fps == 0 || self.display.animation_fps > 30 {
+            return Err("config error: display.animation_fps must be between 1 and 30".into());
+        }
+        for display in self.all_displays() {
+            if display.elements.iter().any(|e| e.screen == Screen::Summary) {
+                return Err(
+                    "config error: layout elements can only be on the timer and idle screens"
+                        .into(),
+                );
+            }
+        }
+        if self.ready.stable_seconds == 0 {
+            return Err("config error: ready.stable_seconds must be at least 1".into());
+        }
+        if self.ready.max_heating_percent > 100 {
+            return Err("config error: ready.max_heating_percent can't exceed 100".into());
+        }
+        if self.menu.select_pin.is_some() {
+            let buttons = self.menu.up_pin.is_some() && self.menu.down_pin.is_some();
+            let encoder = self.menu.encoder_a_pin.is_some() && self.menu.encoder_b_pin.is_some();
+            if !buttons && !encoder {
+                return Err(
+                    "config error: the menu needs up and down buttons or a rotary encoder".into(),
+                );
+            }
+            if self.menu.timeout_seconds == 0 {
+                return Err("config error: menu.timeout_seconds must be at least 1".into());
+            }
+        }
+        if let Some(timezone) = &self.display.timezone {
+            if !Path::new(ZONEINFO).join(timezone).is_file() {
+                return Err(format!("config error: unknown time zone {}", timezone).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse an integer given either in decimal or in hexadecimal with a "0x"
+/// prefix.
+fn parse_u8(s: &str) -> Result<u8, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parse an optional value, where "none" or an empty string means no value.
+fn parse_opt<T: FromStr>(s: &str) -> Result<Option<T>, T::Err> {
+    match s {
+        "" | "none" => Ok(None),
+        _ => s.parse().map(Some),
+    }
+}
+
+/// Parse a comma separated list of values.
+fn parse_list<T: FromStr>(s: &str) -> Result<Vec<T>, T::Err> {
+    s.split(',').map(|item| item.trim().parse()).collect()
+}
+
+fn env_override_with<T, E: Display>(
+    name: &str,
+    value: &mut T,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Result<(), Box<dyn Error>> {
+    if let Ok(s) = env::var(name) {
+        *value = parse(&s).map_err(|e| format!("invalid value for {}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+fn env_override<T: FromStr>(name: &str, value: &mut T) -> Result<(), Box<dyn Error>>
+where
+    T::Err: Display,
+{
+    env_override_with(name, value, T::from_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn empty_history_is_rejected() {
+        let mut config = Config::default();
+        config.timer.history_size = 0;
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! Lifetime shot count, kept in a file across restarts.
+
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+/// Read the count from the file, or 0 if there is no file yet.
+pub fn load(path: &Path) -> Result<u64, Box<dyn Error>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("failed to read {}: {}", path.display(), e).into()),
+    };
+    contents
+        .trim()
+        .parse()
+        .map_err(|e| format!("bad shot count in {}: {}", path.display(), e).into())
+}
+
+/// Write the count to the file. It's written to a temporary file first, and
+/// both the file and the rename are synced to the disk, so that a power cut
+/// in the middle doesn't lose the count.
+pub fn save(path: &Path, count: u64) -> Result<(), Box<dyn Error>> {
+    let temporary = path.with_extension("tmp");
+    let write = || {
+        let mut file = File::create(&temporary)?;
+        writeln!(file, "{}", count)?;
+        file.sync_all()
+    };
+    write().map_err(|e| format!("failed to write {}: {}", temporary.display(), e))?;
+    fs::rename(&temporary, path)
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+
+    // The rename is on the disk only once the directory is.
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)
+        .and_then(|directory| directory.sync_all())
+        .map_err(|e| format!("failed to sync {}: {}", directory.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("marax-shot-timer-count-{}", std::process::id()));
+        assert_eq!(load(&path).unwrap(), 0);
+        save(&path, 1234).unwrap();
+        save(&path, 1235).unwrap();
+        let count = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(count.unwrap(), 1235);
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
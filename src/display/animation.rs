@@ -0,0 +1,123 @@
+//! Animation played on an idle screen page, loaded from a directory of
+//! frames.
+
+use embedded_graphics::{pixelcolor::PixelColor, prelude::*};
+
+use std::{fs, path::PathBuf};
+
+use super::Result;
+use crate::config::DisplayConfig;
+
+/// Frames of the animation in the raw 1 bit per pixel format of the digit
+/// fonts, one file per frame, played in the order of the file names.
+pub struct Animation {
+    width: u32,
+    height: u32,
+    frames: Vec<Vec<u8>>,
+}
+
+impl Animation {
+    /// Load the frames from the directory given in the configuration, or
+    /// None if there isn't one.
+    pub fn load(config: &DisplayConfig) -> Result<Option<Self>> {
+        let dir = match &config.animation_dir {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        let (width, height) = (config.animation_width, config.animation_height);
+        if width == 0 || height == 0 {
+            return Err("animation error: the frame size can't be zero".into());
+        }
+
+        let entries = fs::read_dir(dir).map_err(|e| format!("failed to open {}: {}", dir, e))?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "raw"))
+            .collect();
+        if paths.is_empty() {
+            return Err(format!("animation error: no .raw frames in {}", dir).into());
+        }
+        paths.sort();
+
+        let size = (width.div_ceil(8) * height) as usize;
+        let mut frames = Vec::with_capacity(paths.len());
+        for path in paths {
+            let frame =
+                fs::read(&path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+            if frame.len() != size {
+                return Err(format!(
+                    "animation error: {} is {} bytes, {}x{} frames need {}",
+                    path.display(),
+                    frame.len(),
+                    width,
+                    height,
+                    size
+                )
+                .into());
+            }
+            frames.push(frame);
+        }
+
+        Ok(Some(Self {
+            width,
+            height,
+            frames,
+        }))
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The frame at `index`, wrapping around to the start after the last
+    /// one.
+    pub fn frame(&self, index: usize) -> Frame<'_> {
+        Frame {
+            width: self.width,
+            height: self.height,
+            pixels: &self.frames[index % self.frames.len()],
+        }
+    }
+}
+
+/// One frame of an animation.
+pub struct Frame<'a> {
+    pub width: u32,
+    pub height: u32,
+    pixels: &'a [u8],
+}
+
+impl Frame<'_> {
+    /// Draw the set pixels of the frame with its top left corner at
+    /// `position`.
+    pub fn draw<C: PixelColor, D: DrawTarget<C>>(
+        &self,
+        target: &mut D,
+        position: Point,
+        color: C,
+    ) -> std::result::Result<(), D::Error> {
+        let bytes_per_row = self.width.div_ceil(8);
+        let pixels = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                let byte = self.pixels[(y * bytes_per_row + x / 8) as usize];
+                byte & (0x80 >> (x % 8)) != 0
+            })
+            .map(|(x, y)| Pixel(position + Point::new(x as i32, y as i32), color));
+        target.draw_iter(pixels)
+    }
+
+    /// Draw the frame centered on the target.
+    pub fn draw_centered<C: PixelColor, D: DrawTarget<C>>(
+        &self,
+        target: &mut D,
+        color: C,
+    ) -> std::result::Result<(), D::Error> {
+        let size = target.size();
+        let position = Point::new(
+            (size.width as i32 - self.width as i32) / 2,
+            (size.height as i32 - self.height as i32) / 2,
+        );
+        self.draw(target, position, color)
+    }
+}
@@ -0,0 +1,661 @@
+//! Screen layouts and frame buffering for color TFT displays.
+
+use embedded_graphics::{
+    egtext,
+    fonts::{Font12x16, Font24x32, Font6x12},
+    pixelcolor::{
+        raw::{RawData, RawU16},
+        Rgb565, Rgb888,
+    },
+    prelude::*,
+    primitives::Rectangle,
+    style::PrimitiveStyle,
+    text_style,
+};
+
+use std::convert::Infallible;
+
+use super::font::DigitFont;
+use super::{
+    icons, segments, DisplayBackend, Frame, Idle, Info, InfoValue, Result, Snapshot, Summary,
+    Timer, Trend,
+};
+use crate::config::{Brightness, Layout};
+
+const TEXT_COLOR: Rgb565 = Rgb565::WHITE;
+const TARGET_COLOR: Rgb565 = Rgb565::YELLOW;
+const HEATING_COLOR: Rgb565 = Rgb565::RED;
+const READY_COLOR: Rgb565 = Rgb565::GREEN;
+
+/// The controller specific part of a color display: something that can
+/// show a whole frame of RGB565 pixels.
+pub trait Panel: Send {
+    fn write_frame(&mut self, width: u32, height: u32, pixels: &[Rgb565]) -> Result<()>;
+}
+
+/// Frame kept in memory, so that the screen can be updated in one go
+/// without flickering.
+struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgb565>,
+}
+
+impl Framebuffer {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Rgb565::BLACK; (width * height) as usize],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|p| *p = Rgb565::BLACK);
+    }
+}
+
+impl DrawTarget<Rgb565> for Framebuffer {
+    type Error = Infallible;
+
+    fn draw_pixel(
+        &mut self,
+        Pixel(point, color): Pixel<Rgb565>,
+    ) -> std::result::Result<(), Infallible> {
+        if point.x >= 0
+            && point.y >= 0
+            && (point.x as u32) < self.width
+            && (point.y as u32) < self.height
+        {
+            self.pixels[(point.y as u32 * self.width + point.x as u32) as usize] = color;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+/// Draws every pixel as a `scale` by `scale` block starting from `offset`,
+/// which makes the fonts designed for the small OLED screens usable on the
+/// bigger TFT displays.
+struct Scaled<'a, D> {
+    target: &'a mut D,
+    scale: i32,
+    offset: Point,
+}
+
+impl<D: DrawTarget<Rgb565>> DrawTarget<Rgb565> for Scaled<'_, D> {
+    type Error = D::Error;
+
+    fn draw_pixel(
+        &mut self,
+        Pixel(point, color): Pixel<Rgb565>,
+    ) -> std::result::Result<(), D::Error> {
+        let top_left = self.offset + point * self.scale;
+        Rectangle::new(
+            top_left,
+            top_left + Point::new(self.scale - 1, self.scale - 1),
+        )
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(self.target)
+    }
+
+    fn size(&self) -> Size {
+        self.target.size() / self.scale as u32
+    }
+}
+
+/// Scale factor for the seven segment digits on a screen of the given size.
+fn scale(size: Size) -> i32 {
+    (size.width.min(size.height) / 120).max(1) as i32
+}
+
+/// Draw a line of text, with a bigger font on the bigger screens.
+fn draw_text<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    text: &str,
+    position: Point,
+    color: Rgb565,
+) -> std::result::Result<(), D::Error> {
+    if scale(target.size()) > 1 {
+        egtext!(
+            text = text,
+            top_left = position,
+            style = text_style!(font = Font12x16, text_color = color)
+        )
+        .draw(target)
+    } else {
+        egtext!(
+            text = text,
+            top_left = position,
+            style = text_style!(font = Font6x12, text_color = color)
+        )
+        .draw(target)
+    }
+}
+
+fn text_width<D: DrawTarget<Rgb565>>(target: &D, text: &str) -> i32 {
+    let char_width = if scale(target.size()) > 1 { 12 } else { 6 };
+    char_width * text.chars().count() as i32
+}
+
+/// Draw the heating element indicator in the bottom right corner, and the
+/// machine mode above it.
+fn draw_status_icons<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    heating_element_on: bool,
+    coffee_mode: bool,
+) -> std::result::Result<(), D::Error> {
+    let size = target.size();
+    let scale = 2 * scale(size);
+    let icon_size = icons::SIZE as i32 * scale;
+    let mut scaled = Scaled {
+        target,
+        scale,
+        offset: Point::new(
+            size.width as i32 - icon_size - 4,
+            size.height as i32 - 2 * icon_size - 8,
+        ),
+    };
+    icons::draw(
+        &mut scaled,
+        icons::mode(coffee_mode),
+        Point::zero(),
+        TEXT_COLOR,
+    )?;
+    if heating_element_on {
+        let below = Point::new(0, icons::SIZE as i32 + 4 / scale);
+        icons::draw(&mut scaled, &icons::FLAME, below, HEATING_COLOR)?;
+    }
+    Ok(())
+}
+
+/// Draw the trend arrow `scale` times the icon size.
+fn draw_trend<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    trend: Trend,
+    top_left: Point,
+    scale: i32,
+) -> std::result::Result<(), D::Error> {
+    let mut scaled = Scaled {
+        target,
+        scale,
+        offset: top_left,
+    };
+    icons::draw(&mut scaled, icons::trend(trend), Point::zero(), TEXT_COLOR)
+}
+
+/// Draw the heat-up progress as a bar of the given size.
+fn draw_progress_bar<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    progress: f32,
+    top_left: Point,
+    size: Size,
+) -> std::result::Result<(), D::Error> {
+    let bottom_right = top_left + Point::new(size.width as i32 - 1, size.height as i32 - 1);
+    Rectangle::new(top_left, bottom_right)
+        .into_styled(PrimitiveStyle::with_stroke(TEXT_COLOR, 1))
+        .draw(target)?;
+    let filled = ((size.width - 1) as f32 * progress.clamp(0.0, 1.0)) as i32;
+    Rectangle::new(top_left, Point::new(top_left.x + filled, bottom_right.y))
+        .into_styled(PrimitiveStyle::with_fill(HEATING_COLOR))
+        .draw(target)
+}
+
+/// Draw the READY text in dark on a green banner of the given size.
+fn draw_ready_banner<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    text: &str,
+    top_left: Point,
+    size: Size,
+) -> std::result::Result<(), D::Error> {
+    let bottom_right = top_left + Point::new(size.width as i32 - 1, size.height as i32 - 1);
+    Rectangle::new(top_left, bottom_right)
+        .into_styled(PrimitiveStyle::with_fill(READY_COLOR))
+        .draw(target)?;
+    let x = (size.width as i32 - text_width(target, text)) / 2;
+    draw_text(target, text, top_left + Point::new(x, 2), Rgb565::BLACK)
+}
+
+/// Draw an animation frame centered on the screen, scaled up like the text
+/// on the bigger screens.
+fn draw_animation<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    frame: &Frame,
+) -> std::result::Result<(), D::Error> {
+    let size = target.size();
+    let scale = scale(size);
+    let offset = Point::new(
+        (size.width as i32 - frame.width as i32 * scale) / 2,
+        (size.height as i32 - frame.height as i32 * scale) / 2,
+    );
+    let mut scaled = Scaled {
+        target,
+        scale,
+        offset,
+    };
+    frame.draw(&mut scaled, Point::zero(), TEXT_COLOR)
+}
+
+/// Draw the elapsed time centered on the screen, turning green once the
+/// target time is reached, and the target time in the top right corner.
+fn draw_timer<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    font: &DigitFont,
+    timer: &Timer,
+) -> std::result::Result<(), D::Error> {
+    let size = target.size();
+    let scale = scale(size);
+    let shown = timer.shown();
+    let seconds = shown.as_secs() as u32;
+
+    let color =
+        if timer.target_seconds != 0 && timer.elapsed.as_secs() >= timer.target_seconds.into() {
+            READY_COLOR
+        } else {
+            TEXT_COLOR
+        };
+    // Flash by filling the screen with the color of the digits.
+    let color = if timer.flash {
+        let size = Point::new(size.width as i32 - 1, size.height as i32 - 1);
+        Rectangle::new(Point::zero(), size)
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(target)?;
+        Rgb565::BLACK
+    } else {
+        color
+    };
+
+    if timer.layout == Layout::Jumbo {
+        let tenths = shown.subsec_millis() / 100;
+        let digits = segments::timer_digits(seconds, Some(tenths).filter(|_| timer.shows_tenths()));
+        return segments::draw_digits(target, &digits, timer.shows_tenths(), color);
+    }
+    draw_status_icons(target, timer.heating_element_on, timer.coffee_mode)?;
+    draw_target(target, timer.target_seconds)?;
+
+    // The pre-infusion and extraction times don't fit in the big digits.
+    if let Some(text) = timer.phases_text("/") {
+        let scale = (size.width as i32 / (24 * 6)).max(1);
+        let offset = Point::new(
+            (size.width as i32 - 24 * text.len() as i32 * scale) / 2,
+            (size.height as i32 - 32 * scale) / 2,
+        );
+        let mut scaled = Scaled {
+            target,
+            scale,
+            offset,
+        };
+        return egtext!(
+            text = &text,
+            top_left = Point::zero(),
+            style = text_style!(font = Font24x32, text_color = color)
+        )
+        .draw(&mut scaled);
+    }
+
+    // Past 99 seconds the three digits take the room of the tenths.
+    if seconds >= 100 {
+        let text = seconds.to_string();
+        let offset = Point::new(
+            (size.width as i32 - font.text_width(&text) * scale) / 2,
+            (size.height as i32 - font.height as i32 * scale) / 2,
+        );
+        let mut scaled = Scaled {
+            target,
+            scale,
+            offset,
+        };
+        return font.draw_text(&mut scaled, &text, Point::zero(), color);
+    }
+
+    // Same relative layout as on the monochrome displays.
+    let digit_width = font.width as i32;
+    let advance = font.advance();
+    let width = if timer.tenths {
+        2 * advance + 10 + digit_width
+    } else {
+        2 * digit_width + 15
+    };
+    let height = font.height as i32;
+    let offset = Point::new(
+        (size.width as i32 - width * scale) / 2,
+        (size.height as i32 - height * scale) / 2,
+    );
+    let mut scaled = Scaled {
+        target: &mut *target,
+        scale,
+        offset,
+    };
+
+    if timer.tenths {
+        if seconds / 10 != 0 {
+            font.draw_digit(&mut scaled, seconds / 10, Point::zero(), color)?;
+        }
+        font.draw_digit(&mut scaled, seconds % 10, Point::new(advance, 0), color)?;
+        let decimal_point = Point::new(2 * advance, height - 4);
+        Rectangle::new(decimal_point, decimal_point + Point::new(3, 3))
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(&mut scaled)?;
+        font.draw_digit(
+            &mut scaled,
+            shown.subsec_millis() / 100,
+            Point::new(2 * advance + 10, 0),
+            color,
+        )?;
+    } else {
+        if seconds / 10 != 0 {
+            font.draw_digit(&mut scaled, seconds / 10, Point::zero(), color)?;
+        }
+        font.draw_digit(
+            &mut scaled,
+            seconds % 10,
+            Point::new(digit_width + 15, 0),
+            color,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Draw the target time in the top right corner, if one is set.
+fn draw_target<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    target_seconds: u32,
+) -> std::result::Result<(), D::Error> {
+    if target_seconds != 0 {
+        let text = format!("Target {}", target_seconds);
+        let x = target.size().width as i32 - text_width(target, &text) - 4;
+        draw_text(target, &text, Point::new(x, 4), TARGET_COLOR)?;
+    }
+    Ok(())
+}
+
+/// Draw the heat exchanger temperature, red while the heating element is on
+/// and green otherwise, and optionally the steam boiler temperature and its
+/// target.
+fn draw_idle<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    font: &DigitFont,
+    idle: &Idle,
+) -> std::result::Result<(), D::Error> {
+    let size = target.size();
+    let scale = scale(size);
+    let hx_temperature = idle.hx_temperature.clamp(0, 999) as u32;
+
+    let color = if idle.heating_element_on {
+        HEATING_COLOR
+    } else {
+        READY_COLOR
+    };
+
+    if idle.layout == Layout::Jumbo {
+        let digits = segments::temperature_digits(hx_temperature);
+        return segments::draw_digits(target, &digits, false, color);
+    }
+    draw_status_icons(target, idle.heating_element_on, idle.coffee_mode)?;
+
+    let advance = font.advance();
+    let width = 2 * advance + font.width as i32;
+    let offset = Point::new(
+        (size.width as i32 - width * scale) / 2,
+        (size.height as i32 - font.height as i32 * scale) / 2,
+    );
+    if idle.show_labels {
+        draw_text(target, "HX", Point::new(4, 4), TEXT_COLOR)?;
+    }
+
+    let mut scaled = Scaled {
+        target: &mut *target,
+        scale,
+        offset,
+    };
+    if hx_temperature >= 100 {
+        font.draw_digit(&mut scaled, hx_temperature / 100, Point::zero(), color)?;
+    }
+    if hx_temperature >= 10 {
+        font.draw_digit(
+            &mut scaled,
+            hx_temperature / 10 % 10,
+            Point::new(advance, 0),
+            color,
+        )?;
+    }
+    font.draw_digit(
+        &mut scaled,
+        hx_temperature % 10,
+        Point::new(2 * advance, 0),
+        color,
+    )?;
+
+    let degrees = Point::new(offset.x + (width + 2) * scale, offset.y);
+    draw_text(target, "C", degrees, color)?;
+    let line_height = if scale > 1 { 16 } else { 12 };
+    let below_degrees = degrees + Point::new(0, line_height + 4);
+    draw_trend(target, idle.hx_trend, below_degrees, 2 * scale)?;
+
+    let below_digits = offset + Point::new(0, (font.height as i32 + 4) * scale);
+    if idle.ready {
+        let banner_size = Size::new((width * scale) as u32, (line_height + 4) as u32);
+        draw_ready_banner(target, idle.texts.ready, below_digits, banner_size)?;
+    } else if let Some(progress) = idle.heat_up {
+        let bar_size = Size::new((width * scale) as u32, 4 * scale as u32);
+        draw_progress_bar(target, progress, below_digits, bar_size)?;
+    }
+
+    if idle.boost_countdown > 0 {
+        let text = format!("Boost {:04}", idle.boost_countdown.min(9999));
+        let x = size.width as i32 - text_width(target, &text) - 4;
+        draw_text(target, &text, Point::new(x, 4), TEXT_COLOR)?;
+    }
+
+    if idle.show_steam {
+        let text = format!(
+            "{} {} / {}",
+            idle.texts.steam, idle.steam_temperature, idle.target_steam_temperature
+        );
+        let position = Point::new(4, size.height as i32 - line_height - 4);
+        draw_text(target, &text, position, TEXT_COLOR)?;
+        let after_text = position + Point::new(text_width(target, &text) + 4, line_height / 6);
+        draw_trend(target, idle.steam_trend, after_text, scale)?;
+    }
+
+    Ok(())
+}
+
+/// Draw the value of an information page centered on the screen, with the
+/// label in the top left corner.
+fn draw_info<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    font: &DigitFont,
+    info: &Info,
+) -> std::result::Result<(), D::Error> {
+    let size = target.size();
+    if info.show_labels {
+        draw_text(target, info.label(), Point::new(4, 4), TEXT_COLOR)?;
+    }
+
+    // The clock and the temperature difference are drawn with the seven
+    // segment digits, as big as they fit.
+    let text = info.value.text();
+    if let InfoValue::Clock { .. } | InfoValue::SteamDelta(_) = info.value {
+        let (width, height) = (font.text_width(&text), font.height as i32);
+        let scale = (size.width as i32 / (width + 8))
+            .min(size.height as i32 / (height + 8))
+            .max(1);
+        let offset = Point::new(
+            (size.width as i32 - width * scale) / 2,
+            (size.height as i32 - height * scale) / 2,
+        );
+        let mut scaled = Scaled {
+            target,
+            scale,
+            offset,
+        };
+        return font.draw_text(&mut scaled, &text, Point::zero(), TEXT_COLOR);
+    }
+
+    let scale = (size.width as i32 / (24 * 6)).max(1);
+    let offset = Point::new(
+        (size.width as i32 - 24 * text.len() as i32 * scale) / 2,
+        (size.height as i32 - 32 * scale) / 2,
+    );
+    let mut scaled = Scaled {
+        target,
+        scale,
+        offset,
+    };
+    egtext!(
+        text = &text,
+        top_left = Point::zero(),
+        style = text_style!(font = Font24x32, text_color = TEXT_COLOR)
+    )
+    .draw(&mut scaled)
+}
+
+/// Draw the number of the shot, its duration in the middle of the screen,
+/// and the heat exchanger temperatures at the bottom.
+fn draw_summary<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    summary: &Summary,
+) -> std::result::Result<(), D::Error> {
+    let size = target.size();
+    let line_height = if scale(size) > 1 { 16 } else { 12 };
+    let shot = format!("{} {}", summary.texts.shot, summary.number);
+    draw_text(target, &shot, Point::new(4, 4), TEXT_COLOR)?;
+    if let Some(phases) = summary.phases_text() {
+        let x = size.width as i32 - text_width(target, &phases) - 4;
+        draw_text(target, &phases, Point::new(x, 4), TEXT_COLOR)?;
+    }
+    let bottom = Point::new(4, size.height as i32 - 4 - line_height);
+    draw_text(target, &summary.hx_text(), bottom, TEXT_COLOR)?;
+
+    let text = summary.time_text();
+    let scale = (size.width as i32 / (24 * 6)).max(1);
+    let offset = Point::new(
+        (size.width as i32 - 24 * text.len() as i32 * scale) / 2,
+        (size.height as i32 - 32 * scale) / 2,
+    );
+    let mut scaled = Scaled {
+        target,
+        scale,
+        offset,
+    };
+    egtext!(
+        text = &text,
+        top_left = Point::zero(),
+        style = text_style!(font = Font24x32, text_color = TEXT_COLOR)
+    )
+    .draw(&mut scaled)
+}
+
+/// Draw lines of text from the top of the screen.
+fn draw_message<D: DrawTarget<Rgb565>>(
+    target: &mut D,
+    lines: &[&str],
+) -> std::result::Result<(), D::Error> {
+    let line_height = if scale(target.size()) > 1 { 20 } else { 14 };
+    for (row, line) in lines.iter().enumerate() {
+        let position = Point::new(4, 4 + line_height * row as i32);
+        draw_text(target, line, position, TEXT_COLOR)?;
+    }
+    Ok(())
+}
+
+/// Color TFT display, drawn into a frame buffer which is sent to the panel
+/// on `flush()`.
+pub struct ColorDisplay<P> {
+    panel: P,
+    frame: Framebuffer,
+    font: DigitFont,
+}
+
+impl<P: Panel> ColorDisplay<P> {
+    pub fn new(panel: P, width: u32, height: u32, font: DigitFont) -> Result<Self> {
+        let mut disp = Self {
+            panel,
+            frame: Framebuffer::new(width, height),
+            font,
+        };
+        disp.flush()?;
+        Ok(disp)
+    }
+}
+
+impl<P: Panel> DisplayBackend for ColorDisplay<P> {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = draw_timer(&mut self.frame, &self.font, timer);
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = draw_idle(&mut self.frame, &self.font, idle);
+        Ok(())
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = draw_info(&mut self.frame, &self.font, info);
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = draw_summary(&mut self.frame, summary);
+        Ok(())
+    }
+
+    fn draw_animation(&mut self, frame: &Frame) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = draw_animation(&mut self.frame, frame);
+        Ok(())
+    }
+
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = draw_message(&mut self.frame, lines);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.frame.clear();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.panel
+            .write_frame(self.frame.width, self.frame.height, &self.frame.pixels)
+    }
+
+    fn set_brightness(&mut self, _brightness: Brightness) -> Result<()> {
+        // The backlight of the TFT modules isn't controlled by the display
+        // controller.
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Option<Snapshot> {
+        let rgb = self
+            .frame
+            .pixels
+            .iter()
+            .flat_map(|&p| {
+                let p = Rgb888::from(p);
+                [p.r(), p.g(), p.b()]
+            })
+            .collect();
+        Some(Snapshot {
+            width: self.frame.width,
+            height: self.frame.height,
+            rgb,
+        })
+    }
+}
+
+/// Convert a frame to the big endian RGB565 words the controllers expect.
+pub fn raw_pixels(pixels: &[Rgb565]) -> impl Iterator<Item = u16> + '_ {
+    pixels.iter().map(|p| RawU16::from(*p).into_inner())
+}
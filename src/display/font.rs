@@ -0,0 +1,230 @@
+use embedded_graphics::{
+    pixelcolor::PixelColor, prelude::*, primitives::Rectangle, style::PrimitiveStyle,
+};
+
+use std::{borrow::Cow, fs};
+
+use super::Result;
+use crate::config::DisplayConfig;
+
+const SEVEN_SEGMENT_FONT: &[u8] = include_bytes!("../../assets/seven-segment-font.raw");
+
+/// Characters of the glyphs in the font image, in order. The font files
+/// need to have at least the digits.
+const GLYPHS: &str = "0123456789:.- +";
+
+/// Bitmap font with the digits 0-9 for the big numbers on the screen, and
+/// optionally the colon, the decimal point, the minus sign, the space and
+/// the plus sign.
+/// The image is in the raw format of the embedded-graphics fonts: one bit
+/// per pixel, rows padded to full bytes, with the glyphs next to each other
+/// in the order of `GLYPHS`.
+pub struct DigitFont {
+    image: Cow<'static, [u8]>,
+    image_width: u32,
+    // Number of glyphs in the image.
+    glyphs: u32,
+    pub width: u32,
+    pub height: u32,
+    pub spacing: u32,
+}
+
+impl DigitFont {
+    /// The built-in 22x40 seven-segment font.
+    pub fn seven_segment() -> Self {
+        Self {
+            image: Cow::Borrowed(SEVEN_SEGMENT_FONT),
+            image_width: 312,
+            // Everything but the plus sign.
+            glyphs: 14,
+            width: 22,
+            height: 40,
+            spacing: 4,
+        }
+    }
+
+    /// Load the font file given in the configuration, or use the built-in
+    /// font if there isn't one.
+    pub fn open(config: &DisplayConfig) -> Result<Self> {
+        let path = match &config.font_path {
+            Some(path) => path,
+            None => return Ok(Self::seven_segment()),
+        };
+        let (width, height) = (config.font_width, config.font_height);
+        if width == 0 || height == 0 {
+            return Err("font error: the digit size can't be zero".into());
+        }
+
+        // By default the digits are on a single row, without the optional
+        // glyphs.
+        let image_width = config
+            .font_image_width
+            .unwrap_or_else(|| (10 * width).div_ceil(8) * 8);
+        let digits_per_row = image_width / width;
+        if digits_per_row == 0 {
+            return Err("font error: the image is narrower than a digit".into());
+        }
+
+        let image = fs::read(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+        let row_bytes = (image_width.div_ceil(8) * height) as usize;
+        let rows = 10_u32.div_ceil(digits_per_row);
+        let needed = rows as usize * row_bytes;
+        if image.len() < needed {
+            return Err(format!(
+                "font error: {} is {} bytes, {}x{} digits need {}",
+                path,
+                image.len(),
+                width,
+                height,
+                needed
+            )
+            .into());
+        }
+
+        let glyphs = (digits_per_row * (image.len() / row_bytes) as u32).min(GLYPHS.len() as u32);
+        Ok(Self {
+            image: Cow::Owned(image),
+            image_width,
+            glyphs,
+            width,
+            height,
+            spacing: config.font_spacing,
+        })
+    }
+
+    /// Horizontal distance from one digit to the next.
+    pub fn advance(&self) -> i32 {
+        (self.width + self.spacing) as i32
+    }
+
+    // Side of the square dots of the colon and the decimal point.
+    fn dot_size(&self) -> u32 {
+        (self.width / 5).max(1)
+    }
+
+    /// Width of the glyph of a character. The colon and the decimal point
+    /// are narrower than the rest.
+    pub fn glyph_width(&self, c: char) -> u32 {
+        match c {
+            ':' | '.' => (self.width / 3).max(self.dot_size()),
+            _ => self.width,
+        }
+    }
+
+    /// Width of a line of text.
+    pub fn text_width(&self, text: &str) -> i32 {
+        let glyphs: u32 = text.chars().map(|c| self.glyph_width(c)).sum();
+        let gaps = text.chars().count().saturating_sub(1) as u32;
+        (glyphs + gaps * self.spacing) as i32
+    }
+
+    fn pixel(&self, glyph: u32, x: u32, y: u32) -> bool {
+        let digits_per_row = self.image_width / self.width;
+        let image_x = glyph % digits_per_row * self.width + x;
+        let image_y = glyph / digits_per_row * self.height + y;
+        let bytes_per_row = self.image_width.div_ceil(8);
+        let byte = self.image[(image_y * bytes_per_row + image_x / 8) as usize];
+        byte & (0x80 >> (image_x % 8)) != 0
+    }
+
+    /// Draw a digit with its top left corner at `position`.
+    pub fn draw_digit<C: PixelColor, D: DrawTarget<C>>(
+        &self,
+        target: &mut D,
+        digit: u32,
+        position: Point,
+        color: C,
+    ) -> std::result::Result<(), D::Error> {
+        let c = std::char::from_digit(digit % 10, 10).unwrap_or('0');
+        self.draw_char(target, c, position, color)
+    }
+
+    /// Draw a character with its top left corner at `position`. Characters
+    /// without a glyph are left blank.
+    pub fn draw_char<C: PixelColor, D: DrawTarget<C>>(
+        &self,
+        target: &mut D,
+        c: char,
+        position: Point,
+        color: C,
+    ) -> std::result::Result<(), D::Error> {
+        let glyph = match GLYPHS.find(c) {
+            Some(glyph) => glyph as u32,
+            None => return Ok(()),
+        };
+        if glyph >= self.glyphs {
+            return self.draw_missing(target, c, position, color);
+        }
+        let width = self.glyph_width(c);
+        let pixels = (0..self.height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.pixel(glyph, x, y))
+            .map(|(x, y)| Pixel(position + Point::new(x as i32, y as i32), color));
+        target.draw_iter(pixels)
+    }
+
+    /// Draw the optional glyphs missing from a font file as simple shapes.
+    fn draw_missing<C: PixelColor, D: DrawTarget<C>>(
+        &self,
+        target: &mut D,
+        c: char,
+        position: Point,
+        color: C,
+    ) -> std::result::Result<(), D::Error> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let size = self.dot_size() as i32;
+        let left = (self.glyph_width(c) as i32 - size) / 2;
+        let dot = |top| {
+            (
+                Point::new(left, top),
+                Point::new(left + size - 1, top + size - 1),
+            )
+        };
+
+        // Top left and bottom right corners of the filled rectangles.
+        let shapes = match c {
+            ':' => vec![dot(height / 3 - size / 2), dot(height * 2 / 3 - size / 2)],
+            '.' => vec![dot(height - size)],
+            '-' | '+' => {
+                let top = (height - size) / 2;
+                let mut shapes = vec![(
+                    Point::new(size / 2, top),
+                    Point::new(width - size / 2 - 1, top + size - 1),
+                )];
+                if c == '+' {
+                    // The vertical bar is as tall as the horizontal one is wide.
+                    let half = (width - size) / 2;
+                    let middle = top + size / 2;
+                    shapes.push((
+                        Point::new(half, middle - half),
+                        Point::new(half + size - 1, middle + half - 1),
+                    ));
+                }
+                shapes
+            }
+            _ => vec![],
+        };
+        for (top_left, bottom_right) in shapes {
+            Rectangle::new(position + top_left, position + bottom_right)
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(target)?;
+        }
+        Ok(())
+    }
+
+    /// Draw a line of text with its top left corner at `position`.
+    pub fn draw_text<C: PixelColor, D: DrawTarget<C>>(
+        &self,
+        target: &mut D,
+        text: &str,
+        position: Point,
+        color: C,
+    ) -> std::result::Result<(), D::Error> {
+        let mut position = position;
+        for c in text.chars() {
+            self.draw_char(target, c, position, color)?;
+            position += Point::new((self.glyph_width(c) + self.spacing) as i32, 0);
+        }
+        Ok(())
+    }
+}
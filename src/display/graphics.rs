@@ -0,0 +1,735 @@
+//! Screen layouts for pixel based monochrome displays.
+
+use embedded_graphics::{
+    egtext,
+    fonts::{Font24x32, Font6x8},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::Rectangle,
+    style::PrimitiveStyle,
+    text_style,
+};
+
+use std::convert::Infallible;
+
+use super::font::DigitFont;
+use super::{icons, segments, Idle, Info, InfoValue, Snapshot, Summary, Timer, Trend};
+use crate::config::{Element, ElementFont, ElementValue, Layout, Screen};
+
+fn draw_digit<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    font: &DigitFont,
+    digit: u32,
+    position: Point,
+) -> Result<(), D::Error> {
+    font.draw_digit(target, digit, position, BinaryColor::On)
+}
+
+/// Left edge of the big digits, and the top edge which puts the digits at
+/// the bottom of the screen.
+fn digits_origin(font: &DigitFont) -> Point {
+    Point::new(24, (COMPACT_HEIGHT as i32 - font.height as i32 - 2).max(0))
+}
+
+// The burn-in protection moves the contents at most this much to the right
+// and down.
+const MAX_SHIFT: Size = Size::new(2, 1);
+
+// Displays lower than this don't fit the seven segment digits, and get the
+// compact layouts instead. The burn-in protection leaves the 64 pixel tall
+// displays a row short.
+const COMPACT_HEIGHT: u32 = 64 - MAX_SHIFT.height;
+
+fn draw_large_text<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    text: &str,
+    position: Point,
+) -> Result<(), D::Error> {
+    egtext!(
+        text = text,
+        top_left = position,
+        style = text_style!(font = Font24x32, text_color = BinaryColor::On)
+    )
+    .draw(target)
+}
+
+fn draw_small_text<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    text: &str,
+    position: Point,
+) -> Result<(), D::Error> {
+    egtext!(
+        text = text,
+        top_left = position,
+        style = text_style!(font = Font6x8, text_color = BinaryColor::On)
+    )
+    .draw(target)
+}
+
+/// Draw a line of small text aligned to the top right corner, `inset`
+/// pixels from the edges.
+fn draw_top_right<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    text: &str,
+    inset: i32,
+) -> Result<(), D::Error> {
+    let width = target.size().width as i32;
+    draw_small_text(
+        target,
+        text,
+        Point::new(width - 6 * text.len() as i32 - inset, inset),
+    )
+}
+
+/// Draw the target time in the top right corner, if one is set.
+fn draw_target_time<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    target_seconds: u32,
+    inset: i32,
+) -> Result<(), D::Error> {
+    if target_seconds != 0 {
+        draw_top_right(target, &target_seconds.to_string(), inset)?;
+    }
+    Ok(())
+}
+
+/// Draw the boost mode countdown in the top right corner while the boost is
+/// on.
+fn draw_boost_countdown<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    countdown: i64,
+) -> Result<(), D::Error> {
+    if countdown > 0 {
+        draw_top_right(target, &format!("{:04}", countdown.min(9999)), 0)?;
+    }
+    Ok(())
+}
+
+/// Draw the heating element indicator in the bottom left corner, `inset`
+/// pixels from the edges.
+fn draw_heating_icon<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    inset: i32,
+) -> Result<(), D::Error> {
+    let bottom = (target.size().height - icons::SIZE) as i32 - inset;
+    icons::draw(
+        target,
+        &icons::FLAME,
+        Point::new(inset, bottom),
+        BinaryColor::On,
+    )
+}
+
+/// Draw the machine mode at the bottom of the screen, ending at `right`,
+/// `inset` pixels from the bottom edge.
+fn draw_mode_icon<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    coffee_mode: bool,
+    right: i32,
+    inset: i32,
+) -> Result<(), D::Error> {
+    let top_left = Point::new(
+        right - icons::SIZE as i32,
+        (target.size().height - icons::SIZE) as i32 - inset,
+    );
+    icons::draw(target, icons::mode(coffee_mode), top_left, BinaryColor::On)
+}
+
+fn draw_trend<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    trend: Trend,
+    top_left: Point,
+) -> Result<(), D::Error> {
+    icons::draw(target, icons::trend(trend), top_left, BinaryColor::On)
+}
+
+/// Draw a one pixel wide line around the edges of the screen, starting from
+/// the middle of the top edge and going clockwise until `progress` of the
+/// way around.
+fn draw_progress_ring<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    progress: f32,
+) -> Result<(), D::Error> {
+    let size = target.size();
+    let (right, bottom) = (size.width as i32 - 1, size.height as i32 - 1);
+    let length = 2 * (right + bottom);
+    let start = right / 2;
+    let filled = (length as f32 * progress.clamp(0.0, 1.0)) as i32;
+
+    let pixels = (start..start + filled).map(|i| {
+        let i = i % length;
+        let point = if i < right {
+            Point::new(i, 0)
+        } else if i < right + bottom {
+            Point::new(right, i - right)
+        } else if i < 2 * right + bottom {
+            Point::new(2 * right + bottom - i, bottom)
+        } else {
+            Point::new(0, length - i)
+        };
+        Pixel(point, BinaryColor::On)
+    });
+    target.draw_iter(pixels)
+}
+
+/// Draw the heat-up progress as a bar between `left` and `right`.
+fn draw_progress_bar<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    progress: f32,
+    left: i32,
+    right: i32,
+    top: i32,
+) -> Result<(), D::Error> {
+    let bottom = top + 5;
+    Rectangle::new(Point::new(left, top), Point::new(right, bottom))
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(target)?;
+    let filled = left + ((right - left) as f32 * progress.clamp(0.0, 1.0)) as i32;
+    Rectangle::new(Point::new(left, top), Point::new(filled, bottom))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(target)
+}
+
+/// Draw the READY text inverted, centered between `left` and `right`.
+fn draw_ready_banner<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    text: &str,
+    left: i32,
+    right: i32,
+    top: i32,
+) -> Result<(), D::Error> {
+    Rectangle::new(Point::new(left, top), Point::new(right, top + 9))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(target)?;
+    let width = 6 * text.chars().count() as i32;
+    egtext!(
+        text = text,
+        top_left = Point::new((left + right + 1 - width) / 2, top + 1),
+        style = text_style!(font = Font6x8, text_color = BinaryColor::Off)
+    )
+    .draw(target)
+}
+
+/// Draw the elapsed time, and the target time in the top right corner if
+/// one is set. With `tenths` the time is shown with one decimal, and with
+/// `flash` the screen is inverted.
+pub fn draw_timer<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    font: &DigitFont,
+    timer: &Timer,
+) -> Result<(), D::Error> {
+    if timer.flash {
+        let size = target.size();
+        Rectangle::new(
+            Point::zero(),
+            Point::new(size.width as i32 - 1, size.height as i32 - 1),
+        )
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(target)?;
+        return draw_timer_screen(&mut Inverted(target), font, timer);
+    }
+    draw_timer_screen(target, font, timer)
+}
+
+fn draw_timer_screen<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    font: &DigitFont,
+    timer: &Timer,
+) -> Result<(), D::Error> {
+    let shown = timer.shown();
+    if has_elements(&timer.elements, Screen::Timer) {
+        let seconds = shown.as_secs();
+        let elapsed = timer.phases_text(" / ").unwrap_or_else(|| {
+            if timer.tenths {
+                format!("{}.{}", seconds, shown.subsec_millis() / 100)
+            } else {
+                seconds.to_string()
+            }
+        });
+        let values = ScreenValues {
+            elapsed: Some(elapsed),
+            target_seconds: Some(timer.target_seconds).filter(|&t| t != 0),
+            hx_temperature: timer.hx_temperature,
+            heating_element_on: timer.heating_element_on,
+            coffee_mode: timer.coffee_mode,
+            ..ScreenValues::default()
+        };
+        return draw_elements(target, font, &timer.elements, Screen::Timer, &values);
+    }
+    if timer.layout == Layout::Jumbo {
+        let tenths = shown.subsec_millis() / 100;
+        let digits = segments::timer_digits(
+            shown.as_secs() as u32,
+            Some(tenths).filter(|_| timer.shows_tenths()),
+        );
+        return segments::draw_digits(target, &digits, timer.shows_tenths(), BinaryColor::On);
+    }
+    // The ring only fits around the full size digits, and the other
+    // elements move away from the edges to make room for it.
+    let ring =
+        timer.progress_ring && timer.target_seconds != 0 && target.size().height >= COMPACT_HEIGHT;
+    let inset = if ring { 2 } else { 0 };
+
+    if timer.heating_element_on {
+        draw_heating_icon(target, inset)?;
+    }
+    let right = target.size().width as i32 - inset;
+    draw_mode_icon(target, timer.coffee_mode, right, inset)?;
+    if target.size().height < COMPACT_HEIGHT {
+        return draw_compact_timer(target, timer);
+    }
+    if ring {
+        let progress = timer.elapsed.as_secs_f32() / timer.target_seconds as f32;
+        draw_progress_ring(target, progress)?;
+    }
+
+    // The pre-infusion and extraction times don't fit in the big digits.
+    if let Some(text) = timer.phases_text("/") {
+        let x = (target.size().width as i32 - 24 * text.len() as i32) / 2;
+        draw_large_text(target, &text, Point::new(x, digits_origin(font).y))?;
+        return draw_target_time(target, timer.target_seconds, inset);
+    }
+
+    let seconds = shown.as_secs() as u32;
+
+    let origin = digits_origin(font);
+    let advance = Point::new(font.advance(), 0);
+
+    if seconds >= 100 {
+        let text = seconds.to_string();
+        let x = (target.size().width as i32 - font.text_width(&text)) / 2;
+        font.draw_text(target, &text, Point::new(x, origin.y), BinaryColor::On)?;
+    } else if timer.tenths {
+        let first_digit_position = origin;
+        let second_digit_position = first_digit_position + advance;
+        let decimal_point_position = Point::new(
+            (second_digit_position + advance).x,
+            origin.y + font.height as i32 - 4,
+        );
+        let tenths_digit_position = decimal_point_position + Point::new(10, 0);
+
+        if seconds / 10 != 0 {
+            draw_digit(target, font, seconds / 10, first_digit_position)?;
+        }
+        draw_digit(target, font, seconds % 10, second_digit_position)?;
+        Rectangle::new(
+            decimal_point_position,
+            decimal_point_position + Point::new(3, 3),
+        )
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(target)?;
+        draw_digit(
+            target,
+            font,
+            shown.subsec_millis() / 100,
+            Point::new(tenths_digit_position.x, origin.y),
+        )?;
+    } else {
+        let first_digit_position = origin + Point::new(6, 0);
+        let second_digit_position = first_digit_position + Point::new(font.width as i32 + 15, 0);
+
+        if seconds / 10 != 0 {
+            draw_digit(target, font, seconds / 10, first_digit_position)?;
+        }
+        draw_digit(target, font, seconds % 10, second_digit_position)?;
+    }
+
+    draw_target_time(target, timer.target_seconds, inset)
+}
+
+/// Timer layout for 32 pixel high displays, with the time in a smaller font.
+fn draw_compact_timer<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    timer: &Timer,
+) -> Result<(), D::Error> {
+    let shown = timer.shown();
+    let seconds = shown.as_secs();
+    if let Some(text) = timer.phases_text("/") {
+        let x = (target.size().width as i32 - 24 * text.len() as i32) / 2;
+        draw_large_text(target, &text, Point::new(x, 0))?;
+        return draw_target_time(target, timer.target_seconds, 0);
+    }
+    let text = if timer.shows_tenths() {
+        format!("{:>2}.{}", seconds, shown.subsec_millis() / 100)
+    } else {
+        format!("{:>2}", seconds)
+    };
+    draw_large_text(target, &text, Point::new(16, 0))?;
+
+    draw_target_time(target, timer.target_seconds, 0)
+}
+
+/// Draw the heat exchanger temperature, and optionally the steam boiler
+/// temperature and its target.
+pub fn draw_idle<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    font: &DigitFont,
+    idle: &Idle,
+) -> Result<(), D::Error> {
+    if has_elements(&idle.elements, Screen::Idle) {
+        let values = ScreenValues {
+            hx_temperature: Some(idle.hx_temperature),
+            steam_temperature: Some(idle.steam_temperature),
+            target_steam_temperature: Some(idle.target_steam_temperature),
+            boost_countdown: Some(idle.boost_countdown).filter(|&c| c > 0),
+            heating_element_on: idle.heating_element_on,
+            coffee_mode: idle.coffee_mode,
+            ..ScreenValues::default()
+        };
+        return draw_elements(target, font, &idle.elements, Screen::Idle, &values);
+    }
+    if idle.layout == Layout::Jumbo {
+        let digits = segments::temperature_digits(idle.hx_temperature.clamp(0, 999) as u32);
+        return segments::draw_digits(target, &digits, false, BinaryColor::On);
+    }
+    if idle.heating_element_on {
+        draw_heating_icon(target, 0)?;
+    }
+    if target.size().height < COMPACT_HEIGHT {
+        return draw_compact_idle(target, idle);
+    }
+    draw_mode_icon(target, idle.coffee_mode, target.size().width as i32, 0)?;
+    draw_boost_countdown(target, idle.boost_countdown)?;
+
+    let hx_temperature = idle.hx_temperature.clamp(0, 999) as u32;
+    let origin = digits_origin(font);
+    let advance = Point::new(font.advance(), 0);
+
+    if idle.show_labels {
+        draw_small_text(target, "HX", Point::new(4, origin.y))?;
+        draw_small_text(target, "C", origin + advance * 3)?;
+    }
+    draw_trend(target, idle.hx_trend, Point::new(4, origin.y + 12))?;
+    // Between the steam boiler temperatures and the digits.
+    let right = (origin + advance * 3).x - 2;
+    if idle.ready {
+        draw_ready_banner(target, idle.texts.ready, origin.x, right, origin.y - 11)?;
+    } else if let Some(progress) = idle.heat_up {
+        draw_progress_bar(target, progress, origin.x, right, origin.y - 10)?;
+    }
+
+    if hx_temperature >= 100 {
+        draw_digit(target, font, hx_temperature / 100, origin)?;
+    }
+    if hx_temperature >= 10 {
+        draw_digit(target, font, hx_temperature / 10 % 10, origin + advance)?;
+    }
+    draw_digit(target, font, hx_temperature % 10, origin + advance * 2)?;
+
+    if idle.show_steam {
+        let temperatures = format!(
+            "{} / {}",
+            idle.steam_temperature, idle.target_steam_temperature
+        );
+        if idle.show_labels {
+            draw_small_text(target, idle.texts.steam, Point::new(0, 0))?;
+        }
+        draw_small_text(target, &temperatures, Point::new(36, 0))?;
+        let end = 36 + 6 * temperatures.len() as i32;
+        draw_trend(target, idle.steam_trend, Point::new(end + 2, 0))?;
+    }
+
+    Ok(())
+}
+
+/// Idle layout for 32 pixel high displays, with the steam boiler
+/// temperatures in a column on the right.
+fn draw_compact_idle<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    idle: &Idle,
+) -> Result<(), D::Error> {
+    let column = target.size().width as i32 - 30;
+    // The steam boiler temperatures take the bottom right corner.
+    draw_mode_icon(target, idle.coffee_mode, column - 1, 0)?;
+
+    if idle.show_labels {
+        draw_small_text(target, "HX", Point::new(0, 12))?;
+        draw_small_text(target, "C", Point::new(88, 0))?;
+    }
+    draw_large_text(
+        target,
+        &format!("{:>3}", idle.hx_temperature.clamp(0, 999)),
+        Point::new(16, 0),
+    )?;
+    draw_trend(target, idle.hx_trend, Point::zero())?;
+
+    // The countdown and the READY text take the place of the label. The
+    // text is moved left if it's longer than the column.
+    draw_boost_countdown(target, idle.boost_countdown)?;
+    if idle.boost_countdown <= 0 && idle.ready {
+        let width = 6 * idle.texts.ready.chars().count() as i32;
+        let x = column.min(target.size().width as i32 - width);
+        draw_small_text(target, idle.texts.ready, Point::new(x, 0))?;
+    }
+    if idle.show_steam {
+        if idle.show_labels && idle.boost_countdown <= 0 && !idle.ready {
+            draw_small_text(target, idle.texts.steam, Point::new(column, 0))?;
+        }
+        let steam_temperature = idle.steam_temperature.to_string();
+        draw_small_text(target, &steam_temperature, Point::new(column, 12))?;
+        let end = column + 6 * steam_temperature.len() as i32;
+        draw_trend(target, idle.steam_trend, Point::new(end + 2, 12))?;
+        draw_small_text(
+            target,
+            &format!("/{}", idle.target_steam_temperature),
+            Point::new(column, 22),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Draw the value of an information page centered on the screen, with the
+/// label above it. On the 32 pixel high displays the label is shown only if
+/// it fits next to the value.
+pub fn draw_info<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    font: &DigitFont,
+    info: &Info,
+) -> Result<(), D::Error> {
+    let size = target.size();
+    let text = info.value.text();
+    let label = info.label();
+    let text_width = 24 * text.len() as i32;
+
+    if size.height < COMPACT_HEIGHT {
+        let x = size.width as i32 - text_width;
+        if info.show_labels && 6 * label.chars().count() as i32 + 2 <= x {
+            draw_small_text(target, label, Point::zero())?;
+        }
+        return draw_large_text(target, &text, Point::new(x, 0));
+    }
+
+    if info.show_labels {
+        draw_small_text(target, label, Point::zero())?;
+    }
+    // The clock and the temperature difference are drawn with the big
+    // digits if they fit.
+    if let InfoValue::Clock { .. } | InfoValue::SteamDelta(_) = info.value {
+        let width = font.text_width(&text);
+        if width <= size.width as i32 {
+            let position = Point::new((size.width as i32 - width) / 2, digits_origin(font).y);
+            return font.draw_text(target, &text, position, BinaryColor::On);
+        }
+    }
+    let x = (size.width as i32 - text_width) / 2;
+    draw_large_text(target, &text, Point::new(x, 22))
+}
+
+/// Values the elements of a custom layout can show. The ones which are None
+/// aren't drawn.
+#[derive(Default)]
+struct ScreenValues {
+    elapsed: Option<String>,
+    target_seconds: Option<u32>,
+    hx_temperature: Option<i64>,
+    steam_temperature: Option<i64>,
+    target_steam_temperature: Option<i64>,
+    boost_countdown: Option<i64>,
+    heating_element_on: bool,
+    coffee_mode: bool,
+}
+
+fn has_elements(elements: &[Element], screen: Screen) -> bool {
+    elements.iter().any(|e| e.screen == screen)
+}
+
+/// Draw the elements of a custom layout for the screen.
+fn draw_elements<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    font: &DigitFont,
+    elements: &[Element],
+    screen: Screen,
+    values: &ScreenValues,
+) -> Result<(), D::Error> {
+    for element in elements.iter().filter(|e| e.screen == screen) {
+        let position = Point::new(element.x, element.y);
+        let text = match element.value {
+            ElementValue::Elapsed => values.elapsed.clone(),
+            ElementValue::TargetTime => values.target_seconds.map(|t| t.to_string()),
+            ElementValue::HxTemperature => values.hx_temperature.map(|t| t.to_string()),
+            ElementValue::SteamTemperature => values.steam_temperature.map(|t| t.to_string()),
+            ElementValue::TargetSteamTemperature => {
+                values.target_steam_temperature.map(|t| t.to_string())
+            }
+            ElementValue::BoostCountdown => values.boost_countdown.map(|c| c.to_string()),
+            ElementValue::Text => Some(element.text.clone()),
+            ElementValue::Heating => {
+                if values.heating_element_on {
+                    icons::draw(target, &icons::FLAME, position, BinaryColor::On)?;
+                }
+                None
+            }
+            ElementValue::Mode => {
+                let icon = icons::mode(values.coffee_mode);
+                icons::draw(target, icon, position, BinaryColor::On)?;
+                None
+            }
+        };
+        if let Some(text) = text {
+            match element.font {
+                ElementFont::Digits => font.draw_text(target, &text, position, BinaryColor::On)?,
+                ElementFont::Large => draw_large_text(target, &text, position)?,
+                ElementFont::Small => draw_small_text(target, &text, position)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Draw the number of the shot and its duration, and the heat exchanger
+/// temperatures at the start and the end of it.
+pub fn draw_summary<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    summary: &Summary,
+) -> Result<(), D::Error> {
+    let size = target.size();
+    let time = summary.time_text();
+    let shot = format!("{} {}", summary.texts.shot, summary.number);
+
+    if size.height < COMPACT_HEIGHT {
+        draw_small_text(target, &format!("{}: {}s", shot, time), Point::zero())?;
+        return draw_small_text(target, &summary.hx_text(), Point::new(0, 12));
+    }
+
+    draw_small_text(target, &shot, Point::zero())?;
+    if let Some(phases) = summary.phases_text() {
+        draw_top_right(target, &phases, 0)?;
+    }
+    let x = (size.width as i32 - 24 * time.len() as i32) / 2;
+    draw_large_text(target, &time, Point::new(x, 14))?;
+    draw_small_text(
+        target,
+        &summary.hx_text(),
+        Point::new(0, size.height as i32 - 8),
+    )
+}
+
+/// Draw lines of small text from the top of the screen.
+pub fn draw_message<D: DrawTarget<BinaryColor>>(
+    target: &mut D,
+    lines: &[&str],
+) -> Result<(), D::Error> {
+    let rows = (target.size().height / 10) as usize;
+    for (row, line) in lines.iter().take(rows).enumerate() {
+        draw_small_text(target, line, Point::new(0, 10 * row as i32))?;
+    }
+    Ok(())
+}
+
+/// In-memory copy of a monochrome screen. The layouts are drawn into it,
+/// and it's copied to the display when flushing, which allows taking
+/// snapshots of the screen.
+pub struct MonoFrame {
+    size: Size,
+    pixels: Vec<bool>,
+}
+
+impl MonoFrame {
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            pixels: vec![false; (size.width * size.height) as usize],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|p| *p = false);
+    }
+
+    /// Draw the pixels which differ from `shown`, the frame the display
+    /// currently has, and update `shown` to match. Returns true if anything
+    /// changed. Leaving the other pixels alone lets the drivers which track
+    /// the changed area send only that part to the display.
+    pub fn draw_changes<D: DrawTarget<BinaryColor>>(
+        &self,
+        shown: &mut MonoFrame,
+        target: &mut D,
+    ) -> Result<bool, D::Error> {
+        let width = self.size.width as usize;
+        let mut changed = false;
+        for (i, (&new, old)) in self.pixels.iter().zip(shown.pixels.iter_mut()).enumerate() {
+            if new != *old {
+                let point = Point::new((i % width) as i32, (i / width) as i32);
+                target.draw_pixel(Pixel(point, BinaryColor::from(new)))?;
+                *old = new;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// The frame with the lit pixels in white.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            width: self.size.width,
+            height: self.size.height,
+            rgb: self
+                .pixels
+                .iter()
+                .flat_map(|&on| [if on { 0xff } else { 0 }; 3])
+                .collect(),
+        }
+    }
+}
+
+impl DrawTarget<BinaryColor> for MonoFrame {
+    type Error = Infallible;
+
+    fn draw_pixel(&mut self, Pixel(point, color): Pixel<BinaryColor>) -> Result<(), Infallible> {
+        if point.x >= 0
+            && point.y >= 0
+            && (point.x as u32) < self.size.width
+            && (point.y as u32) < self.size.height
+        {
+            let index = point.y as u32 * self.size.width + point.x as u32;
+            self.pixels[index as usize] = color.is_on();
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+/// Moves everything drawn through it by `offset`. The size is reduced by
+/// the largest possible offset, so that the layouts aligned to the right or
+/// the bottom edge stay on the screen.
+pub struct Shifted<'a, D> {
+    target: &'a mut D,
+    offset: Point,
+}
+
+impl<'a, D> Shifted<'a, D> {
+    pub fn new(target: &'a mut D, offset: Point) -> Self {
+        Self { target, offset }
+    }
+}
+
+impl<D: DrawTarget<BinaryColor>> DrawTarget<BinaryColor> for Shifted<'_, D> {
+    type Error = D::Error;
+
+    fn draw_pixel(&mut self, Pixel(point, color): Pixel<BinaryColor>) -> Result<(), D::Error> {
+        self.target.draw_pixel(Pixel(point + self.offset, color))
+    }
+
+    fn size(&self) -> Size {
+        self.target.size() - MAX_SHIFT
+    }
+}
+
+/// Inverts everything drawn through it.
+struct Inverted<'a, D>(&'a mut D);
+
+impl<D: DrawTarget<BinaryColor>> DrawTarget<BinaryColor> for Inverted<'_, D> {
+    type Error = D::Error;
+
+    fn draw_pixel(&mut self, Pixel(point, color): Pixel<BinaryColor>) -> Result<(), D::Error> {
+        self.0.draw_pixel(Pixel(point, color.invert()))
+    }
+
+    fn size(&self) -> Size {
+        self.0.size()
+    }
+}
@@ -0,0 +1,128 @@
+//! Helpers for opening the Linux devices the displays are connected to.
+
+use linux_embedded_hal::gpio_cdev::{Chip, EventRequestFlags, LineEventHandle, LineRequestFlags};
+use linux_embedded_hal::spidev::{SpiModeFlags, SpidevOptions};
+use linux_embedded_hal::{CdevPin, Spidev};
+
+use std::thread;
+
+use super::Result;
+
+/// Open an SPI device in mode 0 with the given clock speed.
+pub fn open_spi(device: &str, speed_hz: u32) -> Result<Spidev> {
+    let mut spi = Spidev::open(device).map_err(|e| format!("failed to open {}: {}", device, e))?;
+    let options = SpidevOptions::new()
+        .bits_per_word(8)
+        .max_speed_hz(speed_hz)
+        .mode(SpiModeFlags::SPI_MODE_0)
+        .build();
+    spi.0
+        .configure(&options)
+        .map_err(|e| format!("failed to configure {}: {}", device, e))?;
+    Ok(spi)
+}
+
+fn request_pin(chip: &str, line: u32, flags: LineRequestFlags, initial: u8) -> Result<CdevPin> {
+    let mut chip = Chip::new(chip).map_err(|e| format!("failed to open {}: {}", chip, e))?;
+    let handle = chip
+        .get_line(line)
+        .and_then(|l| l.request(flags, initial, "marax-shot-timer"))
+        .map_err(|e| format!("failed to request GPIO line {}: {}", line, e))?;
+    Ok(CdevPin::new(handle)?)
+}
+
+/// Request a GPIO line as an output pin.
+pub fn output_pin(chip: &str, line: u32, initial: u8) -> Result<CdevPin> {
+    request_pin(chip, line, LineRequestFlags::OUTPUT, initial)
+}
+
+/// Request a GPIO line as an open drain output, which can also be read to
+/// see if another device is pulling it low.
+pub fn open_drain_pin(chip: &str, line: u32) -> Result<CdevPin> {
+    request_pin(
+        chip,
+        line,
+        LineRequestFlags::OUTPUT | LineRequestFlags::OPEN_DRAIN,
+        1,
+    )
+}
+
+// Edges closer to each other than this are contact bounce.
+const BUTTON_DEBOUNCE_NS: u64 = 50_000_000;
+const ENCODER_DEBOUNCE_NS: u64 = 2_000_000;
+
+fn falling_edges(chip: &mut Chip, line: u32) -> Result<LineEventHandle> {
+    chip.get_line(line)
+        .and_then(|l| {
+            l.events(
+                LineRequestFlags::INPUT,
+                EventRequestFlags::FALLING_EDGE,
+                "marax-shot-timer",
+            )
+        })
+        .map_err(|e| format!("failed to request GPIO line {}: {}", line, e).into())
+}
+
+/// Call `on_edge` for every falling edge of the GPIO line, ignoring the
+/// edges which follow the previous one within `debounce_ns`.
+fn watch_edges(
+    events: LineEventHandle,
+    line: u32,
+    debounce_ns: u64,
+    mut on_edge: impl FnMut() + Send + 'static,
+) {
+    // Waiting for the events blocks, so do it in a thread of its own.
+    thread::spawn(move || {
+        let mut last = None;
+        for event in events {
+            match event {
+                Ok(event) => {
+                    let time = event.timestamp();
+                    if last.is_none_or(|last| time.saturating_sub(last) >= debounce_ns) {
+                        on_edge();
+                    }
+                    last = Some(time);
+                }
+                Err(e) => {
+                    println!("Failed to read GPIO line {}: {}", line, e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Call `pressed` whenever the button connected to the GPIO line is
+/// pressed, i.e. the line is pulled low.
+pub fn watch_button(chip: &str, line: u32, pressed: impl Fn() + Send + 'static) -> Result<()> {
+    let mut chip = Chip::new(chip).map_err(|e| format!("failed to open {}: {}", chip, e))?;
+    let events = falling_edges(&mut chip, line)?;
+    watch_edges(events, line, BUTTON_DEBOUNCE_NS, pressed);
+    Ok(())
+}
+
+/// Call `turned` with true whenever the rotary encoder connected to the
+/// GPIO lines `a` and `b` is turned a step clockwise, and with false when
+/// it's turned counterclockwise.
+pub fn watch_encoder(
+    chip: &str,
+    a: u32,
+    b: u32,
+    turned: impl Fn(bool) + Send + 'static,
+) -> Result<()> {
+    let mut chip = Chip::new(chip).map_err(|e| format!("failed to open {}: {}", chip, e))?;
+    let events = falling_edges(&mut chip, a)?;
+    let b_handle = chip
+        .get_line(b)
+        .and_then(|l| l.request(LineRequestFlags::INPUT, 0, "marax-shot-timer"))
+        .map_err(|e| format!("failed to request GPIO line {}: {}", b, e))?;
+
+    // When A falls, B is still high if the encoder is turning clockwise.
+    watch_edges(events, a, ENCODER_DEBOUNCE_NS, move || {
+        match b_handle.get_value() {
+            Ok(value) => turned(value != 0),
+            Err(e) => println!("Failed to read GPIO line {}: {}", b, e),
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use ::hd44780_driver::bus::I2CBus;
+use ::hd44780_driver::error::Error;
+use ::hd44780_driver::HD44780;
+use ::hd44780_driver::{Cursor, CursorBlink, Display, DisplayMode};
+use linux_embedded_hal::{Delay, I2cdev};
+
+use super::{DisplayBackend, Idle, Info, Result, Summary, Timer};
+use crate::config::{Brightness, DisplayConfig};
+
+fn display_error(e: Error) -> Box<dyn std::error::Error + Send + Sync> {
+    format!("display error: {:?}", e).into()
+}
+
+// The degree sign in the character ROM of the common HD44780 variant.
+const DEGREE: u8 = 0xdf;
+// The umlauts in the same ROM.
+const A_UMLAUT: u8 = 0xe1;
+const O_UMLAUT: u8 = 0xef;
+const U_UMLAUT: u8 = 0xf5;
+
+/// HD44780 character LCD with a PCF8574 I2C backpack. The display is slow to
+/// write to, so only the characters which have changed are sent to it.
+pub struct Hd44780Display {
+    lcd: HD44780<I2CBus<I2cdev>>,
+    delay: Delay,
+    columns: usize,
+    lines: Vec<Vec<u8>>,
+    shown: Vec<Vec<u8>>,
+}
+
+impl Hd44780Display {
+    pub fn open_i2c(config: &DisplayConfig) -> Result<Self> {
+        let (columns, rows) = config.size();
+        if rows > 4 {
+            return Err("display error: HD44780 displays have at most 4 rows".into());
+        }
+
+        let i2c = I2cdev::new(&config.i2c_bus)
+            .map_err(|e| format!("failed to open {}: {}", config.i2c_bus, e))?;
+        let mut delay = Delay;
+        let mut lcd =
+            HD44780::new_i2c(i2c, config.i2c_address, &mut delay).map_err(display_error)?;
+        lcd.set_display_mode(
+            DisplayMode {
+                cursor_visibility: Cursor::Invisible,
+                cursor_blink: CursorBlink::Off,
+                display: Display::On,
+            },
+            &mut delay,
+        )
+        .map_err(display_error)?;
+        lcd.clear(&mut delay).map_err(display_error)?;
+
+        let blank = vec![b' '; columns as usize];
+        Ok(Self {
+            lcd,
+            delay,
+            columns: columns as usize,
+            lines: vec![blank.clone(); rows as usize],
+            shown: vec![blank; rows as usize],
+        })
+    }
+
+    /// Replace the text on a row, if the display has that many. `°` is shown
+    /// as the degree sign, and the umlauts of the ROM are used for `ä`, `ö`
+    /// and `ü`.
+    fn set_line(&mut self, row: usize, text: &str) {
+        let columns = self.columns;
+        if let Some(line) = self.lines.get_mut(row) {
+            let chars = text
+                .chars()
+                .map(|c| match c {
+                    '°' => DEGREE,
+                    'ä' => A_UMLAUT,
+                    'ö' => O_UMLAUT,
+                    'ü' => U_UMLAUT,
+                    c if c.is_ascii() => c as u8,
+                    _ => b'?',
+                })
+                .chain(std::iter::repeat(b' '));
+            line.iter_mut()
+                .zip(chars)
+                .take(columns)
+                .for_each(|(dst, src)| *dst = src);
+        }
+    }
+
+    /// DDRAM address of the start of a row. The third and the fourth rows
+    /// continue the first and the second ones.
+    fn row_address(&self, row: usize) -> u8 {
+        let columns = self.columns as u8;
+        [0x00, 0x40, columns, 0x40 + columns][row]
+    }
+}
+
+impl DisplayBackend for Hd44780Display {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        let shown = timer.shown();
+        let seconds = shown.as_secs();
+        let mut time = if let Some(phases) = timer.phases_text(" / ") {
+            format!("{} {}s", timer.texts.shot, phases)
+        } else if timer.tenths {
+            format!(
+                "{} {:>2}.{}s",
+                timer.texts.shot,
+                seconds,
+                shown.subsec_millis() / 100
+            )
+        } else {
+            format!("{} {:>2}s", timer.texts.shot, seconds)
+        };
+        if timer.flash {
+            time.push_str(" STOP");
+        } else if timer.target_seconds != 0 {
+            time.push_str(&format!(" /{}s", timer.target_seconds));
+        }
+
+        self.clear()?;
+        self.set_line(0, &time);
+        if let Some(hx_temperature) = timer.hx_temperature {
+            self.set_line(1, &format!("HX {:>3}°C", hx_temperature));
+        }
+
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        let status = if idle.heating_element_on {
+            idle.texts.heating
+        } else if idle.ready {
+            idle.texts.ready
+        } else {
+            ""
+        };
+
+        self.clear()?;
+        self.set_line(0, &format!("HX {:>3}°C {}", idle.hx_temperature, status));
+        if idle.show_steam {
+            self.set_line(
+                1,
+                &format!(
+                    "{} {}/{}°C",
+                    idle.texts.steam, idle.steam_temperature, idle.target_steam_temperature
+                ),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.clear()?;
+        self.set_line(0, info.label());
+        self.set_line(1, &info.value.text());
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.clear()?;
+        self.set_line(
+            0,
+            &format!(
+                "{} {}: {}s",
+                summary.texts.shot,
+                summary.number,
+                summary.phases_text().unwrap_or_else(|| summary.time_text())
+            ),
+        );
+        self.set_line(
+            1,
+            &format!(
+                "HX {}->{}°C",
+                summary.start_hx_temperature, summary.end_hx_temperature
+            ),
+        );
+        Ok(())
+    }
+
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()> {
+        self.clear()?;
+        for (row, line) in lines.iter().enumerate() {
+            self.set_line(row, line);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        for line in self.lines.iter_mut() {
+            line.iter_mut().for_each(|c| *c = b' ');
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for row in 0..self.lines.len() {
+            let line = &self.lines[row];
+            let shown = &self.shown[row];
+            let first = line.iter().zip(shown).position(|(a, b)| a != b);
+            let last = line.iter().zip(shown).rposition(|(a, b)| a != b);
+
+            if let (Some(first), Some(last)) = (first, last) {
+                let address = self.row_address(row) + first as u8;
+                self.lcd
+                    .set_cursor_pos(address, &mut self.delay)
+                    .map_err(display_error)?;
+                self.lcd
+                    .write_bytes(&self.lines[row][first..=last], &mut self.delay)
+                    .map_err(display_error)?;
+                self.shown[row].clone_from(&self.lines[row]);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, _brightness: Brightness) -> Result<()> {
+        // The backlight of the I2C backpack can only be switched on or off.
+        Ok(())
+    }
+}
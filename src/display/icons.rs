@@ -0,0 +1,115 @@
+//! Small status icons for the pixel based displays.
+
+use embedded_graphics::{pixelcolor::PixelColor, prelude::*};
+
+use super::Trend;
+
+/// Width and height of the icons in pixels.
+pub const SIZE: u32 = 8;
+
+/// Icon bitmap, one byte per row with the leftmost pixel in the highest bit.
+pub type Icon = [u8; SIZE as usize];
+
+/// Shown while the heating element is on.
+pub const FLAME: Icon = [
+    0b0001_0000,
+    0b0001_1000,
+    0b0011_1000,
+    0b0111_1100,
+    0b0110_1100,
+    0b1100_0110,
+    0b1100_0110,
+    0b0111_1100,
+];
+
+/// Shown in the coffee mode.
+pub const CUP: Icon = [
+    0b0000_0000,
+    0b1111_1100,
+    0b1000_0110,
+    0b1000_0101,
+    0b1000_0110,
+    0b0100_1000,
+    0b0011_0000,
+    0b1111_1110,
+];
+
+/// Shown in the steam mode.
+pub const STEAM: Icon = [
+    0b0010_0100,
+    0b0100_1000,
+    0b0100_1000,
+    0b0010_0100,
+    0b0001_0010,
+    0b0001_0010,
+    0b0010_0100,
+    0b0000_0000,
+];
+
+/// Icon for the machine mode.
+pub fn mode(coffee_mode: bool) -> &'static Icon {
+    if coffee_mode {
+        &CUP
+    } else {
+        &STEAM
+    }
+}
+
+/// Shown next to a rising temperature.
+pub const ARROW_UP: Icon = [
+    0b0001_0000,
+    0b0011_1000,
+    0b0111_1100,
+    0b1101_0110,
+    0b0001_0000,
+    0b0001_0000,
+    0b0001_0000,
+    0b0000_0000,
+];
+
+/// Shown next to a falling temperature.
+pub const ARROW_DOWN: Icon = [
+    0b0001_0000,
+    0b0001_0000,
+    0b0001_0000,
+    0b1101_0110,
+    0b0111_1100,
+    0b0011_1000,
+    0b0001_0000,
+    0b0000_0000,
+];
+
+/// Shown next to a steady temperature.
+pub const STEADY: Icon = [
+    0b0000_0000,
+    0b0000_1000,
+    0b0000_1100,
+    0b1111_1110,
+    0b0000_1100,
+    0b0000_1000,
+    0b0000_0000,
+    0b0000_0000,
+];
+
+/// Icon for the temperature trend.
+pub fn trend(trend: Trend) -> &'static Icon {
+    match trend {
+        Trend::Rising => &ARROW_UP,
+        Trend::Falling => &ARROW_DOWN,
+        Trend::Steady => &STEADY,
+    }
+}
+
+pub fn draw<C: PixelColor, D: DrawTarget<C>>(
+    target: &mut D,
+    icon: &Icon,
+    top_left: Point,
+    color: C,
+) -> Result<(), D::Error> {
+    let pixels = icon.iter().enumerate().flat_map(|(y, row)| {
+        (0..SIZE as i32)
+            .filter(move |x| row & (0x80 >> x) != 0)
+            .map(move |x| Pixel(top_left + Point::new(x, y as i32), color))
+    });
+    target.draw_iter(pixels)
+}
@@ -0,0 +1,183 @@
+use ::max7219::{connectors::SpiConnector, DataError, MAX7219};
+use linux_embedded_hal::Spidev;
+
+use super::{contrast, hal, DisplayBackend, Idle, Info, InfoValue, Result, Summary, Timer};
+use crate::config::{Brightness, DisplayConfig};
+
+// The driver can address at most this many modules in a chain.
+const MAX_MODULES: u32 = 8;
+
+fn display_error(e: DataError) -> Box<dyn std::error::Error + Send + Sync> {
+    format!("display error: {:?}", e).into()
+}
+
+/// Intensity register value, 0-15.
+fn intensity(brightness: Brightness) -> u8 {
+    contrast(brightness) >> 4
+}
+
+/// Text of one eight digit module, with the decimal points as a bit mask
+/// where the most significant bit is the leftmost digit.
+#[derive(Clone, Copy)]
+struct Digits {
+    text: [u8; 8],
+    dots: u8,
+}
+
+impl Digits {
+    const BLANK: Digits = Digits {
+        text: *b"        ",
+        dots: 0,
+    };
+
+    fn set(&mut self, position: usize, s: &str) {
+        let end = (position + s.len()).min(self.text.len());
+        self.text[position..end].copy_from_slice(&s.as_bytes()[..end - position]);
+    }
+}
+
+/// Four digit temperature, e.g. " 93C".
+fn temperature(t: i64) -> String {
+    format!("{:>3}C", t.clamp(-99, 999))
+}
+
+/// Chain of MAX7219 driven eight digit seven-segment LED modules connected
+/// to the SPI bus. The first module shows the timer on the left and the
+/// heat exchanger temperature on the right, and the second one, if
+/// present, the steam boiler temperature and its target.
+pub struct Max7219Display {
+    disp: MAX7219<SpiConnector<Spidev>>,
+    modules: Vec<Digits>,
+}
+
+impl Max7219Display {
+    pub fn open_spi(config: &DisplayConfig) -> Result<Self> {
+        if config.modules == 0 || config.modules > MAX_MODULES {
+            return Err(format!(
+                "display error: the number of MAX7219 modules must be between 1 and {}",
+                MAX_MODULES
+            )
+            .into());
+        }
+
+        let spi = hal::open_spi(&config.spi_device, config.spi_speed_hz)?;
+        let mut disp = MAX7219::from_spi(config.modules as usize, spi).map_err(display_error)?;
+        disp.power_on().map_err(display_error)?;
+
+        let mut disp = Self {
+            disp,
+            modules: vec![Digits::BLANK; config.modules as usize],
+        };
+        disp.set_brightness(config.brightness)?;
+        disp.flush()?;
+
+        Ok(disp)
+    }
+}
+
+impl DisplayBackend for Max7219Display {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        self.clear()?;
+
+        let shown = timer.shown();
+        let seconds = shown.as_secs();
+        let first = &mut self.modules[0];
+        if timer.tenths {
+            let tenths = shown.subsec_millis() / 100;
+            first.set(0, &format!("{:>3}{}", seconds, tenths));
+            first.dots = 0b0010_0000;
+        } else {
+            first.set(0, &format!("{:>4}", seconds));
+        }
+        if let Some(hx_temperature) = timer.hx_temperature {
+            first.set(4, &temperature(hx_temperature));
+        }
+        if timer.flash {
+            // Light up all the decimal points.
+            first.dots = 0xff;
+        }
+
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        self.clear()?;
+
+        self.modules[0].set(4, &temperature(idle.hx_temperature));
+        if idle.show_steam {
+            if let Some(second) = self.modules.get_mut(1) {
+                second.set(0, &temperature(idle.steam_temperature));
+                second.set(4, &temperature(idle.target_steam_temperature));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.clear()?;
+
+        let first = &mut self.modules[0];
+        match info.value {
+            InfoValue::LastShot(elapsed)
+            | InfoValue::AverageShot(elapsed)
+            | InfoValue::MedianShot(elapsed) => {
+                let tenths = elapsed.subsec_millis() / 100;
+                first.set(0, &format!("{:>3}{}", elapsed.as_secs(), tenths));
+                first.dots = 0b0010_0000;
+            }
+            InfoValue::ShotCount(count) => first.set(0, &format!("{:>8}", count)),
+            InfoValue::TotalShots(count) => first.set(0, &format!("{:>8}", count)),
+            InfoValue::Clock { hour, minute } => {
+                first.set(4, &format!("{:02}{:02}", hour, minute));
+                first.dots = 0b0000_0100;
+            }
+            InfoValue::SteamDelta(delta) => first.set(0, &format!("{:>8}", delta)),
+        }
+
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.clear()?;
+
+        // The shot time on the left and the heat exchanger temperature at
+        // the end of the shot on the right, like while the shot runs.
+        let first = &mut self.modules[0];
+        let tenths = summary.elapsed.subsec_millis() / 100;
+        first.set(0, &format!("{:>3}{}", summary.elapsed.as_secs(), tenths));
+        first.dots = 0b0010_0000;
+        first.set(4, &temperature(summary.end_hx_temperature));
+
+        Ok(())
+    }
+
+    fn draw_message(&mut self, _lines: &[&str]) -> Result<()> {
+        self.clear()?;
+        self.modules[0].set(0, "--------");
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.modules.iter_mut().for_each(|m| *m = Digits::BLANK);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for (addr, module) in self.modules.iter().enumerate() {
+            self.disp
+                .write_str(addr, &module.text, module.dots)
+                .map_err(display_error)?;
+        }
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        for addr in 0..self.modules.len() {
+            self.disp
+                .set_intensity(addr, intensity(brightness))
+                .map_err(display_error)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,659 @@
+//! Display backends. The timer logic talks to the display only through the
+//! `DisplayBackend` trait, so that different kinds of hardware can be
+//! supported.
+
+mod animation;
+mod color;
+mod font;
+mod graphics;
+mod hal;
+mod hd44780;
+mod icons;
+mod max7219;
+mod png;
+mod segments;
+mod sh1106;
+mod ssd1306;
+mod ssd1309;
+mod st7735;
+mod st7789;
+mod tm1637;
+
+use std::{
+    cmp::Ordering,
+    collections::VecDeque,
+    error::Error,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+pub use animation::{Animation, Frame};
+pub use hal::{watch_button, watch_encoder};
+
+use crate::config::{
+    Brightness, DisplayConfig, Driver, Element, Interface, Layout, Rotation, Screen,
+};
+use crate::health::Health;
+use crate::lang::Texts;
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Contents of the screen while a shot is running.
+pub struct Timer {
+    pub elapsed: Duration,
+    /// How long the pre-infusion took, once it has ended.
+    pub preinfusion: Option<Duration>,
+    /// Show the elapsed time with tenths of a second.
+    pub tenths: bool,
+    /// Target shot time in seconds, or 0 for no target.
+    pub target_seconds: u32,
+    /// Count down from the target time to zero, and then up again.
+    pub countdown: bool,
+    /// Heat exchanger temperature, for displays which show it next to the
+    /// timer.
+    pub hx_temperature: Option<i64>,
+    /// The heating element is on.
+    pub heating_element_on: bool,
+    /// The machine is in the coffee mode instead of the steam mode.
+    pub coffee_mode: bool,
+    /// Highlight the screen, to tell that the target time has been reached.
+    pub flash: bool,
+    /// Show the progress towards the target time around the edges of the
+    /// screen.
+    pub progress_ring: bool,
+    pub layout: Layout,
+    /// Custom layout replacing the built-in one, if there are any elements
+    /// for the timer screen.
+    pub elements: Vec<Element>,
+    pub texts: &'static Texts,
+}
+
+impl Timer {
+    /// The time on the display: the elapsed time, or in the countdown mode
+    /// the time left until the target and then the time past it.
+    pub fn shown(&self) -> Duration {
+        let target = Duration::from_secs(self.target_seconds.into());
+        if !self.countdown || self.target_seconds == 0 {
+            return self.elapsed;
+        }
+        match target.checked_sub(self.elapsed) {
+            // Round up, so that the countdown starts from the target and
+            // reaches zero at it.
+            Some(left) => {
+                let step = if self.tenths { 100 } else { 1000 };
+                let steps = left.as_millis().div_ceil(step);
+                Duration::from_millis((steps * step) as u64)
+            }
+            None => self.elapsed - target,
+        }
+    }
+
+    /// Show the tenths of a second. Past 99 seconds they make room for the
+    /// third digit on the displays with big digits.
+    pub fn shows_tenths(&self) -> bool {
+        self.tenths && self.shown().as_secs() < 100
+    }
+
+    /// The pre-infusion and extraction times in whole seconds, like
+    /// "8 / 27" with " / " as the separator, once the pre-infusion has
+    /// ended.
+    pub fn phases_text(&self, separator: &str) -> Option<String> {
+        self.preinfusion
+            .map(|preinfusion| phases_text(preinfusion, self.elapsed, separator))
+    }
+}
+
+/// Contents of the screen while the machine is idle.
+pub struct Idle {
+    pub hx_temperature: i64,
+    pub steam_temperature: i64,
+    pub target_steam_temperature: i64,
+    /// The heating element is on, so the machine isn't ready yet.
+    pub heating_element_on: bool,
+    /// The machine is in the coffee mode instead of the steam mode.
+    pub coffee_mode: bool,
+    /// Countdown of the boost mode, or 0 when the boost isn't on.
+    pub boost_countdown: i64,
+    pub hx_trend: Trend,
+    pub steam_trend: Trend,
+    /// How far the steam boiler has heated up from cold towards the target
+    /// temperature, from 0.0 to 1.0, or None once it's there.
+    pub heat_up: Option<f32>,
+    /// The machine has settled at the target temperature, and the READY
+    /// banner is shown.
+    pub ready: bool,
+    /// Show also the steam boiler temperature and its target.
+    pub show_steam: bool,
+    /// Show the labels next to the temperatures.
+    pub show_labels: bool,
+    pub layout: Layout,
+    /// Custom layout replacing the built-in one, if there are any elements
+    /// for the idle screen.
+    pub elements: Vec<Element>,
+    pub texts: &'static Texts,
+}
+
+/// Value shown on one of the information pages.
+pub enum InfoValue {
+    /// Duration of the last shot.
+    LastShot(Duration),
+    /// Average duration of the recent shots.
+    AverageShot(Duration),
+    /// Median duration of the recent shots.
+    MedianShot(Duration),
+    /// Number of shots pulled today.
+    ShotCount(u32),
+    /// Number of shots pulled over the lifetime of the machine.
+    TotalShots(u64),
+    /// Time of day.
+    Clock { hour: u32, minute: u32 },
+    /// Steam boiler temperature minus its target.
+    SteamDelta(i64),
+}
+
+impl InfoValue {
+    pub fn label(&self, texts: &Texts) -> &'static str {
+        match self {
+            InfoValue::LastShot(_) => texts.last_shot,
+            InfoValue::AverageShot(_) => texts.average_shot,
+            InfoValue::MedianShot(_) => texts.median_shot,
+            InfoValue::ShotCount(_) => texts.shots_today,
+            InfoValue::TotalShots(_) => texts.total_shots,
+            InfoValue::Clock { .. } => texts.time,
+            InfoValue::SteamDelta(_) => texts.steam_delta,
+        }
+    }
+
+    /// The value as text, for the displays which can show any characters.
+    pub fn text(&self) -> String {
+        match self {
+            InfoValue::LastShot(elapsed)
+            | InfoValue::AverageShot(elapsed)
+            | InfoValue::MedianShot(elapsed) => {
+                format!("{}.{}", elapsed.as_secs(), elapsed.subsec_millis() / 100)
+            }
+            InfoValue::ShotCount(count) => count.to_string(),
+            InfoValue::TotalShots(count) => count.to_string(),
+            InfoValue::Clock { hour, minute } => format!("{:02}:{:02}", hour, minute),
+            InfoValue::SteamDelta(delta) => format!("{:+}", (*delta).clamp(-99, 99)),
+        }
+    }
+}
+
+/// Contents of an information page shown in turn with the idle screen.
+pub struct Info {
+    pub value: InfoValue,
+    /// Show the label of the value.
+    pub show_labels: bool,
+    pub texts: &'static Texts,
+}
+
+impl Info {
+    pub fn label(&self) -> &'static str {
+        self.value.label(self.texts)
+    }
+}
+
+/// Contents of the screen for a while after a shot.
+pub struct Summary {
+    /// Duration of the shot.
+    pub elapsed: Duration,
+    /// Duration of the pre-infusion, if its end was marked.
+    pub preinfusion: Option<Duration>,
+    /// Heat exchanger temperature when the shot started.
+    pub start_hx_temperature: i64,
+    /// Heat exchanger temperature when the shot ended.
+    pub end_hx_temperature: i64,
+    /// Number of the shot today, starting from 1.
+    pub number: u32,
+    pub texts: &'static Texts,
+}
+
+impl Summary {
+    /// Duration of the shot with one decimal, like "27.3".
+    pub fn time_text(&self) -> String {
+        format!(
+            "{}.{}",
+            self.elapsed.as_secs(),
+            self.elapsed.subsec_millis() / 100
+        )
+    }
+
+    /// The pre-infusion and extraction times, like "8 / 27", if the end of
+    /// the pre-infusion was marked.
+    pub fn phases_text(&self) -> Option<String> {
+        self.preinfusion
+            .map(|preinfusion| phases_text(preinfusion, self.elapsed, " / "))
+    }
+
+    /// Heat exchanger temperatures at the start and the end of the shot.
+    pub fn hx_text(&self) -> String {
+        format!(
+            "HX {}->{}C",
+            self.start_hx_temperature, self.end_hx_temperature
+        )
+    }
+}
+
+/// Copy of the screen contents as 8-bit RGB pixels, row by row.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode(self.width, self.height, &self.rgb)
+    }
+}
+
+/// A display showing the shot timer and the machine status. The drawing
+/// functions replace the current contents of the screen, which become
+/// visible after `flush()`.
+pub trait DisplayBackend: Send {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()>;
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()>;
+    fn draw_info(&mut self, info: &Info) -> Result<()>;
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()>;
+    /// Show a frame of the idle animation centered on the screen. The
+    /// displays which aren't made of pixels are left blank.
+    fn draw_animation(&mut self, _frame: &Frame) -> Result<()> {
+        self.clear()
+    }
+    /// Show lines of text, as many as fit on the screen. The segment
+    /// displays just show dashes.
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()>;
+    fn clear(&mut self) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()>;
+
+    /// Move the contents of the screen by a few pixels to the right and
+    /// down, to protect OLED displays from burning in. Takes effect when the
+    /// screen is drawn the next time.
+    fn set_shift(&mut self, _shift: (i32, i32)) {}
+
+    /// The screen contents as of the last `flush()`, for the displays made
+    /// of pixels.
+    fn snapshot(&self) -> Option<Snapshot> {
+        None
+    }
+}
+
+/// Several displays driven together, each showing only the screens it's
+/// configured for.
+struct MultiDisplay {
+    displays: Vec<(Box<dyn DisplayBackend>, Vec<Screen>)>,
+    /// The screen of the latest drawing, or None if it went to all the
+    /// displays. Clearing and flushing go to the same displays, so that the
+    /// others keep what they show.
+    screen: Option<Screen>,
+}
+
+impl MultiDisplay {
+    /// Run `f` on every display, or on the ones showing `screen`. All the
+    /// displays are tried even if some of them fail, and the first error is
+    /// returned.
+    fn each(
+        &mut self,
+        screen: Option<Screen>,
+        mut f: impl FnMut(&mut dyn DisplayBackend) -> Result<()>,
+    ) -> Result<()> {
+        let mut result = Ok(());
+        for (disp, screens) in self.displays.iter_mut() {
+            if screen.is_none_or(|screen| screens.contains(&screen)) {
+                let r = f(disp.as_mut());
+                if result.is_ok() {
+                    result = r;
+                }
+            }
+        }
+        result
+    }
+
+    /// Draw on the displays showing `screen`, or on all of them.
+    fn draw(
+        &mut self,
+        screen: Option<Screen>,
+        f: impl FnMut(&mut dyn DisplayBackend) -> Result<()>,
+    ) -> Result<()> {
+        self.screen = screen;
+        self.each(screen, f)
+    }
+}
+
+impl DisplayBackend for MultiDisplay {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        self.draw(Some(Screen::Timer), |d| d.draw_timer(timer))
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        self.draw(Some(Screen::Idle), |d| d.draw_idle(idle))
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.draw(Some(Screen::Idle), |d| d.draw_info(info))
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.draw(Some(Screen::Summary), |d| d.draw_summary(summary))
+    }
+
+    fn draw_animation(&mut self, frame: &Frame) -> Result<()> {
+        self.draw(Some(Screen::Idle), |d| d.draw_animation(frame))
+    }
+
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()> {
+        self.draw(None, |d| d.draw_message(lines))
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.each(self.screen, |d| d.clear())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.each(self.screen, |d| d.flush())
+    }
+
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        self.each(None, |d| d.set_brightness(brightness))
+    }
+
+    fn set_shift(&mut self, shift: (i32, i32)) {
+        for (disp, _) in self.displays.iter_mut() {
+            disp.set_shift(shift);
+        }
+    }
+
+    /// The snapshot of the first display made of pixels.
+    fn snapshot(&self) -> Option<Snapshot> {
+        self.displays.iter().find_map(|(disp, _)| disp.snapshot())
+    }
+}
+
+/// Publishes a snapshot of the screen every time the display is flushed, and
+/// whether the flush worked.
+pub struct Mirror {
+    inner: Box<dyn DisplayBackend>,
+    screen: watch::Sender<Option<Snapshot>>,
+    health: Arc<Health>,
+}
+
+impl Mirror {
+    pub fn new(
+        inner: Box<dyn DisplayBackend>,
+        screen: watch::Sender<Option<Snapshot>>,
+        health: Arc<Health>,
+    ) -> Self {
+        Self {
+            inner,
+            screen,
+            health,
+        }
+    }
+}
+
+impl DisplayBackend for Mirror {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        self.inner.draw_timer(timer)
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        self.inner.draw_idle(idle)
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.inner.draw_info(info)
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.inner.draw_summary(summary)
+    }
+
+    fn draw_animation(&mut self, frame: &Frame) -> Result<()> {
+        self.inner.draw_animation(frame)
+    }
+
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()> {
+        self.inner.draw_message(lines)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.inner.clear()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let flushed = self.inner.flush();
+        self.health.display_flushed(flushed.is_ok());
+        flushed?;
+        // The segment and character displays don't have snapshots.
+        if let Some(snapshot) = self.inner.snapshot() {
+            self.screen.send_replace(Some(snapshot));
+        }
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        self.inner.set_brightness(brightness)
+    }
+
+    fn set_shift(&mut self, shift: (i32, i32)) {
+        self.inner.set_shift(shift)
+    }
+
+    fn snapshot(&self) -> Option<Snapshot> {
+        self.inner.snapshot()
+    }
+}
+
+/// Offsets the screen contents cycle through with burn-in protection.
+const SHIFT_PATTERN: [(i32, i32); 6] = [(0, 0), (1, 0), (2, 0), (2, 1), (1, 1), (0, 1)];
+
+/// How often the screen contents are moved.
+const SHIFT_PERIOD: Duration = Duration::from_secs(60);
+
+/// Burn-in protection for OLED displays: the contents are moved around a
+/// little every minute, and the static labels are hidden every third
+/// minute.
+pub struct BurnInProtection {
+    started: Instant,
+}
+
+impl BurnInProtection {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+        }
+    }
+
+    fn period(&self) -> usize {
+        (self.started.elapsed().as_secs() / SHIFT_PERIOD.as_secs()) as usize
+    }
+
+    pub fn shift(&self) -> (i32, i32) {
+        SHIFT_PATTERN[self.period() % SHIFT_PATTERN.len()]
+    }
+
+    pub fn show_labels(&self) -> bool {
+        self.period() % 3 != 2
+    }
+}
+
+/// Direction a temperature has been going recently.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trend {
+    Rising,
+    Falling,
+    Steady,
+}
+
+/// How far back the temperature trend is followed.
+const TREND_WINDOW: Duration = Duration::from_secs(30);
+
+/// Temperature readings from the last `TREND_WINDOW`, for telling which way
+/// the temperature is going.
+pub struct TrendTracker {
+    samples: VecDeque<(Instant, i64)>,
+}
+
+impl TrendTracker {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Add a new reading, and return the trend compared to the oldest
+    /// reading within the window.
+    pub fn update(&mut self, temperature: i64) -> Trend {
+        let now = Instant::now();
+        while let Some(&(time, _)) = self.samples.front() {
+            if now.duration_since(time) <= TREND_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, temperature));
+
+        let (_, oldest) = self.samples[0];
+        match temperature.cmp(&oldest) {
+            Ordering::Greater => Trend::Rising,
+            Ordering::Less => Trend::Falling,
+            Ordering::Equal => Trend::Steady,
+        }
+    }
+}
+
+/// Pre-infusion time and the extraction time after it, in whole seconds.
+fn phases_text(preinfusion: Duration, elapsed: Duration, separator: &str) -> String {
+    let extraction = elapsed.saturating_sub(preinfusion);
+    format!(
+        "{}{}{}",
+        preinfusion.as_secs(),
+        separator,
+        extraction.as_secs()
+    )
+}
+
+/// Progress of the steam boiler heating up from the `cold` temperature to
+/// the `target`, or None if it isn't heating up.
+pub fn heat_up_progress(cold: i64, current: i64, target: i64) -> Option<f32> {
+    if current >= target || cold >= target {
+        return None;
+    }
+    Some((current - cold).max(0) as f32 / (target - cold) as f32)
+}
+
+/// Contrast register value for displays which are dimmed by adjusting the
+/// contrast.
+fn contrast(brightness: Brightness) -> u8 {
+    match brightness {
+        Brightness::Dimmest => 0x00,
+        Brightness::Dim => 0x2f,
+        Brightness::Normal => 0x5f,
+        Brightness::Bright => 0x9f,
+        Brightness::Brightest => 0xff,
+    }
+}
+
+/// Returns true if a display whose layouts only work in landscape is
+/// mounted upside down.
+fn upside_down(rotation: Rotation) -> Result<bool> {
+    match rotation {
+        Rotation::Rotate0 => Ok(false),
+        Rotation::Rotate180 => Ok(true),
+        _ => Err("the layouts of this display can only be rotated by 0 or 180 degrees".into()),
+    }
+}
+
+/// Backend for running without a display.
+pub struct NullDisplay;
+
+impl DisplayBackend for NullDisplay {
+    fn draw_timer(&mut self, _timer: &Timer) -> Result<()> {
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, _idle: &Idle) -> Result<()> {
+        Ok(())
+    }
+
+    fn draw_info(&mut self, _info: &Info) -> Result<()> {
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, _summary: &Summary) -> Result<()> {
+        Ok(())
+    }
+
+    fn draw_message(&mut self, _lines: &[&str]) -> Result<()> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, _brightness: Brightness) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Open the display selected in the configuration.
+pub fn open(config: &DisplayConfig) -> Result<Box<dyn DisplayBackend>> {
+    if !config.enabled {
+        return Ok(Box::new(NullDisplay));
+    }
+
+    // The segment and character displays can't be rotated.
+    if matches!(
+        config.driver,
+        Driver::Max7219 | Driver::Tm1637 | Driver::Hd44780
+    ) && config.rotation != Rotation::Rotate0
+    {
+        return Err(format!("{:?} displays can't be rotated", config.driver).into());
+    }
+
+    Ok(match (config.driver, config.interface) {
+        (Driver::Ssd1306, Interface::I2c) => ssd1306::open_i2c(config)?,
+        (Driver::Ssd1306, Interface::Spi) => ssd1306::open_spi(config)?,
+        (Driver::Sh1106, Interface::I2c) => Box::new(sh1106::Sh1106Display::open_i2c(config)?),
+        (Driver::Sh1106, Interface::Spi) => Box::new(sh1106::Sh1106Display::open_spi(config)?),
+        (Driver::Ssd1309, Interface::I2c) => Box::new(ssd1309::Ssd1309Display::open_i2c(config)?),
+        (Driver::Ssd1309, Interface::Spi) => Box::new(ssd1309::Ssd1309Display::open_spi(config)?),
+        (Driver::Max7219, Interface::Spi) => Box::new(max7219::Max7219Display::open_spi(config)?),
+        // The TM1637 is connected directly to two GPIO pins.
+        (Driver::Tm1637, _) => Box::new(tm1637::Tm1637Display::open(config)?),
+        (Driver::St7789, Interface::Spi) => Box::new(st7789::open_spi(config)?),
+        (Driver::St7735, Interface::Spi) => Box::new(st7735::open_spi(config)?),
+        (Driver::Hd44780, Interface::I2c) => Box::new(hd44780::Hd44780Display::open_i2c(config)?),
+        (driver, interface) => {
+            return Err(format!(
+                "{:?} displays can't be connected over {:?}",
+                driver, interface
+            )
+            .into())
+        }
+    })
+}
+
+/// Open all the displays, which are then drawn together.
+pub fn open_all<'a>(
+    configs: impl IntoIterator<Item = &'a DisplayConfig>,
+) -> Result<Box<dyn DisplayBackend>> {
+    let mut displays = Vec::new();
+    for config in configs {
+        displays.push((open(config)?, config.screens.clone()));
+    }
+    Ok(Box::new(MultiDisplay {
+        displays,
+        screen: None,
+    }))
+}
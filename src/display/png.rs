@@ -0,0 +1,49 @@
+//! Minimal PNG encoder for the snapshots of the screen.
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encode an image given as 8-bit RGB pixels, row by row.
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, truecolor, no interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Every row starts with the filter type, which is always "none" here.
+    let row_length = 3 * width as usize;
+    let mut raw = Vec::with_capacity((row_length + 1) * height as usize);
+    for row in rgb.chunks(row_length.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &compress_to_vec_zlib(&raw, 6));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
@@ -0,0 +1,121 @@
+//! Seven segment digits drawn at any size, for the jumbo layout.
+
+use embedded_graphics::{
+    pixelcolor::PixelColor, prelude::*, primitives::Rectangle, style::PrimitiveStyle,
+};
+
+// Segments a-g of the digits 0-9, with a in the lowest bit.
+const DIGIT_SEGMENTS: [u8; 10] = [0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f];
+
+fn fill<C: PixelColor, D: DrawTarget<C>>(
+    target: &mut D,
+    top_left: Point,
+    bottom_right: Point,
+    color: C,
+) -> Result<(), D::Error> {
+    Rectangle::new(top_left, bottom_right)
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(target)
+}
+
+/// Draw a digit of the given size with segments `thickness` pixels wide.
+fn draw_digit<C: PixelColor, D: DrawTarget<C>>(
+    target: &mut D,
+    digit: u32,
+    top_left: Point,
+    size: Size,
+    thickness: i32,
+    color: C,
+) -> Result<(), D::Error> {
+    let (w, h, t) = (size.width as i32, size.height as i32, thickness);
+    let middle = (h - t) / 2;
+
+    // The corners are left empty, which keeps the segments apart.
+    let segments = [
+        (Point::new(t, 0), Point::new(w - t - 1, t - 1)),
+        (Point::new(w - t, t), Point::new(w - 1, middle - 1)),
+        (Point::new(w - t, middle + t), Point::new(w - 1, h - t - 1)),
+        (Point::new(t, h - t), Point::new(w - t - 1, h - 1)),
+        (Point::new(0, middle + t), Point::new(t - 1, h - t - 1)),
+        (Point::new(0, t), Point::new(t - 1, middle - 1)),
+        (Point::new(t, middle), Point::new(w - t - 1, middle + t - 1)),
+    ];
+
+    let lit = DIGIT_SEGMENTS[(digit % 10) as usize];
+    for (i, (start, end)) in segments.iter().enumerate() {
+        if lit & (1 << i) != 0 {
+            fill(target, top_left + *start, top_left + *end, color)?;
+        }
+    }
+    Ok(())
+}
+
+/// Draw the digits side by side as big as they fit on the screen, centered
+/// horizontally. `None` leaves the place of a digit empty. With
+/// `decimal_point` a decimal point is drawn before the last digit.
+pub fn draw_digits<C: PixelColor, D: DrawTarget<C>>(
+    target: &mut D,
+    digits: &[Option<u32>],
+    decimal_point: bool,
+    color: C,
+) -> Result<(), D::Error> {
+    let screen = target.size();
+    let count = digits.len() as i32;
+    let height = screen.height as i32;
+    let gap = (screen.width as i32 / 32).max(1);
+
+    // The decimal point is a square as wide as the segments.
+    let thickness = (height / 8).max(1);
+    let point_space = if decimal_point { thickness + gap } else { 0 };
+    let width = ((screen.width as i32 - (count - 1) * gap - point_space) / count)
+        .min(height * 3 / 4)
+        .max(1);
+    let thickness = thickness.min(width / 4).max(1);
+
+    let total = count * width + (count - 1) * gap + point_space;
+    let mut x = (screen.width as i32 - total) / 2;
+    let size = Size::new(width as u32, height as u32);
+
+    for (i, digit) in digits.iter().enumerate() {
+        if decimal_point && i + 1 == digits.len() {
+            let top_left = Point::new(x, height - thickness);
+            let bottom_right = top_left + Point::new(thickness - 1, thickness - 1);
+            fill(target, top_left, bottom_right, color)?;
+            x += point_space;
+        }
+        if let Some(digit) = digit {
+            draw_digit(target, *digit, Point::new(x, 0), size, thickness, color)?;
+        }
+        x += width + gap;
+    }
+    Ok(())
+}
+
+/// Digits of the elapsed time in the jumbo layout: the seconds, and the
+/// tenths if they are shown. Past 99 seconds the third digit replaces the
+/// tenths.
+pub fn timer_digits(seconds: u32, tenths: Option<u32>) -> Vec<Option<u32>> {
+    if seconds >= 100 {
+        return vec![
+            Some(seconds / 100 % 10),
+            Some(seconds / 10 % 10),
+            Some(seconds % 10),
+        ];
+    }
+    let mut digits = vec![
+        Some(seconds / 10 % 10).filter(|_| seconds >= 10),
+        Some(seconds % 10),
+    ];
+    digits.extend(tenths.map(Some));
+    digits
+}
+
+/// Digits of a three digit temperature in the jumbo layout, without the
+/// leading zeros.
+pub fn temperature_digits(temperature: u32) -> Vec<Option<u32>> {
+    vec![
+        Some(temperature / 100 % 10).filter(|_| temperature >= 100),
+        Some(temperature / 10 % 10).filter(|_| temperature >= 10),
+        Some(temperature % 10),
+    ]
+}
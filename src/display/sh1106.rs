@@ -0,0 +1,220 @@
+use ::sh1106::interface::DisplayInterface;
+use ::sh1106::prelude::{DisplayRotation, DisplaySize, GraphicsMode, I2cInterface, SpiInterface};
+use ::sh1106::{Builder, NoOutputPin};
+use linux_embedded_hal::{gpio_cdev, CdevPin, Delay, I2cdev, Spidev};
+
+use std::fmt::Debug;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use super::font::DigitFont;
+use super::graphics::{MonoFrame, Shifted};
+use super::{
+    contrast, graphics, hal, upside_down, DisplayBackend, Frame, Idle, Info, Result, Snapshot,
+    Summary, Timer,
+};
+use crate::config::{Brightness, DisplayConfig};
+
+fn display_error<E: Debug>(e: E) -> Box<dyn std::error::Error + Send + Sync> {
+    format!("display error: {:?}", e).into()
+}
+
+fn display_size(config: &DisplayConfig) -> Result<DisplaySize> {
+    match config.size() {
+        (128, 64) => Ok(DisplaySize::Display128x64),
+        (128, 32) => Ok(DisplaySize::Display128x32),
+        (width, height) => Err(format!(
+            "SH1106 displays of size {}x{} aren't supported",
+            width, height
+        )
+        .into()),
+    }
+}
+
+fn display_rotation(config: &DisplayConfig) -> Result<DisplayRotation> {
+    Ok(if upside_down(config.rotation)? {
+        DisplayRotation::Rotate180
+    } else {
+        DisplayRotation::Rotate0
+    })
+}
+
+/// SH1106 OLED display connected to the I2C or SPI bus. The SH1106 has RAM
+/// for 132 columns, and the driver takes care of centering the 128 visible
+/// ones.
+pub struct Sh1106Display<DI: DisplayInterface> {
+    disp: GraphicsMode<DI>,
+    // The reset line needs to be held high while the display is in use.
+    _reset: Option<CdevPin>,
+    frame: MonoFrame,
+    // What was sent to the display on the last flush.
+    shown: MonoFrame,
+    shift: Point,
+    font: DigitFont,
+}
+
+impl<DI: DisplayInterface> Sh1106Display<DI>
+where
+    DI::Error: Debug,
+{
+    fn new(
+        mut disp: GraphicsMode<DI>,
+        mut reset: Option<CdevPin>,
+        config: &DisplayConfig,
+    ) -> Result<Self> {
+        if let Some(reset) = reset.as_mut() {
+            disp.reset(reset, &mut Delay)
+                .map_err(|e| format!("display reset failed: {:?}", e))?;
+        }
+
+        disp.init().map_err(display_error)?;
+        disp.set_contrast(contrast(config.brightness))
+            .map_err(display_error)?;
+        disp.flush().map_err(display_error)?;
+
+        Ok(Self {
+            frame: MonoFrame::new(DrawTarget::<BinaryColor>::size(&disp)),
+            shown: MonoFrame::new(DrawTarget::<BinaryColor>::size(&disp)),
+            disp,
+            _reset: reset,
+            shift: Point::zero(),
+            font: DigitFont::open(config)?,
+        })
+    }
+
+    /// Send the pixels which changed since the last flush to the display,
+    /// skipping the transfer if nothing changed.
+    fn send_changes(&mut self) -> Result<()> {
+        let changed = self
+            .frame
+            .draw_changes(&mut self.shown, &mut self.disp)
+            .map_err(display_error)?;
+        if changed {
+            self.disp.flush().map_err(display_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sh1106Display<I2cInterface<I2cdev>> {
+    pub fn open_i2c(config: &DisplayConfig) -> Result<Self> {
+        let i2c = I2cdev::new(&config.i2c_bus)
+            .map_err(|e| format!("failed to open {}: {}", config.i2c_bus, e))?;
+
+        let disp = Builder::new()
+            .with_size(display_size(config)?)
+            .with_rotation(display_rotation(config)?)
+            .with_i2c_addr(config.i2c_address)
+            .connect_i2c(i2c)
+            .into();
+
+        Self::new(disp, None, config)
+    }
+}
+
+impl Sh1106Display<SpiInterface<Spidev, CdevPin, NoOutputPin<gpio_cdev::errors::Error>>> {
+    pub fn open_spi(config: &DisplayConfig) -> Result<Self> {
+        let spi = hal::open_spi(&config.spi_device, config.spi_speed_hz)?;
+        let dc = hal::output_pin(&config.gpio_chip, config.dc_pin, 0)?;
+        let reset = match config.reset_pin {
+            Some(pin) => Some(hal::output_pin(&config.gpio_chip, pin, 1)?),
+            None => None,
+        };
+
+        // The chip select is handled by the kernel SPI driver.
+        let disp = Builder::new()
+            .with_size(display_size(config)?)
+            .with_rotation(display_rotation(config)?)
+            .connect_spi(spi, dc, NoOutputPin::new())
+            .into();
+
+        Self::new(disp, reset, config)
+    }
+}
+
+impl<DI: DisplayInterface + Send> DisplayBackend for Sh1106Display<DI>
+where
+    DI::Error: Debug,
+{
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_timer(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            timer,
+        );
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_idle(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            idle,
+        );
+        Ok(())
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_info(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            info,
+        );
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) =
+            graphics::draw_summary(&mut Shifted::new(&mut self.frame, self.shift), summary);
+        Ok(())
+    }
+
+    fn draw_animation(&mut self, frame: &Frame) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = frame.draw_centered(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            BinaryColor::On,
+        );
+        Ok(())
+    }
+
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_message(&mut Shifted::new(&mut self.frame, self.shift), lines);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.frame.clear();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let result = self.send_changes();
+        if result.is_err() {
+            // Nobody knows what the display shows now, so start over from a
+            // blank screen and send everything the next time.
+            self.disp.clear();
+            self.shown.clear();
+        }
+        result
+    }
+
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        self.disp
+            .set_contrast(contrast(brightness))
+            .map_err(display_error)
+    }
+
+    fn set_shift(&mut self, (x, y): (i32, i32)) {
+        self.shift = Point::new(x, y);
+    }
+
+    fn snapshot(&self) -> Option<Snapshot> {
+        Some(self.frame.snapshot())
+    }
+}
@@ -0,0 +1,235 @@
+use ::ssd1306::displaysize::{DisplaySize, DisplaySize128x32};
+use ::ssd1306::prelude::{DisplayRotation, SPIInterfaceNoCS};
+use ::ssd1306::{mode::displaymode::DisplayModeTrait, mode::GraphicsMode, Builder, I2CDIBuilder};
+use display_interface::WriteOnlyDataCommand;
+use linux_embedded_hal::{CdevPin, Delay, I2cdev};
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use super::font::DigitFont;
+use super::graphics::{MonoFrame, Shifted};
+use super::{
+    graphics, hal, upside_down, DisplayBackend, Frame, Idle, Info, Result, Snapshot, Summary, Timer,
+};
+use crate::config::{Brightness, DisplayConfig};
+
+impl From<Brightness> for ::ssd1306::prelude::Brightness {
+    fn from(b: Brightness) -> Self {
+        match b {
+            Brightness::Dimmest => Self::DIMMEST,
+            Brightness::Dim => Self::DIM,
+            Brightness::Normal => Self::NORMAL,
+            Brightness::Bright => Self::BRIGHT,
+            Brightness::Brightest => Self::BRIGHTEST,
+        }
+    }
+}
+
+fn display_error(e: display_interface::DisplayError) -> Box<dyn std::error::Error + Send + Sync> {
+    format!("display error: {:?}", e).into()
+}
+
+/// SSD1306 OLED display connected to the I2C or SPI bus.
+pub struct Ssd1306Display<DI, DSIZE: DisplaySize> {
+    disp: GraphicsMode<DI, DSIZE>,
+    // The reset line needs to be held high while the display is in use.
+    _reset: Option<CdevPin>,
+    frame: MonoFrame,
+    // What was sent to the display on the last flush.
+    shown: MonoFrame,
+    shift: Point,
+    font: DigitFont,
+}
+
+impl<DI: WriteOnlyDataCommand, DSIZE: DisplaySize> Ssd1306Display<DI, DSIZE> {
+    fn new(
+        mut disp: GraphicsMode<DI, DSIZE>,
+        mut reset: Option<CdevPin>,
+        config: &DisplayConfig,
+    ) -> Result<Self> {
+        if let Some(reset) = reset.as_mut() {
+            disp.reset(reset, &mut Delay)
+                .map_err(|e| format!("display reset failed: {:?}", e))?;
+        }
+
+        disp.init().map_err(display_error)?;
+        disp.set_brightness(config.brightness.into())
+            .map_err(display_error)?;
+        disp.flush().map_err(display_error)?;
+
+        Ok(Self {
+            frame: MonoFrame::new(DrawTarget::<BinaryColor>::size(&disp)),
+            shown: MonoFrame::new(DrawTarget::<BinaryColor>::size(&disp)),
+            disp,
+            _reset: reset,
+            shift: Point::zero(),
+            font: DigitFont::open(config)?,
+        })
+    }
+
+    /// Send the pixels which changed since the last flush to the display,
+    /// skipping the transfer if nothing changed.
+    fn send_changes(&mut self) -> Result<()> {
+        let changed = self
+            .frame
+            .draw_changes(&mut self.shown, &mut self.disp)
+            .map_err(display_error)?;
+        if changed {
+            self.disp.flush().map_err(display_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The driver needs to know the size of the display at compile time, so
+/// pick the right variant for the configured size.
+fn open<DI: WriteOnlyDataCommand + Send + 'static>(
+    interface: DI,
+    reset: Option<CdevPin>,
+    config: &DisplayConfig,
+) -> Result<Box<dyn DisplayBackend>> {
+    let rotation = if upside_down(config.rotation)? {
+        DisplayRotation::Rotate180
+    } else {
+        DisplayRotation::Rotate0
+    };
+
+    Ok(match config.size() {
+        (128, 64) => Box::new(Ssd1306Display::new(
+            Builder::new()
+                .with_rotation(rotation)
+                .connect(interface)
+                .into(),
+            reset,
+            config,
+        )?),
+        (128, 32) => Box::new(Ssd1306Display::new(
+            Builder::new()
+                .size(DisplaySize128x32)
+                .with_rotation(rotation)
+                .connect(interface)
+                .into(),
+            reset,
+            config,
+        )?),
+        (width, height) => {
+            return Err(format!(
+                "SSD1306 displays of size {}x{} aren't supported",
+                width, height
+            )
+            .into())
+        }
+    })
+}
+
+pub fn open_i2c(config: &DisplayConfig) -> Result<Box<dyn DisplayBackend>> {
+    let i2c = I2cdev::new(&config.i2c_bus)
+        .map_err(|e| format!("failed to open {}: {}", config.i2c_bus, e))?;
+
+    let interface = I2CDIBuilder::new()
+        .with_i2c_addr(config.i2c_address)
+        .init(i2c);
+
+    open(interface, None, config)
+}
+
+pub fn open_spi(config: &DisplayConfig) -> Result<Box<dyn DisplayBackend>> {
+    let spi = hal::open_spi(&config.spi_device, config.spi_speed_hz)?;
+    let dc = hal::output_pin(&config.gpio_chip, config.dc_pin, 0)?;
+    let reset = match config.reset_pin {
+        Some(pin) => Some(hal::output_pin(&config.gpio_chip, pin, 1)?),
+        None => None,
+    };
+
+    let interface = SPIInterfaceNoCS::new(spi, dc);
+
+    open(interface, reset, config)
+}
+
+impl<DI, DSIZE> DisplayBackend for Ssd1306Display<DI, DSIZE>
+where
+    DI: WriteOnlyDataCommand + Send,
+    DSIZE: DisplaySize + Send,
+{
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_timer(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            timer,
+        );
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_idle(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            idle,
+        );
+        Ok(())
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_info(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            info,
+        );
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) =
+            graphics::draw_summary(&mut Shifted::new(&mut self.frame, self.shift), summary);
+        Ok(())
+    }
+
+    fn draw_animation(&mut self, frame: &Frame) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = frame.draw_centered(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            BinaryColor::On,
+        );
+        Ok(())
+    }
+
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_message(&mut Shifted::new(&mut self.frame, self.shift), lines);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.frame.clear();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let result = self.send_changes();
+        if result.is_err() {
+            // Nobody knows what the display shows now, so start over from a
+            // blank screen and send everything the next time.
+            self.disp.clear();
+            self.shown.clear();
+        }
+        result
+    }
+
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        self.disp
+            .set_brightness(brightness.into())
+            .map_err(display_error)
+    }
+
+    fn set_shift(&mut self, (x, y): (i32, i32)) {
+        self.shift = Point::new(x, y);
+    }
+
+    fn snapshot(&self) -> Option<Snapshot> {
+        Some(self.frame.snapshot())
+    }
+}
@@ -0,0 +1,205 @@
+use ::ssd1309::prelude::DisplayRotation;
+use ::ssd1309::{mode::GraphicsMode, Builder};
+use display_interface::WriteOnlyDataCommand;
+use display_interface_i2c::I2CInterface;
+use display_interface_spi::SPIInterfaceNoCS;
+use linux_embedded_hal::{CdevPin, Delay, I2cdev, Spidev};
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use super::font::DigitFont;
+use super::graphics::{MonoFrame, Shifted};
+use super::{
+    contrast, graphics, hal, upside_down, DisplayBackend, Frame, Idle, Info, Result, Snapshot,
+    Summary, Timer,
+};
+use crate::config::{Brightness, DisplayConfig};
+
+fn display_error(e: display_interface::DisplayError) -> Box<dyn std::error::Error + Send + Sync> {
+    format!("display error: {:?}", e).into()
+}
+
+fn builder(config: &DisplayConfig) -> Result<Builder> {
+    let rotation = if upside_down(config.rotation)? {
+        DisplayRotation::Rotate180
+    } else {
+        DisplayRotation::Rotate0
+    };
+    Ok(Builder::new().with_rotation(rotation))
+}
+
+/// SSD1309 OLED display connected to the I2C or SPI bus. These are mostly
+/// found in the 2.42" modules, which have the same 128x64 resolution as the
+/// smaller SSD1306 ones, so the same layouts fill the bigger screen.
+pub struct Ssd1309Display<DI: WriteOnlyDataCommand> {
+    disp: GraphicsMode<DI>,
+    // The reset line needs to be held high while the display is in use.
+    _reset: Option<CdevPin>,
+    frame: MonoFrame,
+    // What was sent to the display on the last flush.
+    shown: MonoFrame,
+    shift: Point,
+    font: DigitFont,
+}
+
+impl<DI: WriteOnlyDataCommand> Ssd1309Display<DI> {
+    fn new(
+        mut disp: GraphicsMode<DI>,
+        mut reset: Option<CdevPin>,
+        config: &DisplayConfig,
+    ) -> Result<Self> {
+        if config.size() != (128, 64) {
+            let (width, height) = config.size();
+            return Err(format!(
+                "SSD1309 displays of size {}x{} aren't supported",
+                width, height
+            )
+            .into());
+        }
+
+        // Unlike the SSD1306, the SSD1309 doesn't come up reliably without a
+        // reset pulse, so it should be given whenever the pin is connected.
+        if let Some(reset) = reset.as_mut() {
+            disp.reset(reset, &mut Delay)
+                .map_err(|e| format!("display reset failed: {:?}", e))?;
+        }
+
+        disp.init().map_err(display_error)?;
+        disp.set_contrast(contrast(config.brightness))
+            .map_err(display_error)?;
+        disp.flush().map_err(display_error)?;
+
+        Ok(Self {
+            frame: MonoFrame::new(DrawTarget::<BinaryColor>::size(&disp)),
+            shown: MonoFrame::new(DrawTarget::<BinaryColor>::size(&disp)),
+            disp,
+            _reset: reset,
+            shift: Point::zero(),
+            font: DigitFont::open(config)?,
+        })
+    }
+
+    /// Send the pixels which changed since the last flush to the display,
+    /// skipping the transfer if nothing changed.
+    fn send_changes(&mut self) -> Result<()> {
+        let changed = self
+            .frame
+            .draw_changes(&mut self.shown, &mut self.disp)
+            .map_err(display_error)?;
+        if changed {
+            self.disp.flush().map_err(display_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ssd1309Display<I2CInterface<I2cdev>> {
+    pub fn open_i2c(config: &DisplayConfig) -> Result<Self> {
+        let i2c = I2cdev::new(&config.i2c_bus)
+            .map_err(|e| format!("failed to open {}: {}", config.i2c_bus, e))?;
+
+        // 0x40 is the control byte announcing display data.
+        let interface = I2CInterface::new(i2c, config.i2c_address, 0x40);
+
+        Self::new(builder(config)?.connect(interface).into(), None, config)
+    }
+}
+
+impl Ssd1309Display<SPIInterfaceNoCS<Spidev, CdevPin>> {
+    pub fn open_spi(config: &DisplayConfig) -> Result<Self> {
+        let spi = hal::open_spi(&config.spi_device, config.spi_speed_hz)?;
+        let dc = hal::output_pin(&config.gpio_chip, config.dc_pin, 0)?;
+        let reset = match config.reset_pin {
+            Some(pin) => Some(hal::output_pin(&config.gpio_chip, pin, 1)?),
+            None => None,
+        };
+
+        let interface = SPIInterfaceNoCS::new(spi, dc);
+
+        Self::new(builder(config)?.connect(interface).into(), reset, config)
+    }
+}
+
+impl<DI: WriteOnlyDataCommand + Send> DisplayBackend for Ssd1309Display<DI> {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_timer(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            timer,
+        );
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_idle(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            idle,
+        );
+        Ok(())
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_info(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            &self.font,
+            info,
+        );
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) =
+            graphics::draw_summary(&mut Shifted::new(&mut self.frame, self.shift), summary);
+        Ok(())
+    }
+
+    fn draw_animation(&mut self, frame: &Frame) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = frame.draw_centered(
+            &mut Shifted::new(&mut self.frame, self.shift),
+            BinaryColor::On,
+        );
+        Ok(())
+    }
+
+    fn draw_message(&mut self, lines: &[&str]) -> Result<()> {
+        self.frame.clear();
+        let Ok(()) = graphics::draw_message(&mut Shifted::new(&mut self.frame, self.shift), lines);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.frame.clear();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let result = self.send_changes();
+        if result.is_err() {
+            // Nobody knows what the display shows now, so start over from a
+            // blank screen and send everything the next time.
+            self.disp.clear();
+            self.shown.clear();
+        }
+        result
+    }
+
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        self.disp
+            .set_contrast(contrast(brightness))
+            .map_err(display_error)
+    }
+
+    fn set_shift(&mut self, (x, y): (i32, i32)) {
+        self.shift = Point::new(x, y);
+    }
+
+    fn snapshot(&self) -> Option<Snapshot> {
+        Some(self.frame.snapshot())
+    }
+}
@@ -0,0 +1,53 @@
+use ::st7735_lcd::{Orientation, ST7735};
+use embedded_graphics::pixelcolor::Rgb565;
+use linux_embedded_hal::{CdevPin, Delay, Spidev};
+
+use super::color::{raw_pixels, ColorDisplay, Panel};
+use super::font::DigitFont;
+use super::{hal, Result};
+use crate::config::{DisplayConfig, Rotation};
+
+type Driver = ST7735<Spidev, CdevPin, CdevPin>;
+
+impl Panel for Driver {
+    fn write_frame(&mut self, width: u32, height: u32, pixels: &[Rgb565]) -> Result<()> {
+        self.set_pixels_buffered(
+            0,
+            0,
+            width as u16 - 1,
+            height as u16 - 1,
+            raw_pixels(pixels),
+        )
+        .map_err(|()| "display error".into())
+    }
+}
+
+/// Open an ST7735 color TFT display connected to the SPI bus.
+pub fn open_spi(config: &DisplayConfig) -> Result<ColorDisplay<Driver>> {
+    let (width, height) = config.size();
+    let font = DigitFont::open(config)?;
+    let spi = hal::open_spi(&config.spi_device, config.spi_speed_hz)?;
+    let dc = hal::output_pin(&config.gpio_chip, config.dc_pin, 0)?;
+    let reset = config
+        .reset_pin
+        .ok_or("the ST7735 needs the reset pin to be connected")?;
+    let reset = hal::output_pin(&config.gpio_chip, reset, 1)?;
+
+    let mut disp = ST7735::new(spi, dc, reset, true, false, width, height);
+    disp.init(&mut Delay).map_err(|()| "display init failed")?;
+
+    let orientation = match config.rotation {
+        Rotation::Rotate0 => Orientation::Portrait,
+        Rotation::Rotate90 => Orientation::Landscape,
+        Rotation::Rotate180 => Orientation::PortraitSwapped,
+        Rotation::Rotate270 => Orientation::LandscapeSwapped,
+    };
+    disp.set_orientation(&orientation)
+        .map_err(|()| "display init failed")?;
+
+    if config.rotation.is_quarter_turn() {
+        ColorDisplay::new(disp, height, width, font)
+    } else {
+        ColorDisplay::new(disp, width, height, font)
+    }
+}
@@ -0,0 +1,57 @@
+use ::st7789::{Orientation, ST7789};
+use display_interface_spi::SPIInterfaceNoCS;
+use embedded_graphics::pixelcolor::Rgb565;
+use linux_embedded_hal::{CdevPin, Delay, Spidev};
+
+use super::color::{raw_pixels, ColorDisplay, Panel};
+use super::font::DigitFont;
+use super::{hal, Result};
+use crate::config::{DisplayConfig, Rotation};
+
+type Driver = ST7789<SPIInterfaceNoCS<Spidev, CdevPin>, CdevPin>;
+
+impl Panel for Driver {
+    fn write_frame(&mut self, width: u32, height: u32, pixels: &[Rgb565]) -> Result<()> {
+        self.set_pixels(
+            0,
+            0,
+            width as u16 - 1,
+            height as u16 - 1,
+            raw_pixels(pixels),
+        )
+        .map_err(|e| format!("display error: {:?}", e).into())
+    }
+}
+
+/// Open an ST7789 color TFT display connected to the SPI bus.
+pub fn open_spi(config: &DisplayConfig) -> Result<ColorDisplay<Driver>> {
+    let (width, height) = config.size();
+    let font = DigitFont::open(config)?;
+    let spi = hal::open_spi(&config.spi_device, config.spi_speed_hz)?;
+    let dc = hal::output_pin(&config.gpio_chip, config.dc_pin, 0)?;
+    let reset = config
+        .reset_pin
+        .ok_or("the ST7789 needs the reset pin to be connected")?;
+    let reset = hal::output_pin(&config.gpio_chip, reset, 1)?;
+
+    // The chip select is handled by the kernel SPI driver.
+    let interface = SPIInterfaceNoCS::new(spi, dc);
+    let mut disp = ST7789::new(interface, reset, width as u16, height as u16);
+    disp.init(&mut Delay)
+        .map_err(|e| format!("display init failed: {:?}", e))?;
+
+    let orientation = match config.rotation {
+        Rotation::Rotate0 => Orientation::Portrait,
+        Rotation::Rotate90 => Orientation::Landscape,
+        Rotation::Rotate180 => Orientation::PortraitSwapped,
+        Rotation::Rotate270 => Orientation::LandscapeSwapped,
+    };
+    disp.set_orientation(orientation)
+        .map_err(|e| format!("display init failed: {:?}", e))?;
+
+    if config.rotation.is_quarter_turn() {
+        ColorDisplay::new(disp, height, width, font)
+    } else {
+        ColorDisplay::new(disp, width, height, font)
+    }
+}
@@ -0,0 +1,164 @@
+use ::tm1637::TM1637;
+use linux_embedded_hal::{gpio_cdev, CdevPin, Delay};
+
+use super::{contrast, hal, DisplayBackend, Idle, Info, InfoValue, Result, Summary, Timer};
+use crate::config::{Brightness, DisplayConfig};
+
+fn display_error(
+    e: ::tm1637::Error<gpio_cdev::errors::Error>,
+) -> Box<dyn std::error::Error + Send + Sync> {
+    format!("display error: {:?}", e).into()
+}
+
+/// Brightness level, 0-7.
+fn level(brightness: Brightness) -> u8 {
+    contrast(brightness) >> 5
+}
+
+// The eighth segment is the colon after the second digit on the clock
+// displays, or the decimal point of the digit on the others.
+const DOT: u8 = 0x80;
+const BLANK: u8 = 0x00;
+const DEGREES_C: u8 = 0x39;
+const DASH: u8 = 0x40;
+const DIGITS: [u8; 10] = [0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f];
+
+fn digit(n: u64) -> u8 {
+    DIGITS[(n % 10) as usize]
+}
+
+/// Segments for a number right aligned in `N` digits, with the leading
+/// zeros left blank.
+fn number<const N: usize>(n: u64) -> [u8; N] {
+    let mut segments = [BLANK; N];
+    let mut rest = n;
+    for (i, segment) in segments.iter_mut().enumerate().rev() {
+        if rest == 0 && i != N - 1 {
+            break;
+        }
+        *segment = digit(rest);
+        rest /= 10;
+    }
+    segments
+}
+
+/// TM1637 driven four digit seven-segment display, connected to two GPIO
+/// pins. The time is shown as MM:SS, or as seconds with tenths when the
+/// tenths are enabled.
+pub struct Tm1637Display {
+    clk: CdevPin,
+    dio: CdevPin,
+    delay: Delay,
+    segments: [u8; 4],
+}
+
+impl Tm1637Display {
+    pub fn open(config: &DisplayConfig) -> Result<Self> {
+        let clk = hal::output_pin(&config.gpio_chip, config.clk_pin, 1)?;
+        // The display acknowledges every byte by pulling the data line low.
+        let dio = hal::open_drain_pin(&config.gpio_chip, config.dio_pin)?;
+
+        let mut disp = Self {
+            clk,
+            dio,
+            delay: Delay,
+            segments: [BLANK; 4],
+        };
+        disp.tm1637().init().map_err(display_error)?;
+        disp.set_brightness(config.brightness)?;
+        disp.flush()?;
+
+        Ok(disp)
+    }
+
+    fn tm1637(&mut self) -> TM1637<'_, CdevPin, CdevPin, Delay> {
+        TM1637::new(&mut self.clk, &mut self.dio, &mut self.delay)
+    }
+}
+
+impl DisplayBackend for Tm1637Display {
+    fn draw_timer(&mut self, timer: &Timer) -> Result<()> {
+        let shown = timer.shown();
+        let seconds = shown.as_secs();
+
+        self.segments = if timer.tenths {
+            let [hundreds, tens, ones] = number::<3>(seconds);
+            let tenths = (shown.subsec_millis() / 100).into();
+            [hundreds, tens, ones | DOT, digit(tenths)]
+        } else {
+            let [m1, m2] = number::<2>(seconds / 60);
+            [m1, m2 | DOT, digit(seconds % 60 / 10), digit(seconds % 10)]
+        };
+        if timer.flash {
+            // Light up all the decimal points.
+            self.segments.iter_mut().for_each(|s| *s |= DOT);
+        }
+
+        Ok(())
+    }
+
+    fn draw_idle(&mut self, idle: &Idle) -> Result<()> {
+        let [a, b, c] = number::<3>(idle.hx_temperature.clamp(0, 999) as u64);
+        self.segments = [a, b, c, DEGREES_C];
+        Ok(())
+    }
+
+    fn draw_info(&mut self, info: &Info) -> Result<()> {
+        self.segments = match info.value {
+            InfoValue::LastShot(elapsed)
+            | InfoValue::AverageShot(elapsed)
+            | InfoValue::MedianShot(elapsed) => {
+                let [hundreds, tens, ones] = number::<3>(elapsed.as_secs());
+                let tenths = (elapsed.subsec_millis() / 100).into();
+                [hundreds, tens, ones | DOT, digit(tenths)]
+            }
+            InfoValue::ShotCount(count) => number::<4>(count.into()),
+            // Only the last four digits fit.
+            InfoValue::TotalShots(count) => number::<4>(count),
+            InfoValue::Clock { hour, minute } => {
+                let minute = u64::from(minute);
+                let [h1, h2] = number::<2>(hour.into());
+                [h1, h2 | DOT, digit(minute / 10), digit(minute)]
+            }
+            InfoValue::SteamDelta(delta) => {
+                let mut segments = number::<4>(delta.unsigned_abs().min(999));
+                // The minus sign goes right before the first digit.
+                if delta < 0 {
+                    if let Some(i) = segments.iter().rposition(|&s| s == BLANK) {
+                        segments[i] = DASH;
+                    }
+                }
+                segments
+            }
+        };
+        Ok(())
+    }
+
+    fn draw_summary(&mut self, summary: &Summary) -> Result<()> {
+        let [hundreds, tens, ones] = number::<3>(summary.elapsed.as_secs());
+        let tenths = (summary.elapsed.subsec_millis() / 100).into();
+        self.segments = [hundreds, tens, ones | DOT, digit(tenths)];
+        Ok(())
+    }
+
+    fn draw_message(&mut self, _lines: &[&str]) -> Result<()> {
+        self.segments = [DASH; 4];
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.segments = [BLANK; 4];
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let segments = self.segments;
+        self.tm1637().print_raw(0, &segments).map_err(display_error)
+    }
+
+    fn set_brightness(&mut self, brightness: Brightness) -> Result<()> {
+        self.tm1637()
+            .set_brightness(level(brightness))
+            .map_err(display_error)
+    }
+}
@@ -0,0 +1,86 @@
+//! Whether the serial and display loops of a machine are still going, for
+//! the health checks of a service manager.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use tokio::time::{Duration, Instant};
+
+// The display loop wakes up every minute even when nothing happens.
+const DISPLAY_LIMIT: Duration = Duration::from_secs(3 * 60);
+
+/// The time of the latest sign of life of a loop.
+struct Heartbeat(Mutex<Instant>);
+
+impl Heartbeat {
+    fn new() -> Self {
+        Self(Mutex::new(Instant::now()))
+    }
+
+    fn beat(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    fn within(&self, limit: Duration) -> bool {
+        self.0.lock().unwrap().elapsed() < limit
+    }
+}
+
+pub struct Health {
+    serial: Heartbeat,
+    /// The serial loop wakes up at least this often.
+    serial_period: Duration,
+    display: Heartbeat,
+    /// The latest status line arrived recently.
+    receiving: AtomicBool,
+    display_failed: AtomicBool,
+}
+
+impl Health {
+    pub fn new(serial_period: Duration) -> Self {
+        Self {
+            serial: Heartbeat::new(),
+            serial_period,
+            display: Heartbeat::new(),
+            receiving: AtomicBool::new(false),
+            display_failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Tell that the serial loop is going, and whether the status lines
+    /// are arriving.
+    pub fn serial_alive(&self, receiving: bool) {
+        self.serial.beat();
+        self.receiving.store(receiving, Ordering::Relaxed);
+    }
+
+    /// Tell that the display loop is going.
+    pub fn display_alive(&self) {
+        self.display.beat();
+    }
+
+    /// Record how flushing the display went.
+    pub fn display_flushed(&self, ok: bool) {
+        self.display.beat();
+        self.display_failed.store(!ok, Ordering::Relaxed);
+    }
+
+    /// Whether the serial and the display loops are going, so that the
+    /// program isn't stuck.
+    pub fn live(&self) -> (bool, bool) {
+        (
+            self.serial.within(self.serial_period * 2),
+            self.display.within(DISPLAY_LIMIT),
+        )
+    }
+
+    /// Whether the status lines are arriving and the display works.
+    pub fn ready(&self) -> (bool, bool) {
+        (
+            self.receiving.load(Ordering::Relaxed),
+            !self.display_failed.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,239 @@
+//! The last shots, kept in memory for the HTTP API.
+
+use serde::{Deserialize, Serialize};
+
+use std::{collections::VecDeque, time::Duration};
+
+use crate::config::TimerConfig;
+
+/// Whether a pump run was a shot, a cooling flush, or the steam boiler
+/// filling up.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Shot,
+    Flush,
+    Autofill,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 3] = [Kind::Shot, Kind::Flush, Kind::Autofill];
+
+    /// Tell what a pump run of `duration` was, during which the heat
+    /// exchanger temperature dropped `hx_drop` degrees. Water flowing
+    /// through the group cools the heat exchanger, so a short run which
+    /// barely cooled it filled the steam boiler instead. A run timed by hand
+    /// is a shot, as the pump wasn't followed for it.
+    pub fn classify(duration: Duration, hx_drop: i64, manual: bool, config: &TimerConfig) -> Self {
+        let autofill_time = Duration::from_secs(config.autofill_max_seconds.into());
+        let min_shot_time = Duration::from_secs(config.min_shot_seconds.into());
+        if manual {
+            Kind::Shot
+        } else if duration < autofill_time && hx_drop < config.autofill_hx_drop.into() {
+            Kind::Autofill
+        } else if duration < min_shot_time {
+            Kind::Flush
+        } else {
+            Kind::Shot
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Shot => "shot",
+            Kind::Flush => "flush",
+            Kind::Autofill => "autofill",
+        }
+    }
+}
+
+/// What the shot was made of and how it turned out, added afterwards.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShotNotes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bean: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dose_grams: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grind: Option<String>,
+    /// From 1 to 5.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+}
+
+impl ShotNotes {
+    /// Replace the notes which are set in `other`, keeping the rest.
+    pub fn merge(&mut self, other: ShotNotes) {
+        if other.bean.is_some() {
+            self.bean = other.bean;
+        }
+        if other.dose_grams.is_some() {
+            self.dose_grams = other.dose_grams;
+        }
+        if other.grind.is_some() {
+            self.grind = other.grind;
+        }
+        if other.rating.is_some() {
+            self.rating = other.rating;
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ShotRecord {
+    /// When the pump was started, in the RFC 3339 format.
+    pub started: String,
+    /// Duration of the pump run in seconds, with one decimal.
+    pub seconds: f32,
+    /// Duration of the pre-infusion in seconds, if its end was marked.
+    pub preinfusion_seconds: Option<f32>,
+    /// Time from the start to the first drip in seconds, if it was marked.
+    pub first_drip_seconds: Option<f32>,
+    pub start_hx_temperature: i64,
+    pub end_hx_temperature: i64,
+    pub kind: Kind,
+    pub notes: ShotNotes,
+}
+
+impl ShotRecord {
+    pub fn new(
+        started: chrono::DateTime<chrono::Local>,
+        duration: Duration,
+        preinfusion: Option<Duration>,
+        first_drip: Option<Duration>,
+        start_hx_temperature: i64,
+        end_hx_temperature: i64,
+        kind: Kind,
+    ) -> Self {
+        Self {
+            started: started.to_rfc3339(),
+            seconds: tenths(duration),
+            preinfusion_seconds: preinfusion.map(tenths),
+            first_drip_seconds: first_drip.map(tenths),
+            start_hx_temperature,
+            end_hx_temperature,
+            kind,
+            notes: ShotNotes::default(),
+        }
+    }
+}
+
+fn tenths(duration: Duration) -> f32 {
+    (duration.as_secs_f32() * 10.0).round() / 10.0
+}
+
+// How many of the latest shot times the statistics list.
+const LATEST_SHOTS: usize = 5;
+
+/// Statistics of the shot times in the history, leaving out the flushes and
+/// the boiler refills.
+#[derive(Serialize, Debug)]
+pub struct ShotStatistics {
+    /// Number of shots the statistics are from.
+    pub shots: usize,
+    pub average_seconds: f32,
+    pub median_seconds: f32,
+    /// Times of the latest shots, newest first.
+    pub latest_seconds: Vec<f32>,
+}
+
+/// The last pump runs, oldest first.
+#[derive(Serialize, Debug, Default)]
+#[serde(transparent)]
+pub struct ShotHistory {
+    records: VecDeque<ShotRecord>,
+}
+
+impl ShotHistory {
+    /// Add a pump run, dropping the oldest ones to keep at most `size` of
+    /// them.
+    pub fn push(&mut self, record: ShotRecord, size: usize) {
+        self.records.push_back(record);
+        while self.records.len() > size {
+            self.records.pop_front();
+        }
+    }
+
+    /// Statistics of the shots in the history, or None if there are no
+    /// shots.
+    pub fn statistics(&self) -> Option<ShotStatistics> {
+        let mut seconds: Vec<f32> = self
+            .records
+            .iter()
+            .filter(|r| r.kind == Kind::Shot)
+            .map(|r| r.seconds)
+            .collect();
+        if seconds.is_empty() {
+            return None;
+        }
+        let latest_seconds = seconds.iter().rev().take(LATEST_SHOTS).copied().collect();
+        let average = seconds.iter().sum::<f32>() / seconds.len() as f32;
+
+        seconds.sort_by(f32::total_cmp);
+        let middle = seconds.len() / 2;
+        let median = if seconds.len().is_multiple_of(2) {
+            (seconds[middle - 1] + seconds[middle]) / 2.0
+        } else {
+            seconds[middle]
+        };
+
+        Some(ShotStatistics {
+            shots: seconds.len(),
+            average_seconds: (average * 10.0).round() / 10.0,
+            median_seconds: (median * 10.0).round() / 10.0,
+            latest_seconds,
+        })
+    }
+
+    /// The latest pump run.
+    pub fn last(&self) -> Option<&ShotRecord> {
+        self.records.back()
+    }
+
+    /// The latest pump run which was a shot.
+    pub fn last_shot(&self) -> Option<&ShotRecord> {
+        self.records.iter().rev().find(|r| r.kind == Kind::Shot)
+    }
+
+    pub fn last_shot_mut(&mut self) -> Option<&mut ShotRecord> {
+        self.records.iter_mut().rev().find(|r| r.kind == Kind::Shot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TimerConfig {
+        TimerConfig {
+            min_shot_seconds: 15,
+            autofill_max_seconds: 10,
+            autofill_hx_drop: 2,
+            ..TimerConfig::default()
+        }
+    }
+
+    #[test]
+    fn pump_runs() {
+        let config = config();
+        let seconds = Duration::from_secs;
+        assert_eq!(
+            Kind::classify(seconds(5), 0, false, &config),
+            Kind::Autofill
+        );
+        assert_eq!(Kind::classify(seconds(5), 4, false, &config), Kind::Flush);
+        assert_eq!(Kind::classify(seconds(12), 0, false, &config), Kind::Flush);
+        assert_eq!(Kind::classify(seconds(28), 6, false, &config), Kind::Shot);
+    }
+
+    #[test]
+    fn manual_run_is_a_shot() {
+        // Nothing cools the heat exchanger when the timer runs without the
+        // pump.
+        let config = config();
+        assert_eq!(
+            Kind::classify(Duration::from_secs(5), 0, true, &config),
+            Kind::Shot
+        );
+    }
+}
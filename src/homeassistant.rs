@@ -0,0 +1,102 @@
+//! Home Assistant MQTT discovery, which makes the sensors of the machine show
+//! up in Home Assistant without configuring them there.
+
+use serde_json::{json, Value};
+
+use crate::config::MqttConfig;
+
+/// The discovery messages of the machine labeled `machine`, whose topics
+/// start with `topic`, as pairs of a topic and a payload.
+pub fn discovery(
+    config: &MqttConfig,
+    machine: Option<&str>,
+    topic: &str,
+    availability: &str,
+) -> Vec<(String, String)> {
+    // The ids allow only letters, digits, underscores and dashes.
+    let node_id: String = match machine {
+        Some(machine) => format!("{}_{}", config.client_id, machine),
+        None => config.client_id.clone(),
+    }
+    .chars()
+    .map(|c| {
+        if c.is_ascii_alphanumeric() || c == '-' {
+            c
+        } else {
+            '_'
+        }
+    })
+    .collect();
+    let device = json!({
+        "identifiers": [node_id],
+        "name": machine.unwrap_or("Mara X"),
+        "manufacturer": "Lelit",
+        "model": "Mara X",
+    });
+    let status_topic = format!("{}/status", topic);
+    let state_topic = format!("{}/state", topic);
+
+    let temperature = |name: &str, field: &str| {
+        json!({
+            "name": name,
+            "state_topic": status_topic,
+            "value_template": format!("{{{{ value_json.{} }}}}", field),
+            "device_class": "temperature",
+            "state_class": "measurement",
+            "unit_of_measurement": "°C",
+        })
+    };
+    let entities: Vec<(&str, &str, Value)> = vec![
+        (
+            "sensor",
+            "steam_temperature",
+            temperature("Steam temperature", "steam_temperature"),
+        ),
+        (
+            "sensor",
+            "hx_temperature",
+            temperature("Heat exchanger temperature", "hx_temperature"),
+        ),
+        (
+            "binary_sensor",
+            "heating",
+            json!({
+                "name": "Heating",
+                "state_topic": status_topic,
+                "value_template":
+                    "{{ 'ON' if value_json.heating_element_on else 'OFF' }}",
+                "device_class": "heat",
+            }),
+        ),
+        (
+            "binary_sensor",
+            "shot",
+            json!({
+                "name": "Shot in progress",
+                "state_topic": state_topic,
+                "value_template": "{{ 'ON' if value in ('flushing', 'brewing') else 'OFF' }}",
+                "device_class": "running",
+            }),
+        ),
+    ];
+
+    entities
+        .into_iter()
+        .map(|(component, object_id, mut entity)| {
+            let fields = entity.as_object_mut().unwrap();
+            fields.insert(
+                "unique_id".to_string(),
+                format!("{}_{}", node_id, object_id).into(),
+            );
+            fields.insert("availability_topic".to_string(), availability.into());
+            fields.insert("device".to_string(), device.clone());
+            (
+                format!(
+                    "{}/{}/{}/{}/config",
+                    config.discovery_prefix, component, node_id, object_id
+                ),
+                entity.to_string(),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,106 @@
+//! Metrics of the computer the timer runs on, which often sits in a warm
+//! cabinet next to the machine.
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{Counter, Gauge, Opts};
+
+use std::fs;
+
+// The temperature of the SoC in millidegrees Celsius, on a Raspberry Pi and
+// most other boards.
+const SOC_TEMPERATURE: &str = "/sys/class/thermal/thermal_zone0/temp";
+
+/// Memory and CPU use of the process and the SoC temperature, read when the
+/// metrics are. The values which can't be read are left out.
+pub struct HostMetrics {
+    resident_memory: Gauge,
+    cpu_seconds: Counter,
+    soc_temperature: Gauge,
+}
+
+impl HostMetrics {
+    /// Create the metrics with the options `opts` gives from the original
+    /// name, the conventional name and the help text.
+    pub fn new(opts: impl Fn(&str, &str, &str) -> Opts) -> Result<Self, prometheus::Error> {
+        Ok(Self {
+            resident_memory: Gauge::with_opts(opts(
+                "ProcessResidentMemory",
+                "process_resident_memory_bytes",
+                "Resident memory of the process in bytes",
+            ))?,
+            cpu_seconds: Counter::with_opts(opts(
+                "ProcessCpuSeconds",
+                "process_cpu_seconds_total",
+                "CPU time used by the process in seconds",
+            ))?,
+            soc_temperature: Gauge::with_opts(opts(
+                "SocTemperature",
+                "soc_temperature_celsius",
+                "Temperature of the SoC of the computer",
+            ))?,
+        })
+    }
+}
+
+fn sysconf(name: libc::c_int) -> Option<f64> {
+    // SAFETY: sysconf() has no preconditions.
+    let value = unsafe { libc::sysconf(name) };
+    (value > 0).then_some(value as f64)
+}
+
+fn resident_memory() -> Option<f64> {
+    // The second value is the resident set in pages.
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: f64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * sysconf(libc::_SC_PAGESIZE)?)
+}
+
+fn cpu_seconds() -> Option<f64> {
+    // The user and system times in clock ticks are the 14th and 15th
+    // values. The name of the program before them is in parentheses and can
+    // have spaces, so the values are counted from after it.
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    let mut values = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+    let user: f64 = values.next()?.parse().ok()?;
+    let system: f64 = values.next()?.parse().ok()?;
+    Some((user + system) / sysconf(libc::_SC_CLK_TCK)?)
+}
+
+fn soc_temperature() -> Option<f64> {
+    let millidegrees: f64 = fs::read_to_string(SOC_TEMPERATURE)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(millidegrees / 1000.0)
+}
+
+impl Collector for HostMetrics {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut desc = self.resident_memory.desc();
+        desc.extend(self.cpu_seconds.desc());
+        desc.extend(self.soc_temperature.desc());
+        desc
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut families = Vec::new();
+        if let Some(bytes) = resident_memory() {
+            self.resident_memory.set(bytes);
+            families.extend(self.resident_memory.collect());
+        }
+        if let Some(seconds) = cpu_seconds() {
+            let used = seconds - self.cpu_seconds.get();
+            if used > 0.0 {
+                self.cpu_seconds.inc_by(used);
+            }
+            families.extend(self.cpu_seconds.collect());
+        }
+        if let Some(celsius) = soc_temperature() {
+            self.soc_temperature.set(celsius);
+            families.extend(self.soc_temperature.collect());
+        }
+        families
+    }
+}
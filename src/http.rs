@@ -0,0 +1,496 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, Registry, TextEncoder};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, Duration, Instant};
+
+use crate::config::Config;
+use crate::display::Snapshot;
+use crate::health::Health;
+use crate::history::{ShotHistory, ShotNotes};
+use crate::protocol::MachineStatus;
+use crate::shot_clock::ShotClock;
+use crate::state::{self, StateMachine};
+use crate::websocket;
+
+// How often the live updates tell the time of a running timer, as often as
+// the display shows it.
+const TIMER_TICK: Duration = Duration::from_millis(100);
+
+// How often an idle event stream gets a comment, so that proxies don't close
+// it and a closed one is noticed.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// State shared by the HTTP request handlers.
+pub struct HttpState {
+    pub registry: Arc<Registry>,
+    pub config: Arc<watch::Sender<Config>>,
+    /// What the display showed when it was last flushed.
+    pub screen: watch::Receiver<Option<Snapshot>>,
+    /// The last shots and flushes.
+    pub history: Arc<watch::Sender<ShotHistory>>,
+    /// The latest status line from the machine.
+    pub machine: watch::Receiver<Option<MachineStatus>>,
+    /// What the timer is doing, moved on when the timer is started by hand.
+    pub state: Arc<watch::Sender<StateMachine>>,
+    pub shot_clock: Arc<Mutex<ShotClock>>,
+    /// Health of all the machines, the main one first.
+    pub health: Vec<Arc<Health>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TargetTime {
+    seconds: u32,
+}
+
+#[derive(Deserialize)]
+struct TimerRequest {
+    running: bool,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    serial: bool,
+    display: bool,
+}
+
+#[derive(Serialize)]
+struct TimerState {
+    running: bool,
+    seconds: f32,
+}
+
+/// Everything a live view of the timer needs at once.
+#[derive(Serialize)]
+struct CurrentState {
+    /// The latest status line, or null until the first one.
+    status: Option<MachineStatus>,
+    state: &'static str,
+    timer: TimerState,
+    stale: bool,
+}
+
+fn response(status: StatusCode, content_type: &str, body: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(body.into())
+        .unwrap()
+}
+
+fn json_response<T: Serialize>(value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => response(StatusCode::OK, "application/json", body),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response<Body> {
+    response(status, "text/plain", format!("{}\n", msg))
+}
+
+fn metrics(state: &HttpState) -> Response<Body> {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    match encoder.encode(&state.registry.gather(), &mut buffer) {
+        Ok(()) => response(StatusCode::OK, encoder.format_type(), buffer),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn machine_status(state: &HttpState) -> Response<Body> {
+    match *state.machine.borrow() {
+        Some(status) => json_response(&status),
+        None => error_response(StatusCode::NOT_FOUND, "no status from the machine yet"),
+    }
+}
+
+fn get_target_time(state: &HttpState) -> Response<Body> {
+    json_response(&TargetTime {
+        seconds: state.config.borrow().timer.target_seconds,
+    })
+}
+
+async fn set_target_time(state: &HttpState, req: Request<Body>) -> Response<Body> {
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let target: TargetTime = match serde_json::from_slice(&body) {
+        Ok(target) => target,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let max_seconds = state.config.borrow().timer.max_seconds;
+    if target.seconds > max_seconds {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            &format!("target time must be at most {} seconds", max_seconds),
+        );
+    }
+
+    state
+        .config
+        .send_modify(|config| config.timer.target_seconds = target.seconds);
+    println!("Target shot time set to {} seconds", target.seconds);
+
+    json_response(&target)
+}
+
+fn shots(state: &HttpState) -> Response<Body> {
+    json_response(&*state.history.borrow())
+}
+
+fn last_shot(state: &HttpState) -> Response<Body> {
+    match state.history.borrow().last_shot() {
+        Some(shot) => json_response(shot),
+        None => error_response(StatusCode::NOT_FOUND, "no shots yet"),
+    }
+}
+
+async fn add_shot_notes(state: &HttpState, req: Request<Body>) -> Response<Body> {
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let notes: ShotNotes = match serde_json::from_slice(&body) {
+        Ok(notes) => notes,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    if notes
+        .rating
+        .is_some_and(|rating| !(1..=5).contains(&rating))
+    {
+        return error_response(StatusCode::BAD_REQUEST, "rating must be from 1 to 5");
+    }
+
+    let mut shot = None;
+    state
+        .history
+        .send_if_modified(|history| match history.last_shot_mut() {
+            Some(last) => {
+                last.notes.merge(notes);
+                shot = Some(last.clone());
+                true
+            }
+            None => false,
+        });
+
+    match shot {
+        Some(shot) => {
+            println!("Notes added to the shot started at {}", shot.started);
+            json_response(&shot)
+        }
+        None => error_response(StatusCode::NOT_FOUND, "no shots yet"),
+    }
+}
+
+fn shot_statistics(state: &HttpState) -> Response<Body> {
+    match state.history.borrow().statistics() {
+        Some(statistics) => json_response(&statistics),
+        None => error_response(StatusCode::NOT_FOUND, "no shots yet"),
+    }
+}
+
+fn timer_state(clock: &ShotClock) -> TimerState {
+    TimerState {
+        running: clock.is_running(),
+        seconds: (clock.elapsed().as_secs_f32() * 10.0).round() / 10.0,
+    }
+}
+
+fn current_state(state: &HttpState) -> CurrentState {
+    let machine = *state.state.borrow();
+    let clock = *state.shot_clock.lock().unwrap();
+    CurrentState {
+        status: *state.machine.borrow(),
+        state: machine.state().name(),
+        timer: timer_state(&clock),
+        stale: machine.is_stale(),
+    }
+}
+
+/// A live update of the timer.
+struct Event {
+    /// What happened: "state", "pump_started" or "pump_stopped".
+    kind: &'static str,
+    /// The data of the event in JSON.
+    data: String,
+}
+
+/// Follows the timer for the live updates. The current state is sent
+/// whenever the status or the state changes, and every tick while the timer
+/// runs. The start of a pump run comes with the state, and its end with the
+/// record of the run, whose kind tells a shot from a flush.
+struct Events {
+    state: Arc<HttpState>,
+    machine: watch::Receiver<Option<MachineStatus>>,
+    timer: watch::Receiver<StateMachine>,
+    history: watch::Receiver<ShotHistory>,
+    running: bool,
+    /// The start time of the latest pump run sent.
+    last_run: Option<String>,
+    tick: time::Interval,
+}
+
+impl Events {
+    fn new(state: &Arc<HttpState>) -> Self {
+        let history = state.history.subscribe();
+        let last_run = history.borrow().last().map(|run| run.started.clone());
+        Self {
+            machine: state.machine.clone(),
+            timer: state.state.subscribe(),
+            running: state.state.borrow().state().is_running(),
+            history,
+            last_run,
+            tick: time::interval(TIMER_TICK),
+            state: Arc::clone(state),
+        }
+    }
+
+    fn current(&self, kind: &'static str) -> Event {
+        Event {
+            kind,
+            data: serde_json::to_string(&current_state(&self.state)).unwrap(),
+        }
+    }
+
+    async fn next(&mut self) -> Event {
+        loop {
+            tokio::select! {
+                Ok(()) = self.machine.changed() => {
+                    self.machine.borrow_and_update();
+                    return self.current("state");
+                }
+                Ok(()) = self.timer.changed() => {
+                    let running = self.timer.borrow_and_update().state().is_running();
+                    let started = running && !self.running;
+                    self.running = running;
+                    if started {
+                        self.tick.reset();
+                        return self.current("pump_started");
+                    }
+                    return self.current("state");
+                }
+                Ok(()) = self.history.changed() => {
+                    // Adding notes changes the history too.
+                    let history = self.history.borrow_and_update();
+                    if let Some(run) = history.last() {
+                        if self.last_run.as_ref() != Some(&run.started) {
+                            self.last_run = Some(run.started.clone());
+                            return Event {
+                                kind: "pump_stopped",
+                                data: serde_json::to_string(run).unwrap(),
+                            };
+                        }
+                    }
+                }
+                _ = self.tick.tick(), if self.running => return self.current("state"),
+                else => return std::future::pending().await,
+            }
+        }
+    }
+}
+
+/// Start following the timer, beginning with the current state, with the
+/// events in the text `format` makes of them. It's followed until the
+/// receiver is dropped.
+fn events(state: &Arc<HttpState>, format: fn(Event) -> String) -> mpsc::Receiver<String> {
+    let (events_tx, events_rx) = mpsc::channel(1);
+    let mut events = Events::new(state);
+    tokio::spawn(async move {
+        let mut event = events.current("state");
+        loop {
+            if events_tx.send(format(event)).await.is_err() {
+                break;
+            }
+            event = tokio::select! {
+                event = events.next() => event,
+                _ = events_tx.closed() => break,
+            };
+        }
+    });
+    events_rx
+}
+
+/// Send the live updates over a WebSocket, as JSON messages like
+/// `{"type":"state","data":{...}}`.
+fn stream(state: &Arc<HttpState>, req: Request<Body>) -> Response<Body> {
+    let response = match websocket::handshake(&req) {
+        Ok(response) => response,
+        Err((status, msg)) => return error_response(status, msg),
+    };
+    let messages = events(state, |event| {
+        format!(r#"{{"type":"{}","data":{}}}"#, event.kind, event.data)
+    });
+    tokio::spawn(async move {
+        match hyper::upgrade::on(req).await {
+            Ok(socket) => {
+                let _ = websocket::send(socket, messages).await;
+            }
+            Err(e) => println!("WebSocket upgrade failed: {}", e),
+        }
+    });
+    response
+}
+
+/// Send the live updates as server-sent events, with the type of the event
+/// in the event field and its JSON in the data field.
+fn event_stream(state: &Arc<HttpState>) -> Response<Body> {
+    let mut messages = events(state, |event| {
+        format!("event: {}\ndata: {}\n\n", event.kind, event.data)
+    });
+    let (mut body, receiver) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            let message = match time::timeout(KEEP_ALIVE, messages.recv()).await {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(_) => ": keep-alive\n\n".to_string(),
+            };
+            if body.send_data(message.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(receiver)
+        .unwrap()
+}
+
+fn get_timer(state: &HttpState) -> Response<Body> {
+    let clock = *state.shot_clock.lock().unwrap();
+    json_response(&timer_state(&clock))
+}
+
+async fn set_timer(state: &HttpState, req: Request<Body>) -> Response<Body> {
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let request: TimerRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let (clock, started) = {
+        let mut clock = state.shot_clock.lock().unwrap();
+        if !request.running && clock.is_running() && !clock.is_manual() {
+            return error_response(
+                StatusCode::CONFLICT,
+                "a shot of the pump is running, it stops with the pump",
+            );
+        }
+        let started = if request.running {
+            clock.start_manual(Instant::now())
+        } else {
+            clock.stop_manual(Instant::now());
+            false
+        };
+        (*clock, started)
+    };
+    if started {
+        println!("Timer started over HTTP");
+        state::transition(&state.state, state::Event::PumpStarted);
+    }
+
+    json_response(&timer_state(&clock))
+}
+
+fn display_png(state: &HttpState) -> Response<Body> {
+    // Encode outside of the lock, the display is updated often.
+    let snapshot = state.screen.borrow().clone();
+    match snapshot {
+        Some(snapshot) => response(StatusCode::OK, "image/png", snapshot.to_png()),
+        None => error_response(StatusCode::NOT_FOUND, "the display has no pixels"),
+    }
+}
+
+/// Answer a health check with `check` of each machine, as 200 if all of them
+/// pass and as 503 if not.
+fn health_response(state: &HttpState, check: impl Fn(&Health) -> (bool, bool)) -> Response<Body> {
+    let reports: Vec<HealthReport> = state
+        .health
+        .iter()
+        .map(|health| {
+            let (serial, display) = check(health);
+            HealthReport { serial, display }
+        })
+        .collect();
+    let mut response = json_response(&reports);
+    if !reports.iter().all(|r| r.serial && r.display) {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    response
+}
+
+async fn handle(state: Arc<HttpState>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => metrics(&state),
+        (&Method::GET, "/api/v1/status") => machine_status(&state),
+        (&Method::GET, "/api/v1/state") => json_response(&current_state(&state)),
+        (&Method::GET, "/api/v1/stream") => stream(&state, req),
+        (&Method::GET, "/api/v1/events") => event_stream(&state),
+        (&Method::GET, "/api/v1/target-time") => get_target_time(&state),
+        (&Method::PUT, "/api/v1/target-time") => set_target_time(&state, req).await,
+        (&Method::GET, "/api/v1/shots") => shots(&state),
+        (&Method::GET, "/api/v1/shots/last") => last_shot(&state),
+        (&Method::POST, "/api/v1/shots/last/notes") => add_shot_notes(&state, req).await,
+        (&Method::GET, "/api/v1/shots/stats") => shot_statistics(&state),
+        (&Method::GET, "/api/v1/timer") => get_timer(&state),
+        (&Method::PUT, "/api/v1/timer") => set_timer(&state, req).await,
+        (&Method::GET, "/display.png") => display_png(&state),
+        (&Method::GET, "/healthz") => health_response(&state, Health::live),
+        (&Method::GET, "/readyz") => health_response(&state, Health::ready),
+        (_, "/metrics")
+        | (_, "/api/v1/status")
+        | (_, "/api/v1/state")
+        | (_, "/api/v1/stream")
+        | (_, "/api/v1/events")
+        | (_, "/api/v1/target-time")
+        | (_, "/api/v1/shots")
+        | (_, "/api/v1/shots/last")
+        | (_, "/api/v1/shots/last/notes")
+        | (_, "/api/v1/shots/stats")
+        | (_, "/api/v1/timer")
+        | (_, "/display.png")
+        | (_, "/healthz")
+        | (_, "/readyz") => error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+        _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    Ok(response)
+}
+
+/// Serve the metrics and the API until `shutdown` completes.
+pub async fn serve(
+    addr: SocketAddr,
+    state: Arc<HttpState>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let state = Arc::clone(&state);
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(Arc::clone(&state), req))) }
+    });
+
+    Server::try_bind(&addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
@@ -0,0 +1,182 @@
+//! Writing the temperatures and the shots to InfluxDB, for setups which graph
+//! them with Grafana from there instead of Prometheus.
+
+use hyper::{header, Body, Client, Method, Request};
+
+use std::{error::Error, fmt::Write, time::SystemTime};
+
+use tokio::time::{self, Duration};
+
+use crate::config::InfluxDbConfig;
+use crate::history::ShotRecord;
+use crate::protocol::{MachineStatus, Mode};
+
+// How long a write can take before it's given up.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Points kept for the next write while InfluxDB can't be reached. The oldest
+// ones are dropped first.
+const MAX_PENDING: usize = 4000;
+
+/// Escape the commas, equal signs and spaces of a tag value in the line
+/// protocol.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis())
+}
+
+/// Collects the status lines and the pump runs of a machine as points of
+/// the line protocol, and writes them in batches.
+pub struct InfluxDb {
+    client: Client<hyper::client::HttpConnector>,
+    url: String,
+    token: String,
+    /// The tags of all the points, starting with a comma.
+    tags: String,
+    points: Vec<String>,
+    /// The start time of the last pump run written, so that the changes to
+    /// the notes don't write it again.
+    last_run: Option<String>,
+}
+
+impl InfluxDb {
+    /// Create a writer for the machine labeled `machine`, if the
+    /// configuration has a server.
+    pub fn new(config: &InfluxDbConfig, machine: Option<&str>) -> Option<Self> {
+        let url = config.url.as_ref()?;
+        let mut query = String::new();
+        for (name, value) in [("org", &config.org), ("bucket", &config.bucket)] {
+            write!(query, "{}={}&", name, percent_encode(value)).unwrap();
+        }
+        Some(Self {
+            client: Client::new(),
+            url: format!(
+                "{}/api/v2/write?{}precision=ms",
+                url.trim_end_matches('/'),
+                query
+            ),
+            token: config.token.clone(),
+            tags: machine
+                .map(|machine| format!(",machine={}", escape_tag(machine)))
+                .unwrap_or_default(),
+            points: Vec::new(),
+            last_run: None,
+        })
+    }
+
+    fn add(&mut self, point: String) {
+        if self.points.len() == MAX_PENDING {
+            self.points.remove(0);
+        }
+        self.points.push(point);
+    }
+
+    /// Add the values of a status line, read now.
+    pub fn add_status(&mut self, status: &MachineStatus) {
+        let mode = match status.mode {
+            Mode::Coffee => "coffee",
+            Mode::Steam => "steam",
+        };
+        let point = format!(
+            "marax_status{},mode={} steam_temperature={}i,target_steam_temperature={}i,\
+             hx_temperature={}i,countdown_boost_mode={}i,heating_element_on={},pump_on={} {}",
+            self.tags,
+            mode,
+            status.steam_temperature,
+            status.target_steam_temperature,
+            status.hx_temperature,
+            status.countdown_boost_mode,
+            status.heating_element_on,
+            status.pump_on,
+            unix_millis(SystemTime::now()),
+        );
+        self.add(point);
+    }
+
+    /// Add the pump run `record` at the time it started, unless it has been
+    /// added already.
+    pub fn add_run(&mut self, record: &ShotRecord) {
+        if self.last_run.as_ref() == Some(&record.started) {
+            return;
+        }
+        self.last_run = Some(record.started.clone());
+        let started = chrono::DateTime::parse_from_rfc3339(&record.started)
+            .map_or_else(|_| SystemTime::now(), SystemTime::from);
+        let mut fields = format!(
+            "seconds={},start_hx_temperature={}i,end_hx_temperature={}i",
+            record.seconds, record.start_hx_temperature, record.end_hx_temperature
+        );
+        if let Some(seconds) = record.preinfusion_seconds {
+            write!(fields, ",preinfusion_seconds={}", seconds).unwrap();
+        }
+        if let Some(seconds) = record.first_drip_seconds {
+            write!(fields, ",first_drip_seconds={}", seconds).unwrap();
+        }
+        let point = format!(
+            "marax_pump_run,kind={}{} {} {}",
+            record.kind.name(),
+            self.tags,
+            fields,
+            unix_millis(started)
+        );
+        self.add(point);
+    }
+
+    async fn write(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8");
+        if !self.token.is_empty() {
+            request = request.header(header::AUTHORIZATION, format!("Token {}", self.token));
+        }
+        let request = request.body(Body::from(self.points.join("\n")))?;
+        let response = time::timeout(WRITE_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| "timed out")??;
+        if !response.status().is_success() {
+            return Err(format!("InfluxDB answered {}", response.status()).into());
+        }
+        Ok(())
+    }
+
+    /// Write the collected points. If the write fails, they are kept for the
+    /// next one.
+    pub async fn flush(&mut self) {
+        if self.points.is_empty() {
+            return;
+        }
+        match self.write().await {
+            Ok(()) => self.points.clear(),
+            Err(e) => println!(
+                "Writing {} points to InfluxDB failed: {}",
+                self.points.len(),
+                e
+            ),
+        }
+    }
+}
+
+/// Percent-encode `value` for a URL query.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            write!(encoded, "%{:02X}", byte).unwrap();
+        }
+    }
+    encoded
+}
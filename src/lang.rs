@@ -0,0 +1,135 @@
+use serde::Deserialize;
+
+use std::str::FromStr;
+
+/// Status texts shown on the display in one language.
+pub struct Texts {
+    /// Banner shown once the machine has settled at the target temperature.
+    pub ready: &'static str,
+    /// Shown next to the temperature while the heating element is on.
+    pub heating: &'static str,
+    /// Heading of an error message.
+    pub error: &'static str,
+    /// Shown below an error message.
+    pub retrying: &'static str,
+    /// Shown at startup until the machine sends its status.
+    pub waiting: &'static str,
+    /// Error reading the serial port.
+    pub serial_port: &'static str,
+    /// Error when the status lines can't be parsed.
+    pub bad_data: &'static str,
+    /// Error when the status lines have stopped coming.
+    pub no_data: &'static str,
+    /// Label of the steam boiler temperature.
+    pub steam: &'static str,
+    /// Labels of the information pages.
+    pub last_shot: &'static str,
+    pub shots_today: &'static str,
+    pub total_shots: &'static str,
+    pub average_shot: &'static str,
+    pub median_shot: &'static str,
+    pub time: &'static str,
+    pub steam_delta: &'static str,
+    /// Shown before the number of the shot in the summary.
+    pub shot: &'static str,
+    /// Shown instead of the final time after a pump run too short to be a
+    /// shot.
+    pub flush: &'static str,
+}
+
+// The texts need to fit the 16 character LCDs next to the values.
+const EN: Texts = Texts {
+    ready: "READY",
+    heating: "heating",
+    error: "Error:",
+    retrying: "Retrying...",
+    waiting: "Waiting for Mara X...",
+    serial_port: "Serial port",
+    bad_data: "Bad Mara X data",
+    no_data: "No Mara X data",
+    steam: "Steam",
+    last_shot: "Last shot",
+    shots_today: "Shots today",
+    total_shots: "Total shots",
+    average_shot: "Average shot",
+    median_shot: "Median shot",
+    time: "Time",
+    steam_delta: "Steam vs target",
+    shot: "Shot",
+    flush: "Flush",
+};
+
+const FI: Texts = Texts {
+    ready: "VALMIS",
+    heating: "lämpenee",
+    error: "Virhe:",
+    retrying: "Yritetään...",
+    waiting: "Odotetaan Mara X...",
+    serial_port: "Sarjaportti",
+    bad_data: "Outoa dataa",
+    no_data: "Ei dataa",
+    steam: "Höyry",
+    last_shot: "Edellinen",
+    shots_today: "Tänään",
+    total_shots: "Yhteensä",
+    average_shot: "Keskiarvo",
+    median_shot: "Mediaani",
+    time: "Kello",
+    steam_delta: "Höyry - tavoite",
+    shot: "Kuppi",
+    flush: "Huuhtelu",
+};
+
+const DE: Texts = Texts {
+    ready: "BEREIT",
+    heating: "heizt",
+    error: "Fehler:",
+    retrying: "Neuer Versuch...",
+    waiting: "Warte auf Mara X...",
+    serial_port: "Serieller Port",
+    bad_data: "Falsche Daten",
+    no_data: "Keine Daten",
+    steam: "Dampf",
+    last_shot: "Letzter Bezug",
+    shots_today: "Bezüge heute",
+    total_shots: "Bezüge gesamt",
+    average_shot: "Mittlerer Bezug",
+    median_shot: "Median Bezug",
+    time: "Uhrzeit",
+    steam_delta: "Dampf - Soll",
+    shot: "Bezug",
+    flush: "Spülen",
+};
+
+/// Language of the texts on the display.
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Fi,
+    De,
+}
+
+impl Language {
+    pub fn texts(&self) -> &'static Texts {
+        match self {
+            Language::En => &EN,
+            Language::Fi => &FI,
+            Language::De => &DE,
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Language::En),
+            "fi" => Ok(Language::Fi),
+            "de" => Ok(Language::De),
+            _ => Err(format!("unknown language \"{}\"", s)),
+        }
+    }
+}
@@ -1,123 +1,841 @@
-use embedded_graphics::{egtext, pixelcolor::BinaryColor, prelude::*, text_style};
-use linux_embedded_hal::I2cdev;
-use ssd1306::prelude::I2CInterface;
-use ssd1306::{mode::GraphicsMode, Builder, I2CDIBuilder};
+mod check;
+mod cli;
+mod config;
+mod counter;
+mod display;
+mod health;
+mod history;
+mod homeassistant;
+mod host;
+mod http;
+mod influx;
+mod lang;
+mod menu;
+mod mqtt;
+mod otlp;
+mod parser;
+mod profile;
+mod protocol;
+mod shot_clock;
+mod simulator;
+mod source;
+mod stability;
+mod state;
+mod websocket;
+
+use chrono::Timelike;
+
+use clap::Parser;
+
+use display::DisplayBackend;
 
-use bytes::BytesMut;
 use futures::stream::StreamExt;
 
-use prometheus::{IntGauge, Opts, Registry};
-use prometheus_hyper::{RegistryFn, Server};
+use protocol::{MachineStatus, Mode, OutOfRange};
 
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::{error::Error, io, net::SocketAddr, str};
+use state::{transition, StateMachine};
 
-use tokio::sync::Notify;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Gauge, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
+
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, mpsc, watch, Notify};
 use tokio::time;
-use tokio_serial::SerialPortBuilderExt;
-use tokio_util::codec::{Decoder, Encoder};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-struct SevenSegmentFont;
+/// Function for registering the metrics to a registry.
+pub type RegistryFn = Box<dyn FnOnce(&Registry) -> Result<(), prometheus::Error>>;
+
+/// Brightness the display should have right now, following the night
+/// dimming schedule.
+fn current_brightness(config: &config::DisplayConfig) -> config::Brightness {
+    let now = chrono::Local::now();
+    config.brightness_at(config::TimeOfDay::new(now.hour(), now.minute()))
+}
+
+// Number of unparseable lines in a row after which the data from the Mara X
+// is reported as bad on the display.
+const PARSE_FAILURE_LIMIT: u32 = 5;
+
+// How long the time of the first drip stays on the display after it's
+// marked.
+const FIRST_DRIP_SHOW_TIME: time::Duration = time::Duration::from_secs(3);
+
+// How long the display flashes after the target shot time is reached.
+const TARGET_FLASH_TIME: time::Duration = time::Duration::from_secs(2);
+
+// How long the firmware version is shown after the first status line.
+const FIRMWARE_SCREEN: time::Duration = time::Duration::from_secs(2);
+
+// How many status updates are kept for the consumers which are behind. The
+// Mara X sends about one line a second.
+const STATUS_UPDATES: usize = 16;
+
+/// Log a display error instead of giving up, so that a glitch on the bus
+/// doesn't stop the timer. The next update of the screen tries again.
+fn report(result: display::Result<()>) {
+    if let Err(e) = result {
+        println!("Display error: {}", e);
+    }
+}
+
+/// Shots pulled since the start, for the information pages.
+struct ShotStats {
+    last: Option<time::Duration>,
+    today: u32,
+    day: chrono::NaiveDate,
+}
+
+impl ShotStats {
+    fn new() -> Self {
+        Self {
+            last: None,
+            today: 0,
+            day: chrono::Local::now().date_naive(),
+        }
+    }
+
+    /// Start counting from zero when the day changes.
+    fn roll_over(&mut self) {
+        let today = chrono::Local::now().date_naive();
+        if today != self.day {
+            self.day = today;
+            self.today = 0;
+        }
+    }
+
+    /// Count a shot, and return its number today.
+    fn record(&mut self, elapsed: time::Duration) -> u32 {
+        self.roll_over();
+        self.last = Some(elapsed);
+        self.today += 1;
+        self.today
+    }
+
+    /// Contents of an information page about the shots, or None for the
+    /// other pages.
+    fn info(&mut self, page: config::Page) -> Option<display::InfoValue> {
+        self.roll_over();
+        match page {
+            config::Page::Temperatures
+            | config::Page::SteamDelta
+            | config::Page::TotalShots
+            | config::Page::AverageShot
+            | config::Page::MedianShot
+            | config::Page::Animation => None,
+            config::Page::LastShot => self.last.map(display::InfoValue::LastShot),
+            config::Page::ShotCount => Some(display::InfoValue::ShotCount(self.today)),
+            config::Page::Clock => {
+                let now = chrono::Local::now();
+                Some(display::InfoValue::Clock {
+                    hour: now.hour(),
+                    minute: now.minute(),
+                })
+            }
+        }
+    }
+}
+
+/// Time between the frames of the idle animation.
+fn frame_interval(fps: u32) -> time::Duration {
+    time::Duration::from_secs(1) / fps.max(1)
+}
+
+/// Wait until a new pump run is started or the program exits.
+async fn until_busy(timer: &mut watch::Receiver<StateMachine>) {
+    // The machine lives as long as the program, so the wait can't fail.
+    let _ = timer.wait_for(StateMachine::is_busy).await;
+}
+
+/// Wait for `duration`, or until a new shot is started or the program
+/// exits. Returns true if the wait was cut short.
+async fn hold_screen(duration: time::Duration, timer: &mut watch::Receiver<StateMachine>) -> bool {
+    tokio::select! {
+        _ = time::sleep(duration) => false,
+        _ = until_busy(timer) => true,
+    }
+}
+
+/// Keep the final screen of a pump run as `policy` says. Returns true if a
+/// new shot was started or the program exits meanwhile.
+async fn hold_after_shot(
+    policy: config::AfterShot,
+    hold: time::Duration,
+    timer: &mut watch::Receiver<StateMachine>,
+) -> bool {
+    match policy {
+        config::AfterShot::Clear | config::AfterShot::Idle => false,
+        config::AfterShot::Hold => hold_screen(hold, timer).await,
+        config::AfterShot::HoldUntilNext => {
+            until_busy(timer).await;
+            true
+        }
+    }
+}
+
+/// Receive the next status update for the `sink`, which handles every
+/// update. If it falls behind, the oldest updates are skipped, and counted.
+/// Returns None when the updates have ended.
+async fn next_status(
+    updates: &mut broadcast::Receiver<MachineStatus>,
+    sink: &str,
+    metrics: &MaraXMetrics,
+) -> Option<MachineStatus> {
+    loop {
+        match updates.recv().await {
+            Ok(status) => return Some(status),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!(
+                    "The {} fell behind, skipping {} status updates",
+                    sink, skipped
+                );
+                metrics.skipped_updates.inc_by(skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
 
-impl Font for SevenSegmentFont {
-    const FONT_IMAGE: &'static [u8] = include_bytes!("../assets/seven-segment-font.raw");
-    const FONT_IMAGE_WIDTH: u32 = 224;
+/// Wait for the next status update to draw, or return right away if the
+/// screen is to be `redraw`n without one. The display shows only the latest
+/// status, so when it has fallen behind, like during a shot, it skips to the
+/// newest update. Returns false when the updates have ended.
+async fn next_redraw(updates: &mut broadcast::Receiver<MachineStatus>, redraw: &mut bool) -> bool {
+    if std::mem::take(redraw) {
+        return true;
+    }
+    match updates.recv().await {
+        Ok(_) => true,
+        Err(broadcast::error::RecvError::Lagged(_)) => {
+            *updates = updates.resubscribe();
+            true
+        }
+        Err(broadcast::error::RecvError::Closed) => false,
+    }
+}
 
-    const CHARACTER_SIZE: Size = Size::new(22, 40);
-    const CHARACTER_SPACING: u32 = 4;
+/// The latest status of the machine, or zeros until the first status line.
+fn current(machine: &watch::Receiver<Option<MachineStatus>>) -> MachineStatus {
+    machine.borrow().unwrap_or_default()
+}
 
-    fn char_offset(c: char) -> u32 {
-        c.to_digit(10).unwrap_or(0)
+/// Close the menu, and save the changes made in it to the configuration
+/// file, if there is one.
+fn close_menu(menu: &mut Option<menu::Menu>, config_path: Option<&Path>, config: &config::Config) {
+    let changed = menu.take().is_some_and(|menu| menu.changed());
+    if !changed {
+        return;
+    }
+    match config_path {
+        Some(path) => match menu::save(path, config) {
+            Ok(()) => println!("Menu changes saved to {}", path.display()),
+            Err(e) => println!("Failed to save the menu changes: {}", e),
+        },
+        None => println!("No configuration file, the menu changes are lost on restart"),
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_pump(
-    mut disp: GraphicsMode<I2CInterface<I2cdev>>,
-    start_pump: Arc<Notify>,
-    pump_running: Arc<AtomicBool>,
-    exit: Arc<AtomicBool>,
+    mut disp: Box<dyn DisplayBackend>,
+    shot_clock: Arc<Mutex<shot_clock::ShotClock>>,
+    mut config: watch::Receiver<config::Config>,
+    metrics: Arc<MaraXMetrics>,
+    mut updates: broadcast::Receiver<MachineStatus>,
+    wake: Arc<Notify>,
+    mut errors: watch::Receiver<Option<String>>,
+    config_tx: Arc<watch::Sender<config::Config>>,
+    config_path: Option<PathBuf>,
+    mut buttons: mpsc::UnboundedReceiver<menu::Button>,
+    animation: Option<display::Animation>,
+    history: Arc<watch::Sender<history::ShotHistory>>,
+    state: Arc<watch::Sender<StateMachine>>,
+    machine: watch::Receiver<Option<MachineStatus>>,
+    health: Arc<health::Health>,
 ) {
-    let first_digit_position = Point::new(30, 22);
-    let second_digit_position = Point::new(67, 22);
-
-    let mut interval = time::interval(time::Duration::from_secs(1));
+    // The waits end when the timer is started or the program exits.
+    let mut timer = state.subscribe();
+    let mut brightness = config.borrow().display.brightness;
+    let mut every_minute = time::interval(time::Duration::from_secs(60));
+    let burn_in = display::BurnInProtection::new();
+    let mut hx_history = display::TrendTracker::new();
+    let mut steam_history = display::TrendTracker::new();
+    // The heat-up progress is counted from the coldest steam boiler
+    // temperature seen.
+    let mut cold_start: Option<i64> = None;
+    let mut shots = ShotStats::new();
+    let pages_started = time::Instant::now();
+
+    // Show that the program is running until the Mara X starts sending its
+    // status.
+    let version = format!("v{}", env!("CARGO_PKG_VERSION"));
+    let serial_device = config.borrow().serial.device.clone();
+    let texts = config.borrow().lang.texts();
+    report(disp.draw_message(&["marax-shot-timer", &version, &serial_device, texts.waiting]));
+    report(disp.flush());
+    let mut waiting = true;
+
+    // The display is blanked after a while without any changes in the
+    // machine status.
+    let mut blanked = false;
+    let mut last_activity = time::Instant::now();
+    let mut last_status = None;
+    // Draw the idle screen without waiting for the next status update.
+    let mut redraw = false;
+
+    // The menu is open from a button press until it's exited, it times out,
+    // or a shot is started.
+    let mut menu: Option<menu::Menu> = None;
+    let mut menu_deadline = time::Instant::now();
+
+    // The animation page is drawn on its own schedule instead of on the
+    // status updates.
+    let mut animating = false;
+    let mut animation_frame = 0;
+    let mut animation_fps = config.borrow().display.animation_fps;
+    let mut next_frame = time::interval(frame_interval(animation_fps));
 
     loop {
-        start_pump.notified().await;
+        tokio::select! {
+            Ok(()) = timer.changed() => {
+                if !timer.borrow_and_update().is_busy() {
+                    continue;
+                }
+                blanked = false;
+                last_activity = time::Instant::now();
+                animating = false;
+                close_menu(&mut menu, config_path.as_deref(), &config.borrow());
+            }
+            Some(button) = buttons.recv() => {
+                blanked = false;
+                last_activity = time::Instant::now();
+                let timeout = config.borrow().menu.timeout_seconds;
+                menu_deadline = last_activity + time::Duration::from_secs(timeout.into());
+
+                match &mut menu {
+                    // Any button opens the menu.
+                    None => menu = Some(menu::Menu::new(&config.borrow())),
+                    Some(open) => {
+                        config_tx.send_if_modified(|config| open.press(button, config));
+                    }
+                }
+                match &menu {
+                    Some(open) if !open.is_closed() => {
+                        let lines = open.lines(&config.borrow());
+                        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+                        report(disp.draw_message(&lines));
+                    }
+                    _ => {
+                        close_menu(&mut menu, config_path.as_deref(), &config.borrow());
+                        report(disp.clear());
+                    }
+                }
+                report(disp.flush());
+                continue;
+            }
+            _ = next_frame.tick(), if animating && !blanked && menu.is_none() => {
+                if let Some(animation) = &animation {
+                    report(disp.draw_animation(&animation.frame(animation_frame)));
+                    report(disp.flush());
+                    animation_frame += 1;
+                }
+                continue;
+            }
+            _ = time::sleep_until(menu_deadline), if menu.is_some() => {
+                close_menu(&mut menu, config_path.as_deref(), &config.borrow());
+                report(disp.clear());
+                report(disp.flush());
+                continue;
+            }
+            _ = wake.notified() => {
+                blanked = false;
+                last_activity = time::Instant::now();
+                continue;
+            }
+            _ = every_minute.tick() => {
+                health.display_alive();
+                let display_config = &config.borrow().display;
+                let wanted = current_brightness(display_config);
+                if wanted != brightness {
+                    report(disp.set_brightness(wanted));
+                    brightness = wanted;
+                }
 
-        if exit.load(Ordering::SeqCst) {
+                let blank_after = time::Duration::from_secs(60 * u64::from(display_config.blank_minutes));
+                if !blanked && display_config.blank_minutes != 0 && last_activity.elapsed() >= blank_after {
+                    println!("No activity for {} minutes, blanking the display", display_config.blank_minutes);
+                    report(disp.clear());
+                    report(disp.flush());
+                    blanked = true;
+                }
+                continue;
+            }
+            true = next_redraw(&mut updates, &mut redraw) => {
+                if waiting {
+                    waiting = false;
+                    // Show the firmware of the machine for a moment, now
+                    // that it's known.
+                    if let Some(firmware) = current(&machine).firmware {
+                        let firmware = format!("Mara X {}", firmware);
+                        report(disp.draw_message(&["marax-shot-timer", &version, &firmware]));
+                        report(disp.flush());
+                        if hold_screen(FIRMWARE_SCREEN, &mut timer).await {
+                            // Let the shot start as if it hadn't been held.
+                            timer.mark_changed();
+                        }
+                    }
+                    report(disp.clear());
+                    report(disp.flush());
+                }
+                let now = current(&machine);
+                let mode = now.mode as i64;
+                let status = [
+                    mode,
+                    now.steam_temperature,
+                    now.target_steam_temperature,
+                    now.hx_temperature,
+                    now.countdown_boost_mode,
+                    now.heating_element_on.into(),
+                ];
+                if last_status != Some(status) {
+                    // Switching between the coffee and steam modes wakes up
+                    // the display.
+                    if last_status.map(|s| s[0]) != Some(mode) {
+                        blanked = false;
+                    }
+                    last_status = Some(status);
+                    last_activity = time::Instant::now();
+                }
+
+                let steam_temperature = now.steam_temperature;
+                let hx_trend = hx_history.update(now.hx_temperature);
+                let steam_trend = steam_history.update(steam_temperature);
+                let cold = cold_start.map_or(steam_temperature, |t| t.min(steam_temperature));
+                cold_start = Some(cold);
+
+                let display_config = &config.borrow().display;
+                if display_config.idle_screen && !blanked && menu.is_none() {
+                    let protect = display_config.burn_in_protection;
+                    disp.set_shift(if protect { burn_in.shift() } else { (0, 0) });
+                    let pages = &display_config.pages;
+                    let period = pages_started.elapsed().as_secs() / u64::from(display_config.page_seconds);
+                    let page = pages[period as usize % pages.len()];
+                    // Without an animation, the temperatures are shown instead.
+                    let was_animating = animating;
+                    animating = page == config::Page::Animation && animation.is_some();
+                    if animating {
+                        if !was_animating {
+                            animation_frame = 0;
+                        }
+                        continue;
+                    }
+                    let value = match page {
+                        config::Page::SteamDelta => Some(display::InfoValue::SteamDelta(
+                            steam_temperature - now.target_steam_temperature,
+                        )),
+                        config::Page::TotalShots => {
+                            Some(display::InfoValue::TotalShots(metrics.shots_total.get()))
+                        }
+                        config::Page::AverageShot => history.borrow().statistics().map(|s| {
+                            let average = time::Duration::from_secs_f32(s.average_seconds);
+                            display::InfoValue::AverageShot(average)
+                        }),
+                        config::Page::MedianShot => history.borrow().statistics().map(|s| {
+                            let median = time::Duration::from_secs_f32(s.median_seconds);
+                            display::InfoValue::MedianShot(median)
+                        }),
+                        _ => shots.info(page),
+                    };
+                    // The pages about the shots are skipped until there is
+                    // a shot.
+                    if let Some(value) = value {
+                        let info = display::Info {
+                            value,
+                            show_labels: !protect || burn_in.show_labels(),
+                            texts: config.borrow().lang.texts(),
+                        };
+                        report(disp.draw_info(&info));
+                        report(disp.flush());
+                        continue;
+                    }
+                    let idle = display::Idle {
+                        hx_temperature: now.hx_temperature,
+                        steam_temperature,
+                        target_steam_temperature: now.target_steam_temperature,
+                        heating_element_on: now.heating_element_on,
+                        coffee_mode: now.mode == Mode::Coffee,
+                        boost_countdown: now.countdown_boost_mode,
+                        hx_trend,
+                        steam_trend,
+                        heat_up: display::heat_up_progress(
+                            cold,
+                            steam_temperature,
+                            now.target_steam_temperature,
+                        ),
+                        ready: display_config.ready_banner && metrics.machine_ready.get() == 1,
+                        show_steam: display_config.idle_steam,
+                        show_labels: !protect || burn_in.show_labels(),
+                        layout: display_config.layout,
+                        elements: display_config.elements.clone(),
+                        texts: config.borrow().lang.texts(),
+                    };
+                    report(disp.draw_idle(&idle));
+                    report(disp.flush());
+                }
+                continue;
+            }
+            Ok(()) = errors.changed() => {
+                // Keep the problem on the screen until the next good status
+                // line clears it.
+                let error = errors.borrow_and_update().clone();
+                match error {
+                    Some(message) => {
+                        blanked = false;
+                        animating = false;
+                        let texts = config.borrow().lang.texts();
+                        report(disp.draw_message(&[texts.error, &message, texts.retrying]));
+                    }
+                    None => report(disp.clear()),
+                }
+                report(disp.flush());
+                continue;
+            }
+            Ok(()) = config.changed() => {
+                // Apply the reloaded display settings while waiting for the pump.
+                brightness = current_brightness(&config.borrow().display);
+                report(disp.set_brightness(brightness));
+                let fps = config.borrow().display.animation_fps;
+                if fps != animation_fps {
+                    animation_fps = fps;
+                    next_frame = time::interval(frame_interval(fps));
+                }
+                continue;
+            }
+        }
+
+        if timer.borrow().state() == state::State::Exiting {
             break;
         }
 
-        for _i in 0..99 {
-            if !pump_running.load(Ordering::SeqCst) {
-                break;
-            }
+        let timer_config = config.borrow().timer.clone();
+        let max_time = time::Duration::from_secs(timer_config.max_seconds.into());
+        let refresh = if timer_config.tenths {
+            time::Duration::from_millis(100)
+        } else {
+            time::Duration::from_secs(1)
+        };
 
-            let first_digit = _i / 10;
-            let second_digit = _i % 10;
+        // A pump run which was over before the display loop got to it is
+        // still timed and recorded, from the stopped clock.
+        let min_shot_time = time::Duration::from_secs(timer_config.min_shot_seconds.into());
 
-            disp.clear();
+        let started = chrono::Local::now();
+        let start_hx_temperature = current(&machine).hx_temperature;
+        let mut lowest_hx_temperature = start_hx_temperature;
+        let mut interval = time::interval(refresh);
+        // A slow display flush delays the next refresh instead of causing a
+        // burst of them.
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut target_reached = None;
 
-            if first_digit != 0 {
-                egtext!(
-                    text = &first_digit.to_string(),
-                    top_left = first_digit_position,
-                    style = text_style!(font = SevenSegmentFont, text_color = BinaryColor::On)
-                )
-                .draw(&mut disp)
-                .unwrap();
+        let preinfusion_time = time::Duration::from_secs(timer_config.preinfusion_seconds.into());
+
+        let (shot_time, preinfusion, first_drip, manual) = loop {
+            interval.tick().await;
+
+            // The refresh only samples the shot clock, and the last frame
+            // shows the time at which the pump stopped.
+            let clock = {
+                let mut clock = shot_clock.lock().unwrap();
+                if timer_config.preinfusion_seconds != 0 {
+                    clock.end_preinfusion_after(preinfusion_time);
+                }
+                clock.stop_manual_after(max_time);
+                *clock
+            };
+            let elapsed = clock.elapsed().min(max_time);
+            let stopped = !clock.is_running() || elapsed >= max_time;
+            if elapsed >= min_shot_time {
+                transition(&state, state::Event::MinShotTimeReached);
+            }
+            let now = current(&machine);
+            lowest_hx_temperature = lowest_hx_temperature.min(now.hx_temperature);
+
+            // The target time can be changed while the shot is running.
+            let target_seconds = config.borrow().timer.target_seconds;
+            if target_seconds != 0
+                && target_reached.is_none()
+                && elapsed.as_secs() >= target_seconds.into()
+            {
+                println!("Target shot time of {} seconds reached", target_seconds);
+                target_reached = Some(time::Instant::now());
             }
-            egtext!(
-                text = &second_digit.to_string(),
-                top_left = second_digit_position,
-                style = text_style!(font = SevenSegmentFont, text_color = BinaryColor::On)
-            )
-            .draw(&mut disp)
-            .unwrap();
 
-            disp.flush().unwrap();
+            let display_config = &config.borrow().display;
+            // Blink for a while after reaching the target, as fast as the
+            // screen is refreshed.
+            let flash = display_config.target_flash
+                && target_reached.is_some_and(|reached| {
+                    let since = reached.elapsed();
+                    since < TARGET_FLASH_TIME && (since.as_millis() / 250).is_multiple_of(2)
+                });
+            // In the countdown mode the time past the target blinks.
+            let countdown = config.borrow().timer.countdown;
+            let overtime = countdown
+                && target_seconds != 0
+                && elapsed.as_secs() >= target_seconds.into()
+                && elapsed.as_secs() % 2 == 1;
+            // Hold the split on the display for a moment after the first
+            // drip, while the shot is timed on.
+            let shown = match clock.first_drip() {
+                Some(split) if !stopped && elapsed < split + FIRST_DRIP_SHOW_TIME => split,
+                _ => elapsed,
+            };
+            let timer = display::Timer {
+                elapsed: shown,
+                preinfusion: clock.preinfusion(),
+                tenths: timer_config.tenths,
+                target_seconds,
+                countdown,
+                hx_temperature: display_config.timer_hx.then_some(now.hx_temperature),
+                heating_element_on: now.heating_element_on,
+                coffee_mode: now.mode == Mode::Coffee,
+                flash: flash || overtime,
+                progress_ring: display_config.progress_ring,
+                layout: display_config.layout,
+                elements: display_config.elements.clone(),
+                texts: config.borrow().lang.texts(),
+            };
+            report(disp.draw_timer(&timer));
+            report(disp.flush());
+
+            if stopped {
+                break (
+                    elapsed,
+                    clock.preinfusion(),
+                    clock.first_drip(),
+                    clock.is_manual(),
+                );
+            }
+        };
+        transition(&state, state::Event::PumpStopped);
+
+        // Keep the final time visible for a while, and then the summary of
+        // the shot, unless a new shot is started or we are told to exit.
+        let hold = time::Duration::from_secs(timer_config.hold_seconds.into());
+        let hx_drop = start_hx_temperature - lowest_hx_temperature;
+        let kind = history::Kind::classify(shot_time, hx_drop, manual, &timer_config);
+        metrics.pump_runs.with_label_values(&[kind.name()]).inc();
+        let record = history::ShotRecord::new(
+            started,
+            shot_time,
+            preinfusion,
+            first_drip,
+            start_hx_temperature,
+            current(&machine).hx_temperature,
+            kind,
+        );
+        let history_size = timer_config.history_size as usize;
+        history.send_modify(|history| history.push(record, history_size));
+
+        if kind == history::Kind::Autofill {
+            println!(
+                "Pump ran for {:.1} s without cooling the heat exchanger, counted as a boiler refill",
+                shot_time.as_secs_f32()
+            );
+            report(disp.clear());
+            report(disp.flush());
+            transition(&state, state::Event::HoldEnded);
+            continue;
+        }
+        if kind == history::Kind::Flush {
+            println!(
+                "Pump ran for {:.1} s, not counted as a shot",
+                shot_time.as_secs_f32()
+            );
+            metrics.flushes_total.inc();
+            let texts = config.borrow().lang.texts();
+            let seconds = format!("{:.1} s", shot_time.as_secs_f32());
+            report(disp.draw_message(&[texts.flush, &seconds]));
+            report(disp.flush());
+            if hold_after_shot(timer_config.after_shot, hold, &mut timer).await {
+                timer.mark_changed();
+            }
+            report(disp.clear());
+            report(disp.flush());
+            transition(&state, state::Event::HoldEnded);
+            redraw = timer_config.after_shot == config::AfterShot::Idle;
+            continue;
+        }
 
-            interval.tick().await;
+        let number = shots.record(shot_time);
+        if let Some(first_drip) = first_drip {
+            println!(
+                "First drip of shot {} at {:.1} s",
+                number,
+                first_drip.as_secs_f32()
+            );
+        }
+        metrics.shots_total.inc();
+        metrics.shot_duration.observe(shot_time.as_secs_f64());
+        if let Some(path) = &timer_config.counter_file {
+            if let Err(e) = counter::save(Path::new(path), metrics.shots_total.get()) {
+                println!("Failed to save the shot count: {}", e);
+            }
+        }
+        if let Some(preinfusion) = preinfusion {
+            println!(
+                "Shot {} took {:.1} s, {:.1} s of it pre-infusion",
+                number,
+                shot_time.as_secs_f32(),
+                preinfusion.as_secs_f32()
+            );
+        }
+        let after_shot = timer_config.after_shot;
+        let mut interrupted = hold_after_shot(after_shot, hold, &mut timer).await;
+        if !interrupted
+            && after_shot == config::AfterShot::Hold
+            && timer_config.summary_seconds != 0
+        {
+            let summary = display::Summary {
+                elapsed: shot_time,
+                preinfusion,
+                start_hx_temperature,
+                end_hx_temperature: current(&machine).hx_temperature,
+                number,
+                texts: config.borrow().lang.texts(),
+            };
+            report(disp.draw_summary(&summary));
+            report(disp.flush());
+            let show = time::Duration::from_secs(timer_config.summary_seconds.into());
+            interrupted = hold_screen(show, &mut timer).await;
+        }
+        if interrupted {
+            // Let the outer loop handle the new state.
+            timer.mark_changed();
         }
 
-        // Clean up after the timer is done. TODO: should we keep the last value visible for a while?
-        disp.clear();
-        disp.flush().unwrap();
+        // Clean up after the timer is done.
+        report(disp.clear());
+        report(disp.flush());
+        transition(&state, state::Event::HoldEnded);
+        redraw = after_shot == config::AfterShot::Idle;
     }
 
     // Clean up before exit.
-    disp.clear();
-    disp.flush().unwrap();
+    report(disp.clear());
+    report(disp.flush());
 }
 
-// Serial port codec implementation
-struct LineCodec;
+async fn handle_signals(
+    args: cli::Args,
+    config_tx: Arc<watch::Sender<config::Config>>,
+    timers: Vec<Arc<watch::Sender<StateMachine>>>,
+    shutdown_prometheus: Arc<Notify>,
+) {
+    let mut sigint = signal(SignalKind::interrupt()).expect("Error setting SIGINT handler");
+    let mut sigterm = signal(SignalKind::terminate()).expect("Error setting SIGTERM handler");
+    let mut sighup = signal(SignalKind::hangup()).expect("Error setting SIGHUP handler");
 
-impl Decoder for LineCodec {
-    type Item = String;
-    type Error = io::Error;
+    loop {
+        tokio::select! {
+            _ = sighup.recv() => {
+                match config::Config::load(&args) {
+                    Ok(new_config) => {
+                        if config_tx.borrow().requires_restart(&new_config) {
+                            println!("Some configuration changes take effect only after restart");
+                        }
+                        config_tx.send_replace(new_config);
+                        println!("Configuration reloaded");
+                    }
+                    Err(e) => println!("Failed to reload configuration: {}", e),
+                }
+            }
+            _ = sigint.recv() => break,
+            _ = sigterm.recv() => break,
+        }
+    }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let newline = src.as_ref().iter().position(|b| *b == b'\n');
-        if let Some(n) = newline {
-            let line = src.split_to(n + 1);
-            return match str::from_utf8(line.as_ref()) {
-                Ok(s) => Ok(Some(s.to_string())),
-                Err(_) => Err(io::Error::new(io::ErrorKind::Other, "Invalid String")),
-            };
+    for timer in timers {
+        transition(&timer, state::Event::Exit);
+    }
+    shutdown_prometheus.notify_one();
+}
+
+/// The gauges of the machine status, which are left out of the metrics while
+/// the data is stale, so that the graphs don't go on with the last values.
+struct StatusGauges {
+    gauges: Vec<IntGauge>,
+    stale: IntGauge,
+}
+
+impl Collector for StatusGauges {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauges.iter().flat_map(|gauge| gauge.desc()).collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        if self.stale.get() == 1 {
+            return Vec::new();
         }
-        Ok(None)
+        self.gauges
+            .iter()
+            .flat_map(|gauge| gauge.collect())
+            .collect()
     }
 }
 
-impl Encoder<String> for LineCodec {
-    type Error = io::Error;
+/// The options of a metric named and labeled as `config` says. Each metric
+/// has its original name, and the name following the Prometheus conventions.
+fn metric_opts(
+    config: &config::MetricsConfig,
+    classic: &str,
+    conventional: &str,
+    help: &str,
+) -> Opts {
+    let opts = match config.names {
+        config::MetricNames::Classic => Opts::new(classic, help),
+        config::MetricNames::Prometheus => {
+            Opts::new(conventional, help).namespace(config.namespace.as_str())
+        }
+    };
+    opts.const_labels(config.labels.clone().into_iter().collect())
+}
 
-    fn encode(&mut self, _item: String, _dst: &mut BytesMut) -> Result<(), Self::Error> {
-        Ok(())
+/// Seconds since the Unix epoch.
+fn unix_time() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64())
+}
+
+/// The age of the latest status line, counted when the metrics are read.
+/// It's left out until the first line.
+struct SampleAge {
+    age: Gauge,
+    last_sample: Gauge,
+}
+
+impl Collector for SampleAge {
+    fn desc(&self) -> Vec<&Desc> {
+        self.age.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let last_sample = self.last_sample.get();
+        if last_sample == 0.0 {
+            return Vec::new();
+        }
+        self.age.set((unix_time() - last_sample).max(0.0));
+        self.age.collect()
     }
 }
 
@@ -129,53 +847,269 @@ pub struct MaraXMetrics {
     pub countdown_boost_mode: IntGauge,
     pub heating_element_on: IntGauge,
     pub pump_on: IntGauge,
+    pub machine_ready: IntGauge,
+    pub stale_data: IntGauge,
+    pub pump_runs: IntCounterVec,
+    pub shots_total: IntCounter,
+    pub flushes_total: IntCounter,
+    pub shot_duration: Histogram,
+    pub serial_reconnects: IntCounter,
+    pub discarded_data: IntCounter,
+    pub out_of_range: IntCounter,
+    pub machine_shot_timer: IntGauge,
+    pub water_low: IntGauge,
+    pub lines_received: IntCounter,
+    pub lines_parsed: IntCounter,
+    pub lines_failed: IntCounter,
+    pub utf8_errors: IntCounter,
+    pub firmware: IntGaugeVec,
+    pub skipped_updates: IntCounter,
+    pub last_sample: Gauge,
 }
 
 impl MaraXMetrics {
-    pub fn new() -> Result<(Self, RegistryFn), Box<dyn Error>> {
-        let machine_mode = IntGauge::with_opts(Opts::new(
+    /// Create the metrics named and labeled as `config` says, with the
+    /// `machine` label set to `label` if there is one.
+    pub fn new(
+        label: Option<&str>,
+        config: &config::MetricsConfig,
+    ) -> Result<(Self, RegistryFn), Box<dyn Error>> {
+        let opts = |classic: &str, conventional: &str, help: &str| {
+            let opts = metric_opts(config, classic, conventional, help);
+            match label {
+                Some(label) => opts.const_label("machine", label),
+                None => opts,
+            }
+        };
+
+        let machine_mode = IntGauge::with_opts(opts(
             "MachineMode",
+            "coffee_mode",
             "Machine mode: coffee (1) or steam (0)",
         ))?;
         let machine_mode_clone = machine_mode.clone();
 
-        let steam_temperature =
-            IntGauge::with_opts(Opts::new("SteamTemperature", "Boiler steam temperature"))?;
+        let steam_temperature = IntGauge::with_opts(opts(
+            "SteamTemperature",
+            "steam_temperature_celsius",
+            "Boiler steam temperature",
+        ))?;
         let steam_temperature_clone = steam_temperature.clone();
 
-        let target_steam_temperature = IntGauge::with_opts(Opts::new(
+        let target_steam_temperature = IntGauge::with_opts(opts(
             "TargetSteamTemperature",
+            "target_steam_temperature_celsius",
             "Boiler target steam temperature",
         ))?;
         let target_steam_temperature_clone = target_steam_temperature.clone();
 
-        let hx_temperature =
-            IntGauge::with_opts(Opts::new("HXTemperature", "Heat exchanger temperature"))?;
+        let hx_temperature = IntGauge::with_opts(opts(
+            "HXTemperature",
+            "hx_temperature_celsius",
+            "Heat exchanger temperature",
+        ))?;
         let hx_temperature_clone = hx_temperature.clone();
 
-        let countdown_boost_mode = IntGauge::with_opts(Opts::new(
+        let countdown_boost_mode = IntGauge::with_opts(opts(
             "CountdownBoostMode",
+            "boost_countdown",
             "Countdown for exiting boost mode",
         ))?;
         let countdown_boost_mode_clone = countdown_boost_mode.clone();
 
-        let heating_element_on = IntGauge::with_opts(Opts::new(
+        let heating_element_on = IntGauge::with_opts(opts(
             "HeatingElementOn",
+            "heating_element_on",
             "Heating element on (1) or off (0)",
         ))?;
         let heating_element_on_clone = heating_element_on.clone();
 
-        let pump_on = IntGauge::with_opts(Opts::new("PumpOn", "Pump on (1) or off (0)"))?;
+        let pump_on = IntGauge::with_opts(opts("PumpOn", "pump_on", "Pump on (1) or off (0)"))?;
         let pump_on_clone = pump_on.clone();
 
+        let machine_ready = IntGauge::with_opts(opts(
+            "MachineReady",
+            "ready",
+            "Steam temperature settled at the target (1) or not (0)",
+        ))?;
+        let machine_ready_clone = machine_ready.clone();
+
+        let stale_data = IntGauge::with_opts(opts(
+            "DataStale",
+            "data_stale",
+            "No status lines from the machine lately (1) or not (0)",
+        ))?;
+        let stale_data_clone = stale_data.clone();
+
+        let pump_runs = IntCounterVec::new(
+            opts(
+                "PumpRuns",
+                "pump_runs_total",
+                "Pump runs by kind: shot, flush or autofill",
+            ),
+            &["kind"],
+        )?;
+        // Export all the kinds from the start, not only after the first run.
+        for kind in history::Kind::ALL {
+            pump_runs.with_label_values(&[kind.name()]);
+        }
+        let pump_runs_clone = pump_runs.clone();
+
+        let shots_total = IntCounter::with_opts(opts(
+            "ShotsTotal",
+            "shots_total",
+            "Shots pulled over the lifetime of the machine",
+        ))?;
+        let shots_total_clone = shots_total.clone();
+
+        let flushes_total = IntCounter::with_opts(opts(
+            "FlushesTotal",
+            "flushes_total",
+            "Pump runs too short to be a shot since the start",
+        ))?;
+        let flushes_total_clone = flushes_total.clone();
+
+        // Most shots take 20-35 seconds, so the buckets are denser there.
+        let shot_duration = Histogram::with_opts(
+            HistogramOpts::from(opts(
+                "ShotDurationSeconds",
+                "shot_duration_seconds",
+                "Duration of the shots",
+            ))
+            .buckets(vec![
+                10.0, 15.0, 20.0, 22.0, 24.0, 26.0, 28.0, 30.0, 32.0, 35.0, 40.0, 50.0, 60.0,
+            ]),
+        )?;
+        let shot_duration_clone = shot_duration.clone();
+
+        let serial_reconnects = IntCounter::with_opts(opts(
+            "SerialReconnects",
+            "serial_reconnects_total",
+            "Times the serial port was opened again after an error",
+        ))?;
+        let serial_reconnects_clone = serial_reconnects.clone();
+
+        let discarded_data = IntCounter::with_opts(opts(
+            "DiscardedData",
+            "serial_discarded_data_total",
+            "Times the serial data was thrown away for having no line ending",
+        ))?;
+        let discarded_data_clone = discarded_data.clone();
+
+        let out_of_range = IntCounter::with_opts(opts(
+            "OutOfRangeLines",
+            "out_of_range_lines_total",
+            "Status lines rejected for implausible values",
+        ))?;
+        let out_of_range_clone = out_of_range.clone();
+
+        let machine_shot_timer = IntGauge::with_opts(opts(
+            "MachineShotTimer",
+            "machine_shot_timer_seconds",
+            "Shot timer of the machine in seconds, newer firmware only",
+        ))?;
+        let machine_shot_timer_clone = machine_shot_timer.clone();
+
+        let water_low = IntGauge::with_opts(opts(
+            "WaterLow",
+            "water_low",
+            "Water reservoir empty (1) or not (0), newer firmware only",
+        ))?;
+        let water_low_clone = water_low.clone();
+
+        let lines_received = IntCounter::with_opts(opts(
+            "LinesReceived",
+            "lines_received_total",
+            "Lines read from the serial port",
+        ))?;
+        let lines_received_clone = lines_received.clone();
+
+        let lines_parsed = IntCounter::with_opts(opts(
+            "LinesParsed",
+            "lines_parsed_total",
+            "Lines accepted as status lines of the machine",
+        ))?;
+        let lines_parsed_clone = lines_parsed.clone();
+
+        let lines_failed = IntCounter::with_opts(opts(
+            "ParseFailures",
+            "parse_failures_total",
+            "Lines which couldn't be parsed as status lines",
+        ))?;
+        let lines_failed_clone = lines_failed.clone();
+
+        let utf8_errors = IntCounter::with_opts(opts(
+            "Utf8Errors",
+            "utf8_errors_total",
+            "Lines with bytes which weren't valid UTF-8",
+        ))?;
+        let utf8_errors_clone = utf8_errors.clone();
+
+        let firmware = IntGaugeVec::new(
+            opts(
+                "MachineFirmware",
+                "firmware_info",
+                "Firmware version of the machine in the version label, always 1",
+            ),
+            &["version"],
+        )?;
+        let firmware_clone = firmware.clone();
+
+        let skipped_updates = IntCounter::with_opts(opts(
+            "SkippedUpdates",
+            "skipped_updates_total",
+            "Status updates skipped by the log and the metrics for falling behind",
+        ))?;
+        let skipped_updates_clone = skipped_updates.clone();
+
+        let last_sample = Gauge::with_opts(opts(
+            "LastSampleTimestamp",
+            "last_sample_timestamp_seconds",
+            "Time of the latest status line in seconds since the Unix epoch",
+        ))?;
+        let last_sample_clone = last_sample.clone();
+
+        let sample_age = Gauge::with_opts(opts(
+            "SampleAge",
+            "sample_age_seconds",
+            "Seconds since the latest status line",
+        ))?;
+
         let f = |r: &Registry| -> Result<(), prometheus::Error> {
-            r.register(Box::new(machine_mode_clone))?;
-            r.register(Box::new(steam_temperature_clone))?;
-            r.register(Box::new(target_steam_temperature_clone))?;
-            r.register(Box::new(hx_temperature_clone))?;
-            r.register(Box::new(countdown_boost_mode_clone))?;
-            r.register(Box::new(heating_element_on_clone))?;
-            r.register(Box::new(pump_on_clone))?;
+            r.register(Box::new(StatusGauges {
+                gauges: vec![
+                    machine_mode_clone,
+                    steam_temperature_clone,
+                    target_steam_temperature_clone,
+                    hx_temperature_clone,
+                    countdown_boost_mode_clone,
+                    heating_element_on_clone,
+                    pump_on_clone,
+                    machine_ready_clone,
+                    machine_shot_timer_clone,
+                    water_low_clone,
+                ],
+                stale: stale_data_clone.clone(),
+            }))?;
+            r.register(Box::new(stale_data_clone))?;
+            r.register(Box::new(pump_runs_clone))?;
+            r.register(Box::new(shots_total_clone))?;
+            r.register(Box::new(flushes_total_clone))?;
+            r.register(Box::new(shot_duration_clone))?;
+            r.register(Box::new(serial_reconnects_clone))?;
+            r.register(Box::new(discarded_data_clone))?;
+            r.register(Box::new(out_of_range_clone))?;
+            r.register(Box::new(lines_received_clone))?;
+            r.register(Box::new(lines_parsed_clone))?;
+            r.register(Box::new(lines_failed_clone))?;
+            r.register(Box::new(utf8_errors_clone))?;
+            r.register(Box::new(firmware_clone))?;
+            r.register(Box::new(skipped_updates_clone))?;
+            r.register(Box::new(last_sample_clone.clone()))?;
+            r.register(Box::new(SampleAge {
+                age: sample_age,
+                last_sample: last_sample_clone,
+            }))?;
             Ok(())
         };
 
@@ -188,154 +1122,667 @@ impl MaraXMetrics {
                 countdown_boost_mode,
                 heating_element_on,
                 pump_on,
+                machine_ready,
+                stale_data,
+                pump_runs,
+                shots_total,
+                flushes_total,
+                shot_duration,
+                serial_reconnects,
+                discarded_data,
+                out_of_range,
+                machine_shot_timer,
+                water_low,
+                lines_received,
+                lines_parsed,
+                lines_failed,
+                utf8_errors,
+                firmware,
+                skipped_updates,
+                last_sample,
             },
             Box::new(f),
         ))
     }
 }
 
-fn parse_line_and_update_metrics(
-    line: &str,
-    metrics: &MaraXMetrics,
-) -> Result<bool, Box<dyn Error>> {
-    // "C1.19,116,124,095,0560,0,0"
-
-    let v: Vec<&str> = line.split(',').collect();
-
-    if v.len() != 7 {
-        return Err("parse error: wrong number of tokens")?;
-    }
-
-    if v[0].is_empty() {
-        return Err("parse error: empty token 0")?;
-    }
-
-    match v[0].chars().next() {
-        None => return Err("parse error: index out of range")?,
-        Some(c) => match c {
-            'C' => metrics.machine_mode.set(1),
-            'V' => metrics.machine_mode.set(0),
-            _ => return Err("parse error: unknown machine mode")?,
-        },
-    }
-
-    let steam_temperature = v[1].parse::<i64>()?;
-    metrics.steam_temperature.set(steam_temperature);
-
-    let target_steam_temperature = v[2].parse::<i64>()?;
+fn update_metrics(status: &MachineStatus, metrics: &MaraXMetrics) {
+    metrics
+        .machine_mode
+        .set((status.mode == Mode::Coffee).into());
+    metrics.steam_temperature.set(status.steam_temperature);
     metrics
         .target_steam_temperature
-        .set(target_steam_temperature);
-
-    let hx_temperature = v[3].parse::<i64>()?;
-    metrics.hx_temperature.set(hx_temperature);
-
-    let countdown_boost_mode = v[4].parse::<i64>()?;
-    metrics.countdown_boost_mode.set(countdown_boost_mode);
+        .set(status.target_steam_temperature);
+    metrics.hx_temperature.set(status.hx_temperature);
+    metrics
+        .countdown_boost_mode
+        .set(status.countdown_boost_mode);
+    metrics
+        .heating_element_on
+        .set(status.heating_element_on.into());
+    metrics.pump_on.set(status.pump_on.into());
+    if let Some(extended) = status.extended {
+        metrics.machine_shot_timer.set(extended.shot_timer);
+        metrics.water_low.set(extended.water_low.into());
+    }
+    if let Some(firmware) = status.firmware {
+        let version = firmware.to_string();
+        // Only the current version is exported, also after an update.
+        if metrics.firmware.with_label_values(&[&version]).get() == 0 {
+            metrics.firmware.reset();
+            metrics.firmware.with_label_values(&[&version]).set(1);
+        }
+    }
+}
 
-    let heating_element_on = v[5].parse::<i64>()?;
-    if heating_element_on != 0 && heating_element_on != 1 {
-        return Err("parse error: wrong heating element state value")?;
+/// Show every digit on the display in turn to check that it is connected
+/// properly.
+async fn test_display(config: &config::Config) {
+    if !config.display.enabled {
+        println!("Display is disabled in the configuration");
+        return;
     }
-    metrics.heating_element_on.set(heating_element_on);
 
-    let pump_on = v[6].parse::<i64>()?;
-    if pump_on != 0 && pump_on != 1 {
-        return Err("parse error: wrong pump state value")?;
+    let mut disp = display::open(&config.display).expect("Failed to open the display");
+
+    for digit in 0..10 {
+        let timer = display::Timer {
+            elapsed: time::Duration::from_secs(digit * 11),
+            preinfusion: None,
+            tenths: false,
+            target_seconds: 0,
+            countdown: false,
+            hx_temperature: None,
+            heating_element_on: false,
+            coffee_mode: true,
+            flash: false,
+            progress_ring: false,
+            layout: config.display.layout,
+            elements: config.display.elements.clone(),
+            texts: config.lang.texts(),
+        };
+        disp.draw_timer(&timer).unwrap();
+        disp.flush().unwrap();
+        time::sleep(time::Duration::from_secs(1)).await;
     }
-    metrics.pump_on.set(pump_on);
 
-    Ok(pump_on == 1)
+    disp.clear().unwrap();
+    disp.flush().unwrap();
 }
 
-#[tokio::main]
-async fn main() {
-    let pump_running = Arc::new(AtomicBool::new(false));
-    let pump_running_clone = pump_running.clone();
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().expect("Failed to start the runtime")
+}
 
-    let start_pump = Arc::new(Notify::new());
-    let start_pump_clone = Arc::clone(&start_pump);
-    let start_pump_clone_ctrlc = Arc::clone(&start_pump);
+fn main() {
+    let args = cli::Args::parse();
 
-    let shutdown_prometheus = Arc::new(Notify::new());
-    let shutdown_prometheus_clone = Arc::clone(&shutdown_prometheus);
+    if let Some(cli::Command::CheckConfig) = args.command {
+        let ok = runtime().block_on(check::check_config(&args));
+        std::process::exit(if ok { 0 } else { 1 });
+    }
 
-    let pump_loop_exit = Arc::new(AtomicBool::new(false));
-    let pump_loop_exit_clone = pump_loop_exit.clone();
+    let config = config::Config::load(&args).expect("Failed to load configuration");
+    // The time zone is set while there are no other threads.
+    config.display.apply_timezone();
 
-    ctrlc::set_handler(move || {
-        pump_loop_exit.store(true, Ordering::SeqCst);
-        start_pump_clone_ctrlc.notify_one();
-        shutdown_prometheus.notify_one();
-    })
-    .expect("Error setting Ctrl-C handler");
+    runtime().block_on(start(args, config));
+}
 
-    // Initialize display
+async fn start(args: cli::Args, config: config::Config) {
+    match args.command.clone().unwrap_or(cli::Command::Run) {
+        cli::Command::Run => {
+            let protocol = config.profile.protocol();
+            let mut readers = vec![source::serial_reconnecting(config.serial.clone(), protocol)];
+            for machine in &config.machines {
+                let protocol = machine.profile.protocol();
+                readers.push(source::serial_reconnecting(
+                    machine.serial.clone(),
+                    protocol,
+                ));
+            }
+            run(args, config, readers).await;
+        }
+        cli::Command::Simulate => {
+            let readers = (0..=config.machines.len())
+                .map(|_| source::simulate())
+                .collect();
+            run(args, config, readers).await;
+        }
+        cli::Command::Replay {
+            file,
+            interval_ms,
+            speed,
+        } => {
+            let interval = time::Duration::from_millis(interval_ms);
+            let lines =
+                source::replay(&file, interval, speed).expect("Failed to open the replay file");
+            // The recording is of the main machine only, so the others stay
+            // silent.
+            let mut readers = vec![lines];
+            for _ in &config.machines {
+                readers.push(Box::pin(futures::stream::pending()) as source::LineStream);
+            }
+            run(args, config, readers).await;
+        }
+        cli::Command::TestDisplay => test_display(&config).await,
+        cli::Command::CheckConfig => unreachable!(),
+    }
+}
 
-    let i2c = I2cdev::new("/dev/i2c-1").unwrap();
+/// Send the presses of the menu buttons and the turns of the rotary encoder
+/// to `buttons`.
+fn watch_menu(
+    config: &config::Config,
+    select_pin: u32,
+    buttons: mpsc::UnboundedSender<menu::Button>,
+) -> display::Result<()> {
+    let chip = &config.display.gpio_chip;
+    let pins = [
+        (Some(select_pin), menu::Button::Select),
+        (config.menu.up_pin, menu::Button::Up),
+        (config.menu.down_pin, menu::Button::Down),
+    ];
+    for (pin, button) in pins {
+        if let Some(pin) = pin {
+            let buttons = buttons.clone();
+            display::watch_button(chip, pin, move || {
+                let _ = buttons.send(button);
+            })?;
+        }
+    }
+    if let (Some(a), Some(b)) = (config.menu.encoder_a_pin, config.menu.encoder_b_pin) {
+        display::watch_encoder(chip, a, b, move |clockwise| {
+            let button = if clockwise {
+                menu::Button::Down
+            } else {
+                menu::Button::Up
+            };
+            let _ = buttons.send(button);
+        })?;
+    }
+    Ok(())
+}
 
-    let interface = I2CDIBuilder::new().init(i2c);
-    let mut disp: GraphicsMode<I2CInterface<I2cdev>> = Builder::new().connect(interface).into();
+/// The parts of a running machine which the signal handler and the HTTP API
+/// need.
+struct Machine {
+    pump: tokio::task::JoinHandle<()>,
+    shot_clock: Arc<Mutex<shot_clock::ShotClock>>,
+    screen: watch::Receiver<Option<display::Snapshot>>,
+    history: Arc<watch::Sender<history::ShotHistory>>,
+    status: watch::Receiver<Option<MachineStatus>>,
+    state: Arc<watch::Sender<StateMachine>>,
+    health: Arc<health::Health>,
+}
 
-    disp.init().unwrap();
-    disp.flush().unwrap();
+/// Follow the configuration of the main machine with the configuration of
+/// the additional machine `index`.
+fn machine_config(
+    mut config: watch::Receiver<config::Config>,
+    index: usize,
+) -> watch::Receiver<config::Config> {
+    let derived = move |config: &config::Config| {
+        config
+            .machines
+            .get(index)
+            .map(|machine| config.for_machine(machine))
+    };
+    let first = derived(&config.borrow()).expect("No such machine");
+    let (tx, rx) = watch::channel(first);
+    tokio::spawn(async move {
+        while config.changed().await.is_ok() {
+            // A machine removed on reload keeps its old settings until the
+            // restart.
+            if let Some(new_config) = derived(&config.borrow_and_update()) {
+                tx.send_replace(new_config);
+            }
+        }
+    });
+    rx
+}
 
-    // Start listening for Mara X serial events
+/// Start reading one machine from `reader`, and timing its shots on its
+/// display. The configurations of the additional machines have no buttons.
+#[allow(clippy::too_many_arguments)]
+fn start_machine(
+    config_rx: watch::Receiver<config::Config>,
+    mut reader: source::LineStream,
+    registry: &Registry,
+    config_tx: Arc<watch::Sender<config::Config>>,
+    config_path: Option<PathBuf>,
+    mqtt: Option<mqtt::Publisher>,
+) -> Machine {
+    let config = config_rx.borrow().clone();
+    // The log lines of the machines are told apart by the label.
+    let prefix = config
+        .label
+        .as_ref()
+        .map(|label| format!("{}: ", label))
+        .unwrap_or_default();
+
+    let shot_clock = Arc::new(Mutex::new(shot_clock::ShotClock::default()));
+    let shot_clock_clone = Arc::clone(&shot_clock);
+    let shot_clock_http = Arc::clone(&shot_clock);
+    let state = Arc::new(watch::channel(StateMachine::default()).0);
+
+    // The parsed status lines are published to the display, the metrics and
+    // the log.
+    let (status_tx, updates) = broadcast::channel(STATUS_UPDATES);
+
+    let wake = Arc::new(Notify::new());
+
+    let (errors_tx, errors_rx) = watch::channel(None);
+
+    // Initialize display, unless running headless
+
+    if !config.display.enabled {
+        println!("{}Display disabled, running headless", prefix);
+    }
+    let disp = display::open_all(config.all_displays()).expect("Failed to open the display");
+    let (screen_tx, screen_rx) = watch::channel(None);
+    let stale_time = time::Duration::from_secs(config.serial.stale_seconds.into());
+    let health = Arc::new(health::Health::new(stale_time));
+    let disp = Box::new(display::Mirror::new(disp, screen_tx, Arc::clone(&health)));
+    if let Some(pin) = config.display.wake_button_pin {
+        let wake = Arc::clone(&wake);
+        display::watch_button(&config.display.gpio_chip, pin, move || wake.notify_one())
+            .expect("Failed to set up the wake button");
+    }
+    let animation =
+        display::Animation::load(&config.display).expect("Failed to load the animation");
+    if let Some(pin) = config.timer.preinfusion_pin {
+        let shot_clock = Arc::clone(&shot_clock);
+        display::watch_button(&config.display.gpio_chip, pin, move || {
+            shot_clock
+                .lock()
+                .unwrap()
+                .end_preinfusion(time::Instant::now())
+        })
+        .expect("Failed to set up the pre-infusion button");
+    }
+    if let Some(pin) = config.timer.first_drip_pin {
+        let shot_clock = Arc::clone(&shot_clock);
+        display::watch_button(&config.display.gpio_chip, pin, move || {
+            shot_clock
+                .lock()
+                .unwrap()
+                .mark_first_drip(time::Instant::now())
+        })
+        .expect("Failed to set up the first drip button");
+    }
+    if let Some(pin) = config.timer.manual_pin {
+        let shot_clock = Arc::clone(&shot_clock);
+        let state = Arc::clone(&state);
+        display::watch_button(&config.display.gpio_chip, pin, move || {
+            let started = {
+                let mut clock = shot_clock.lock().unwrap();
+                if clock.is_running() {
+                    clock.stop_manual(time::Instant::now());
+                    false
+                } else {
+                    clock.start_manual(time::Instant::now())
+                }
+            };
+            if started {
+                println!("Timer started with the button");
+                transition(&state, state::Event::PumpStarted);
+            }
+        })
+        .expect("Failed to set up the manual timer button");
+    }
+    let (buttons_tx, buttons_rx) = mpsc::unbounded_channel();
+    if let Some(pin) = config.menu.select_pin {
+        watch_menu(&config, pin, buttons_tx).expect("Failed to set up the menu buttons");
+    }
 
-    let mut serial_port = tokio_serial::new("/dev/ttyS0", 9600)
-        .open_native_async()
-        .unwrap();
-    serial_port
-        .set_exclusive(false)
-        .expect("Unable to set serial port exclusive to false");
-    let mut reader = LineCodec.framed(serial_port);
+    let (metrics, f) = MaraXMetrics::new(config.label.as_deref(), &config.metrics)
+        .expect("Failed prometheus metrics.");
+    f(registry).expect("Failed registering the registry.");
+    if let Some(path) = &config.timer.counter_file {
+        let count = counter::load(Path::new(path)).expect("Failed to read the shot count");
+        metrics.shots_total.inc_by(count);
+    }
+    let metrics = Arc::new(metrics);
+    let metrics_clone = Arc::clone(&metrics);
+
+    let (machine_tx, machine_rx) = watch::channel(None);
+    let state_clone = Arc::clone(&state);
+    let history = Arc::new(watch::channel(history::ShotHistory::default()).0);
+
+    let mut log_updates = status_tx.subscribe();
+    let log_metrics = Arc::clone(&metrics);
+    let log_prefix = prefix.clone();
+    tokio::spawn(async move {
+        while let Some(status) = next_status(&mut log_updates, "log", &log_metrics).await {
+            println!("{}{}", log_prefix, status);
+        }
+    });
 
-    // Start publishing Mara X values to the Prometheus endpoint
+    let mut metrics_updates = status_tx.subscribe();
+    let sink_metrics = Arc::clone(&metrics);
+    let ready_config = config_rx.clone();
+    tokio::spawn(async move {
+        let mut stability = stability::StabilityTracker::new();
+        while let Some(status) = next_status(&mut metrics_updates, "metrics", &sink_metrics).await {
+            update_metrics(&status, &sink_metrics);
+            let ready = stability.update(
+                &ready_config.borrow().ready,
+                status.steam_temperature,
+                status.target_steam_temperature,
+                status.heating_element_on,
+            );
+            sink_metrics.machine_ready.set(ready.into());
+        }
+    });
 
-    let registry = Arc::new(Registry::new());
-    let (metrics, f) = MaraXMetrics::new().expect("Failed prometheus metrics.");
-    f(&registry).expect("Failed registering the registry.");
+    if let Some(mut influxdb) = influx::InfluxDb::new(&config.influxdb, config.label.as_deref()) {
+        let mut influx_updates = status_tx.subscribe();
+        let mut influx_history = history.subscribe();
+        let influx_metrics = Arc::clone(&metrics);
+        let period = time::Duration::from_secs(config.influxdb.interval_seconds.into());
+        tokio::spawn(async move {
+            let mut interval = time::interval_at(time::Instant::now() + period, period);
+            loop {
+                tokio::select! {
+                    status = next_status(&mut influx_updates, "InfluxDB writer", &influx_metrics) => {
+                        match status {
+                            Some(status) => influxdb.add_status(&status),
+                            None => break,
+                        }
+                    }
+                    Ok(()) = influx_history.changed() => {
+                        if let Some(record) = influx_history.borrow_and_update().last() {
+                            influxdb.add_run(record);
+                        }
+                    }
+                    _ = interval.tick() => influxdb.flush().await,
+                }
+            }
+            influxdb.flush().await;
+        });
+    }
 
-    let _prometheus_handle = tokio::spawn(async move {
-        Server::run(
-            Arc::clone(&registry),
-            SocketAddr::from(([0; 4], 8081)),
-            shutdown_prometheus_clone.notified(),
-        )
-        .await
-    });
+    if let Some(mqtt) = mqtt {
+        let topic = mqtt::machine_topic(&config.mqtt, config.label.as_deref());
+        let mut mqtt_status = machine_rx.clone();
+        let mut mqtt_state = state.subscribe();
+        let mut mqtt_history = history.subscribe();
+        let period = time::Duration::from_secs(config.mqtt.interval_seconds.into());
+        let discovery = config.mqtt.discovery.then(|| {
+            homeassistant::discovery(
+                &config.mqtt,
+                config.label.as_deref(),
+                &topic,
+                &mqtt.availability_topic,
+            )
+        });
+        tokio::spawn(async move {
+            for (topic, payload) in discovery.into_iter().flatten() {
+                mqtt.announce(topic, payload).await;
+            }
+            // The status is published at most once per interval, and the
+            // state and the pump runs as they change.
+            let mut interval = time::interval(period);
+            let mut last_state = mqtt_state.borrow().state().name();
+            mqtt.publish(format!("{}/state", topic), last_state, true);
+            let mut last_run = None;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        if !mqtt_status.has_changed().unwrap_or(false) {
+                            continue;
+                        }
+                        if let Some(status) = *mqtt_status.borrow_and_update() {
+                            let payload = serde_json::to_vec(&status).unwrap();
+                            mqtt.publish(format!("{}/status", topic), payload, true);
+                        }
+                    }
+                    Ok(()) = mqtt_state.changed() => {
+                        let state = mqtt_state.borrow_and_update().state().name();
+                        if state != last_state {
+                            last_state = state;
+                            mqtt.publish(format!("{}/state", topic), state, true);
+                        }
+                    }
+                    Ok(()) = mqtt_history.changed() => {
+                        let payload = match mqtt_history.borrow_and_update().last() {
+                            Some(record) if last_run.as_ref() != Some(&record.started) => {
+                                last_run = Some(record.started.clone());
+                                serde_json::to_vec(record).unwrap()
+                            }
+                            _ => continue,
+                        };
+                        mqtt.publish(format!("{}/pump_run", topic), payload, false);
+                    }
+                }
+            }
+        });
+    }
 
+    let protocol = config.profile.protocol();
+    let serial_config = config_rx.clone();
+    let serial_state = Arc::clone(&state);
+    let serial_health = Arc::clone(&health);
     let _serial_handle = tokio::spawn(async move {
-        while let Some(line_result) = reader.next().await {
-            let line = line_result.expect("Failed to read line");
-            println!("{}", line);
-            // Parse the line we read from Mara X.
-
-            let pump_was_running = pump_running.load(Ordering::SeqCst);
-            match parse_line_and_update_metrics(&line, &metrics) {
-                Ok(pump_on) => {
-                    pump_running.store(pump_on, Ordering::SeqCst);
-
-                    if pump_on && !pump_was_running {
-                        start_pump.notify_one();
+        let state = serial_state;
+        let mut parse_failures = 0;
+        // When the last good status line arrived. Until the first one, the
+        // display tells that it's waiting for the machine.
+        let mut last_line: Option<time::Instant> = None;
+        loop {
+            serial_health.serial_alive(last_line.is_some_and(|line| line.elapsed() < stale_time));
+            let next = time::timeout(stale_time, reader.next()).await;
+            let stale = last_line.filter(|line| line.elapsed() >= stale_time);
+            // The state is borrowed apart from the match, as it's changed in
+            // the branch.
+            let was_stale = state.borrow().is_stale();
+            if let (Some(last_line), false) = (stale, was_stale) {
+                // Stop a running shot at the last line, instead of letting
+                // the timer run until the maximum time.
+                println!(
+                    "{}No data from the Mara X for {} seconds",
+                    prefix,
+                    stale_time.as_secs()
+                );
+                metrics.stale_data.set(1);
+                transition(&state, state::Event::DataLost);
+                shot_clock.lock().unwrap().stop(last_line);
+                let texts = serial_config.borrow().lang.texts();
+                errors_tx.send_replace(Some(texts.no_data.to_string()));
+            }
+            let line_result = match next {
+                Ok(Some(line_result)) => line_result,
+                Ok(None) => break,
+                Err(_) => continue,
+            };
+            let line = match line_result {
+                Ok(line) => line,
+                Err(e) => {
+                    println!("{}Serial port error: {}", prefix, e);
+                    // The port is opened again after every error.
+                    metrics.serial_reconnects.inc();
+                    if e.kind() == io::ErrorKind::InvalidData {
+                        metrics.discarded_data.inc();
+                    }
+                    let texts = serial_config.borrow().lang.texts();
+                    errors_tx.send_replace(Some(texts.serial_port.to_string()));
+                    continue;
+                }
+            };
+            // The shot is timed from when the line arrived.
+            let received = time::Instant::now();
+            metrics.lines_received.inc();
+            // The invalid bytes were replaced when the line was decoded.
+            if line.contains(char::REPLACEMENT_CHARACTER) {
+                metrics.utf8_errors.inc();
+            }
+
+            match protocol.parse(&line) {
+                Ok(status) => {
+                    metrics.lines_parsed.inc();
+                    metrics.last_sample.set(unix_time());
+                    machine_tx.send_replace(Some(status));
+                    parse_failures = 0;
+                    last_line = Some(received);
+                    metrics.stale_data.set(0);
+                    transition(&state, state::Event::DataRestored);
+                    errors_tx.send_if_modified(|error| error.take().is_some());
+                    let off_samples = serial_config.borrow().timer.pump_off_samples;
+                    let started =
+                        shot_clock
+                            .lock()
+                            .unwrap()
+                            .update(status.pump_on, received, off_samples);
+                    if started {
+                        transition(&state, state::Event::PumpStarted);
+                    }
+                    // Nobody listening is fine.
+                    let _ = status_tx.send(status);
+                }
+                Err(e) => {
+                    println!("{}Couldn't parse line: {}", prefix, line);
+                    metrics.lines_failed.inc();
+                    if e.is::<OutOfRange>() {
+                        // Keep the corrupted values out of the metrics, but
+                        // count them.
+                        println!("{}{}", prefix, e);
+                        metrics.out_of_range.inc();
+                    }
+                    // A garbled line now and then is normal, but not a
+                    // stream of them.
+                    parse_failures += 1;
+                    if parse_failures == PARSE_FAILURE_LIMIT {
+                        let texts = serial_config.borrow().lang.texts();
+                        errors_tx.send_replace(Some(texts.bad_data.to_string()));
                     }
                 }
-                _ => println!("Couldn't parse line: {}", line),
             }
         }
+        println!("{}Mara X line stream ended", prefix);
     });
 
-    let _pump_handle = tokio::spawn(async move {
+    let pump_history = Arc::clone(&history);
+    let pump_status = machine_rx.clone();
+    let pump_health = Arc::clone(&health);
+    let pump = tokio::spawn(async move {
         run_pump(
             disp,
-            start_pump_clone,
-            pump_running_clone,
-            pump_loop_exit_clone,
+            shot_clock_clone,
+            config_rx,
+            metrics_clone,
+            updates,
+            wake,
+            errors_rx,
+            config_tx,
+            config_path,
+            buttons_rx,
+            animation,
+            pump_history,
+            state_clone,
+            pump_status,
+            pump_health,
         )
         .await
     });
 
-    // Let the pump function control the server shutdown, so that we leave
-    // the screen in a known state.
-    _pump_handle.await.unwrap();
+    Machine {
+        pump,
+        shot_clock: shot_clock_http,
+        screen: screen_rx,
+        history,
+        status: machine_rx,
+        state,
+        health,
+    }
+}
+
+/// Run the main machine from `readers[0]`, and the additional ones of the
+/// configuration from the rest.
+async fn run(args: cli::Args, config: config::Config, mut readers: Vec<source::LineStream>) {
+    if let Some(path) = &args.record {
+        let main = readers.remove(0);
+        let main = source::record(main, path).expect("Failed to open the recording file");
+        readers.insert(0, main);
+    }
+
+    let shutdown_prometheus = Arc::new(Notify::new());
+    let shutdown_prometheus_clone = Arc::clone(&shutdown_prometheus);
+
+    let config_path = args.config.clone();
+    let (config_tx, config_rx) = watch::channel(config.clone());
+    let config_tx = Arc::new(config_tx);
+
+    // Start publishing Mara X values to the Prometheus endpoint and serving
+    // the API
+
+    let registry = Arc::new(Registry::new());
+    if config.metrics.host {
+        let host = host::HostMetrics::new(|classic, conventional, help| {
+            metric_opts(&config.metrics, classic, conventional, help)
+        })
+        .expect("Failed host metrics.");
+        registry
+            .register(Box::new(host))
+            .expect("Failed registering the host metrics.");
+    }
+    let mqtt = mqtt::start(&config.mqtt);
+    let mut machines = Vec::new();
+    for (index, reader) in readers.into_iter().enumerate() {
+        let machine_rx = match index {
+            0 => config_rx.clone(),
+            _ => machine_config(config_rx.clone(), index - 1),
+        };
+        machines.push(start_machine(
+            machine_rx,
+            reader,
+            &registry,
+            Arc::clone(&config_tx),
+            config_path.clone(),
+            mqtt.clone(),
+        ));
+    }
+
+    let _signal_handle = tokio::spawn(handle_signals(
+        args,
+        Arc::clone(&config_tx),
+        machines.iter().map(|m| Arc::clone(&m.state)).collect(),
+        shutdown_prometheus,
+    ));
+
+    if config.otlp.endpoint.is_some() {
+        tokio::spawn(otlp::export(Arc::clone(&registry), config.otlp.clone()));
+    }
+
+    // The API is about the main machine, while the metrics cover them all.
+    let main = &machines[0];
+    let http_state = Arc::new(http::HttpState {
+        registry,
+        config: config_tx,
+        screen: main.screen.clone(),
+        history: Arc::clone(&main.history),
+        machine: main.status.clone(),
+        state: Arc::clone(&main.state),
+        shot_clock: Arc::clone(&main.shot_clock),
+        health: machines.iter().map(|m| Arc::clone(&m.health)).collect(),
+    });
+    let metrics_address = config.metrics.address;
+    let _prometheus_handle = tokio::spawn(async move {
+        http::serve(
+            metrics_address,
+            http_state,
+            shutdown_prometheus_clone.notified(),
+        )
+        .await
+    });
+
+    // Let the pump functions control the server shutdown, so that we leave
+    // the screens in a known state.
+    for machine in machines {
+        machine.pump.await.unwrap();
+    }
+    if let Some(mqtt) = mqtt {
+        mqtt.close().await;
+    }
 }
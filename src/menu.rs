@@ -0,0 +1,297 @@
+//! Menu for changing the settings at the machine with buttons or a rotary
+//! encoder.
+
+use std::{error::Error, fs, path::Path};
+
+use toml_edit::{table, value, Array, DocumentMut, Item as TomlItem};
+
+use crate::config::{Brightness, Config, Page};
+
+/// A press of a menu button, or a step of the rotary encoder.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Button {
+    Select,
+    Up,
+    Down,
+}
+
+const BRIGHTNESS_LEVELS: [Brightness; 5] = [
+    Brightness::Dimmest,
+    Brightness::Dim,
+    Brightness::Normal,
+    Brightness::Bright,
+    Brightness::Brightest,
+];
+
+const PAGES: [Page; 9] = [
+    Page::Temperatures,
+    Page::LastShot,
+    Page::ShotCount,
+    Page::Clock,
+    Page::SteamDelta,
+    Page::TotalShots,
+    Page::AverageShot,
+    Page::MedianShot,
+    Page::Animation,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Item {
+    TargetTime,
+    Brightness,
+    /// Whether the page is shown on the idle screen.
+    Page(Page),
+    Exit,
+}
+
+impl Item {
+    /// The items of the menu. The animation page is left out without the
+    /// frames to show.
+    fn all(config: &Config) -> Vec<Item> {
+        let mut items = vec![Item::TargetTime, Item::Brightness];
+        items.extend(
+            PAGES
+                .iter()
+                .filter(|page| **page != Page::Animation || config.display.animation_dir.is_some())
+                .map(|page| Item::Page(*page)),
+        );
+        items.push(Item::Exit);
+        items
+    }
+
+    // The labels are short enough for the 16 character LCDs.
+    fn text(&self, config: &Config) -> String {
+        match self {
+            Item::TargetTime => match config.timer.target_seconds {
+                0 => "Target: off".to_string(),
+                seconds => format!("Target: {}s", seconds),
+            },
+            Item::Brightness => format!("Bright: {}", config.display.brightness.name()),
+            Item::Page(page) => {
+                let label = match page {
+                    Page::Temperatures => "Temps",
+                    Page::LastShot => "Last shot",
+                    Page::ShotCount => "Shots",
+                    Page::Clock => "Clock",
+                    Page::SteamDelta => "Delta",
+                    Page::TotalShots => "Total",
+                    Page::AverageShot => "Average",
+                    Page::MedianShot => "Median",
+                    Page::Animation => "Animation",
+                };
+                let shown = config.display.pages.contains(page);
+                format!("{}: {}", label, if shown { "on" } else { "off" })
+            }
+            Item::Exit => "Exit".to_string(),
+        }
+    }
+
+    /// Change the setting one step up or down. Returns true if it changed.
+    fn adjust(&self, config: &mut Config, up: bool) -> bool {
+        match self {
+            Item::TargetTime => {
+                let target = &mut config.timer.target_seconds;
+                let new = if up {
+                    (*target + 1).min(config.timer.max_seconds)
+                } else {
+                    target.saturating_sub(1)
+                };
+                let changed = new != *target;
+                *target = new;
+                changed
+            }
+            Item::Brightness => {
+                let brightness = &mut config.display.brightness;
+                let current = BRIGHTNESS_LEVELS
+                    .iter()
+                    .position(|level| level == brightness)
+                    .unwrap_or(2);
+                let new = if up {
+                    (current + 1).min(BRIGHTNESS_LEVELS.len() - 1)
+                } else {
+                    current.saturating_sub(1)
+                };
+                *brightness = BRIGHTNESS_LEVELS[new];
+                new != current
+            }
+            Item::Page(page) => {
+                let pages = &mut config.display.pages;
+                match pages.iter().position(|p| p == page) {
+                    // The idle screen needs at least one page.
+                    Some(_) if pages.len() == 1 => false,
+                    Some(i) => {
+                        pages.remove(i);
+                        true
+                    }
+                    None => {
+                        pages.push(*page);
+                        true
+                    }
+                }
+            }
+            Item::Exit => false,
+        }
+    }
+}
+
+/// State of the open menu. The up and down buttons move between the items,
+/// and the select button starts and ends changing the selected setting.
+pub struct Menu {
+    items: Vec<Item>,
+    selected: usize,
+    editing: bool,
+    closed: bool,
+    changed: bool,
+}
+
+impl Menu {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            items: Item::all(config),
+            selected: 0,
+            editing: false,
+            closed: false,
+            changed: false,
+        }
+    }
+
+    /// Handle a button press. The changes are made to `config` right away.
+    /// Returns true if the configuration changed.
+    pub fn press(&mut self, button: Button, config: &mut Config) -> bool {
+        let item = self.items[self.selected];
+        match (button, self.editing) {
+            (Button::Select, _) if item == Item::Exit => {
+                self.closed = true;
+                false
+            }
+            (Button::Select, editing) => {
+                self.editing = !editing;
+                false
+            }
+            (Button::Up, false) => {
+                self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+                false
+            }
+            (Button::Down, false) => {
+                self.selected = (self.selected + 1) % self.items.len();
+                false
+            }
+            (button, true) => {
+                let changed = item.adjust(config, button == Button::Up);
+                self.changed |= changed;
+                changed
+            }
+        }
+    }
+
+    /// Returns true once the exit item has been selected.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns true if any setting was changed while the menu was open.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Lines to show on the display: the title, the selected item, and the
+    /// items after it as far as there is room. The selected item is marked
+    /// with ">", or with "*" while it's being changed.
+    pub fn lines(&self, config: &Config) -> Vec<String> {
+        let mut lines = vec!["Menu".to_string()];
+        for (i, item) in self.items.iter().enumerate().skip(self.selected) {
+            let mark = match i == self.selected {
+                true if self.editing => '*',
+                true => '>',
+                false => ' ',
+            };
+            lines.push(format!("{} {}", mark, item.text(config)));
+        }
+        lines
+    }
+}
+
+// The section of the configuration file, added if the file doesn't have it.
+fn section<'a>(document: &'a mut DocumentMut, name: &str) -> &'a mut TomlItem {
+    document.entry(name).or_insert(table())
+}
+
+/// Write the settings which can be changed in the menu to the configuration
+/// file, keeping the rest of the file as it is. Nothing is written if the
+/// settings aren't valid, so that the file can still be loaded.
+pub fn save(path: &Path, config: &Config) -> Result<(), Box<dyn Error>> {
+    config.validate()?;
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let mut document: DocumentMut = contents
+        .parse()
+        .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+
+    let timer = section(&mut document, "timer");
+    timer["target_seconds"] = value(i64::from(config.timer.target_seconds));
+    let display = section(&mut document, "display");
+    display["brightness"] = value(config.display.brightness.name());
+    let pages: Array = config
+        .display
+        .pages
+        .iter()
+        .map(|page| page.name())
+        .collect();
+    display["pages"] = value(pages);
+
+    fs::write(path, document.to_string())
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "marax-shot-timer-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn animation_needs_frames() {
+        let mut config = Config::default();
+        assert!(!Menu::new(&config)
+            .items
+            .contains(&Item::Page(Page::Animation)));
+        config.display.animation_dir = Some("/tmp".to_string());
+        assert!(Menu::new(&config)
+            .items
+            .contains(&Item::Page(Page::Animation)));
+    }
+
+    #[test]
+    fn save_keeps_the_rest() {
+        let path = temp_file("save", "# Kept\n[timer]\nmax_seconds = 60\n");
+        let mut config = Config::default();
+        config.timer.target_seconds = 28;
+        config.display.pages = vec![Page::Clock];
+        save(&path, &config).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(saved.starts_with("# Kept\n"));
+        assert!(saved.contains("max_seconds = 60"));
+        assert!(saved.contains("target_seconds = 28"));
+        assert!(saved.contains("pages = [\"clock\"]"));
+    }
+
+    #[test]
+    fn invalid_settings_are_not_saved() {
+        let path = temp_file("invalid", "[display]\npages = [\"clock\"]\n");
+        let mut config = Config::default();
+        config.display.pages = vec![Page::Animation];
+        assert!(save(&path, &config).is_err());
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved, "[display]\npages = [\"clock\"]\n");
+    }
+}
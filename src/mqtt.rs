@@ -0,0 +1,404 @@
+//! Publishing to an MQTT broker, the usual way into home automation. Only
+//! what's needed for publishing with QoS 0 is implemented, following MQTT
+//! 3.1.1.
+
+use std::{error::Error, io};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Duration};
+
+use crate::config::MqttConfig;
+
+const DEFAULT_PORT: u16 = 1883;
+
+// The broker drops the connection if nothing is sent in this time, so a
+// ping is sent when there's nothing else to.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+// How long connecting can take, and the wait before trying again.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// How long the exit waits for the queued messages to be sent.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Messages waiting while the broker is slow or away. The newer ones are
+// dropped when it's full.
+const QUEUE_SIZE: usize = 64;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+enum Message {
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+    },
+    /// A retained message which is published again on every connection.
+    Announce {
+        topic: String,
+        payload: Vec<u8>,
+    },
+    Close(oneshot::Sender<()>),
+}
+
+/// Handle for publishing through the connection to the broker.
+#[derive(Clone)]
+pub struct Publisher {
+    queue: mpsc::Sender<Message>,
+    /// Where the connection tells whether the timer is online.
+    pub availability_topic: String,
+}
+
+impl Publisher {
+    /// Queue a message to `topic`. It's dropped if the queue is full.
+    pub fn publish(&self, topic: String, payload: impl Into<Vec<u8>>, retain: bool) {
+        let message = Message::Publish {
+            topic,
+            payload: payload.into(),
+            retain,
+        };
+        if self.queue.try_send(message).is_err() {
+            println!("MQTT queue is full, dropping a message");
+        }
+    }
+
+    /// Publish a retained message to `topic`, and again whenever the
+    /// connection is opened, so that it's there even if the broker forgets
+    /// the retained messages on a restart.
+    pub async fn announce(&self, topic: String, payload: impl Into<Vec<u8>>) {
+        let message = Message::Announce {
+            topic,
+            payload: payload.into(),
+        };
+        // Waits for room instead of dropping, as it's sent only once.
+        let _ = self.queue.send(message).await;
+    }
+
+    /// Tell that the timer goes offline and disconnect, after the queued
+    /// messages are sent.
+    pub async fn close(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.queue.send(Message::Close(done_tx)).await.is_ok() {
+            let _ = time::timeout(CLOSE_TIMEOUT, done_rx).await;
+        }
+    }
+}
+
+/// The prefix of the topics of the machine labeled `machine`. The wildcards
+/// and levels in the label are replaced.
+pub fn machine_topic(config: &MqttConfig, machine: Option<&str>) -> String {
+    match machine {
+        Some(machine) => format!("{}/{}", config.topic, machine.replace(['+', '#', '/'], "_")),
+        None => config.topic.clone(),
+    }
+}
+
+/// Start publishing to the broker of the configuration, if there is one.
+/// The connection is opened again whenever it's lost.
+pub fn start(config: &MqttConfig) -> Option<Publisher> {
+    let broker = config.broker.clone()?;
+    let (queue, messages) = mpsc::channel(QUEUE_SIZE);
+    let publisher = Publisher {
+        queue,
+        availability_topic: format!("{}/availability", config.topic),
+    };
+    let config = config.clone();
+    let availability = publisher.availability_topic.clone();
+    tokio::spawn(run(broker, config, availability, messages));
+    Some(publisher)
+}
+
+async fn run(
+    broker: String,
+    config: MqttConfig,
+    availability: String,
+    mut messages: mpsc::Receiver<Message>,
+) {
+    let mut announcements = Vec::new();
+    let address = if broker
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        broker
+    } else {
+        format!("{}:{}", broker, DEFAULT_PORT)
+    };
+    loop {
+        match time::timeout(CONNECT_TIMEOUT, connect(&address, &config, &availability)).await {
+            Ok(Ok(mut stream)) => {
+                println!("Connected to the MQTT broker at {}", address);
+                let served = serve(
+                    &mut stream,
+                    &availability,
+                    &mut messages,
+                    &mut announcements,
+                );
+                match served.await {
+                    Ok(()) => return,
+                    Err(e) => println!("MQTT connection to {} lost: {}", address, e),
+                }
+            }
+            Ok(Err(e)) => println!("Failed to connect to the MQTT broker at {}: {}", address, e),
+            Err(_) => println!(
+                "Failed to connect to the MQTT broker at {}: timed out",
+                address
+            ),
+        }
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Append the remaining length of a packet to `packet`.
+fn put_length(packet: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+/// Append a string or binary data with its length to `body`.
+fn put_bytes(body: &mut Vec<u8>, bytes: &[u8]) {
+    body.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    body.extend_from_slice(bytes);
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    put_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+    put_bytes(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH | u8::from(retain), &body)
+}
+
+/// Read a packet, returning its type and body.
+async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<(u8, Vec<u8>)> {
+    let kind = stream.read_u8().await?;
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        let byte = stream.read_u8().await?;
+        length |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await?;
+            return Ok((kind, body));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "bad packet length",
+    ))
+}
+
+/// Connect to the broker, with a will which tells that the timer went
+/// offline, and tell that it's online.
+async fn connect(
+    address: &str,
+    config: &MqttConfig,
+    availability: &str,
+) -> Result<TcpStream, Box<dyn Error + Send + Sync>> {
+    let mut stream = TcpStream::connect(address).await?;
+
+    // Clean session, and a retained will.
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+    let mut body = Vec::new();
+    put_bytes(&mut body, b"MQTT");
+    body.push(4);
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    put_bytes(&mut body, config.client_id.as_bytes());
+    put_bytes(&mut body, availability.as_bytes());
+    put_bytes(&mut body, b"offline");
+    if let Some(username) = &config.username {
+        put_bytes(&mut body, username.as_bytes());
+    }
+    if let Some(password) = &config.password {
+        put_bytes(&mut body, password.as_bytes());
+    }
+    stream.write_all(&packet(CONNECT, &body)).await?;
+
+    match read_packet(&mut stream).await? {
+        (CONNACK, body) if body.len() == 2 => match body[1] {
+            0 => {}
+            4 | 5 => return Err("the broker refused the username or the password".into()),
+            code => return Err(format!("the broker refused the connection ({})", code).into()),
+        },
+        _ => return Err("unexpected answer from the broker".into()),
+    }
+    stream
+        .write_all(&publish_packet(availability, b"online", true))
+        .await?;
+    Ok(stream)
+}
+
+/// Send the announcements made so far, and then the queued messages until
+/// told to close, pinging the broker when there's nothing to send.
+async fn serve(
+    stream: &mut TcpStream,
+    availability: &str,
+    messages: &mut mpsc::Receiver<Message>,
+    announcements: &mut Vec<(String, Vec<u8>)>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (mut reader, mut writer) = stream.split();
+    for (topic, payload) in announcements.iter() {
+        writer
+            .write_all(&publish_packet(topic, payload, true))
+            .await?;
+    }
+    let mut ping = time::interval_at(time::Instant::now() + KEEP_ALIVE, KEEP_ALIVE);
+    let mut buffer = [0; 64];
+    loop {
+        tokio::select! {
+            message = messages.recv() => match message {
+                Some(Message::Publish { topic, payload, retain }) => {
+                    writer.write_all(&publish_packet(&topic, &payload, retain)).await?;
+                    ping.reset();
+                }
+                Some(Message::Announce { topic, payload }) => {
+                    let packet = publish_packet(&topic, &payload, true);
+                    announcements.push((topic, payload));
+                    writer.write_all(&packet).await?;
+                    ping.reset();
+                }
+                Some(Message::Close(done)) => {
+                    writer.write_all(&publish_packet(availability, b"offline", true)).await?;
+                    writer.write_all(&packet(DISCONNECT, &[])).await?;
+                    let _ = done.send(());
+                    return Ok(());
+                }
+                None => return Ok(()),
+            },
+            _ = ping.tick() => writer.write_all(&packet(PINGREQ, &[])).await?,
+            // Nothing but the ping responses is expected from the broker,
+            // so what it sends is only read to notice when it goes away.
+            read = reader.read(&mut buffer) => {
+                if read? == 0 {
+                    return Err("the broker closed the connection".into());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn remaining_length() {
+        // The examples of the MQTT 3.1.1 specification.
+        let cases: Vec<(usize, Vec<u8>)> = vec![
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (16383, vec![0xff, 0x7f]),
+            (16384, vec![0x80, 0x80, 0x01]),
+            (2097151, vec![0xff, 0xff, 0x7f]),
+        ];
+        for (length, encoded) in cases {
+            let mut packet = Vec::new();
+            put_length(&mut packet, length);
+            assert_eq!(packet, encoded, "{}", length);
+        }
+    }
+
+    #[test]
+    fn publish() {
+        assert_eq!(
+            publish_packet("a/b", b"on", true),
+            vec![0x31, 7, 0, 3, b'a', b'/', b'b', b'o', b'n']
+        );
+        assert_eq!(publish_packet("a", b"", false), vec![0x30, 3, 0, 1, b'a']);
+    }
+
+    #[tokio::test]
+    async fn read_long_packet() {
+        let payload = vec![b'x'; 200];
+        let packet = publish_packet("t", &payload, false);
+        let (kind, body) = read_packet(&mut packet.as_slice()).await.unwrap();
+        assert_eq!(kind, PUBLISH);
+        assert_eq!(body.len(), 203);
+        assert_eq!(&body[3..], payload.as_slice());
+    }
+
+    #[tokio::test]
+    async fn bad_packet_length() {
+        let packet = [PUBLISH, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(read_packet(&mut &packet[..]).await.is_err());
+    }
+
+    /// Accept one connection, read its CONNECT and answer with `code`. Then
+    /// read the next packet if the connection was accepted.
+    async fn broker(code: u8) -> (String, tokio::task::JoinHandle<(Vec<u8>, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (kind, body) = read_packet(&mut stream).await.unwrap();
+            assert_eq!(kind, CONNECT);
+            stream.write_all(&[CONNACK, 2, 0, code]).await.unwrap();
+            if code != 0 {
+                return (body, Vec::new());
+            }
+            let (kind, published) = read_packet(&mut stream).await.unwrap();
+            assert_eq!(kind, PUBLISH | 1);
+            (body, published)
+        });
+        (address, handle)
+    }
+
+    #[tokio::test]
+    async fn connect_with_will_and_login() {
+        let (address, broker) = broker(0).await;
+        let config = MqttConfig {
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            ..MqttConfig::default()
+        };
+        connect(&address, &config, "marax/availability")
+            .await
+            .unwrap();
+        let (body, published) = broker.await.unwrap();
+        assert_eq!(&body[..7], b"\0\x04MQTT\x04");
+        // Clean session, a retained will, the username and the password.
+        assert_eq!(body[7], 0x02 | 0x04 | 0x20 | 0x80 | 0x40);
+        assert_eq!(&body[8..10], &30u16.to_be_bytes());
+        assert_eq!(&published[2..], b"marax/availabilityonline");
+    }
+
+    #[tokio::test]
+    async fn refused_login() {
+        let (address, _broker) = broker(5).await;
+        let error = connect(&address, &MqttConfig::default(), "marax/availability")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("username or the password"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Pushing the metrics to an OpenTelemetry collector over OTLP/HTTP, for
+//! setups which don't scrape the timer.
+
+use hyper::{header, Body, Client, Method, Request};
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use prometheus::Registry;
+use serde_json::{json, Value};
+
+use std::{
+    error::Error,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::time::{self, Duration};
+
+use crate::config::OtlpConfig;
+
+// How long a push can take before it's given up.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn unix_nanos() -> String {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos())
+        .to_string()
+}
+
+fn attributes(metric: &Metric) -> Vec<Value> {
+    metric
+        .get_label()
+        .iter()
+        .map(|label| {
+            json!({
+                "key": label.get_name(),
+                "value": { "stringValue": label.get_value() },
+            })
+        })
+        .collect()
+}
+
+/// Convert a metric family to an OTLP metric, or None if OTLP has no type
+/// for it. The 64-bit integers are strings in the JSON encoding.
+fn convert(family: &MetricFamily, start: &str, now: &str) -> Option<Value> {
+    let metrics = family.get_metric();
+    let data = match family.get_field_type() {
+        MetricType::GAUGE | MetricType::UNTYPED => {
+            let points: Vec<Value> = metrics
+                .iter()
+                .map(|m| {
+                    let value = match family.get_field_type() {
+                        MetricType::GAUGE => m.get_gauge().get_value(),
+                        _ => m.get_untyped().get_value(),
+                    };
+                    json!({
+                        "attributes": attributes(m),
+                        "timeUnixNano": now,
+                        "asDouble": value,
+                    })
+                })
+                .collect();
+            json!({ "gauge": { "dataPoints": points } })
+        }
+        MetricType::COUNTER => {
+            let points: Vec<Value> = metrics
+                .iter()
+                .map(|m| {
+                    json!({
+                        "attributes": attributes(m),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": now,
+                        "asDouble": m.get_counter().get_value(),
+                    })
+                })
+                .collect();
+            // Cumulative, as the counters are never reset.
+            json!({
+                "sum": {
+                    "dataPoints": points,
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                }
+            })
+        }
+        MetricType::HISTOGRAM => {
+            let points: Vec<Value> = metrics
+                .iter()
+                .map(|m| {
+                    let histogram = m.get_histogram();
+                    let buckets = histogram.get_bucket();
+                    // Prometheus counts the buckets cumulatively, OTLP one
+                    // by one with the count above the last bound at the end.
+                    let mut counts = Vec::new();
+                    let mut below = 0;
+                    for bucket in buckets {
+                        counts.push((bucket.get_cumulative_count() - below).to_string());
+                        below = bucket.get_cumulative_count();
+                    }
+                    counts.push((histogram.get_sample_count() - below).to_string());
+                    let bounds: Vec<f64> = buckets.iter().map(|b| b.get_upper_bound()).collect();
+                    json!({
+                        "attributes": attributes(m),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": now,
+                        "count": histogram.get_sample_count().to_string(),
+                        "sum": histogram.get_sample_sum(),
+                        "bucketCounts": counts,
+                        "explicitBounds": bounds,
+                    })
+                })
+                .collect();
+            json!({
+                "histogram": {
+                    "dataPoints": points,
+                    "aggregationTemporality": 2,
+                }
+            })
+        }
+        MetricType::SUMMARY => return None,
+    };
+    let mut metric = json!({
+        "name": family.get_name(),
+        "description": family.get_help(),
+    });
+    metric.as_object_mut()?.extend(data.as_object()?.clone());
+    Some(metric)
+}
+
+/// The OTLP export request of all the metrics in the registry.
+fn export_request(registry: &Registry, start: &str) -> Value {
+    let now = unix_nanos();
+    let metrics: Vec<Value> = registry
+        .gather()
+        .iter()
+        .filter_map(|family| convert(family, start, &now))
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": env!("CARGO_PKG_NAME") },
+                }],
+            },
+            "scopeMetrics": [{
+                "scope": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+async fn push(
+    client: &Client<hyper::client::HttpConnector>,
+    endpoint: &str,
+    config: &OtlpConfig,
+    body: Value,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(endpoint)
+        .header(header::CONTENT_TYPE, "application/json");
+    for (name, value) in &config.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let request = request.body(Body::from(body.to_string()))?;
+    let response = time::timeout(PUSH_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| "timed out")??;
+    if !response.status().is_success() {
+        return Err(format!("the collector answered {}", response.status()).into());
+    }
+    Ok(())
+}
+
+/// Push the metrics in `registry` to the collector every interval. A failed
+/// push is logged, and the next one is tried as usual.
+pub async fn export(registry: Arc<Registry>, config: OtlpConfig) {
+    let endpoint = match &config.endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => return,
+    };
+    let client = Client::new();
+    let start = unix_nanos();
+    let period = Duration::from_secs(config.interval_seconds.into());
+    let mut interval = time::interval_at(time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        let body = export_request(&registry, &start);
+        if let Err(e) = push(&client, &endpoint, &config, body).await {
+            println!("OTLP export to {} failed: {}", endpoint, e);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use serde::Deserialize;
+
+use std::{ops::RangeInclusive, str::FromStr};
+
+use crate::protocol::MachineProtocol;
+
+/// Positions of the values in the comma separated status line.
+pub struct FieldMap {
+    pub mode: usize,
+    pub steam_temperature: usize,
+    pub target_steam_temperature: usize,
+    pub hx_temperature: usize,
+    pub countdown_boost_mode: usize,
+    pub heating_element_on: usize,
+    pub pump_on: usize,
+}
+
+/// The longer status line of the newer firmware, which has the same fields
+/// as the older one followed by a few more.
+pub struct ExtendedFormat {
+    /// Start of the firmware version after the mode letter, as in "C2.03".
+    pub version_prefix: &'static str,
+    /// Number of comma separated tokens in a status line.
+    pub tokens: usize,
+    /// The shot timer of the machine itself, in seconds.
+    pub shot_timer: usize,
+    /// Water reservoir empty (1) or not (0).
+    pub water_low: usize,
+}
+
+/// Description of the status line format of one machine model.
+pub struct ProfileSpec {
+    /// Number of comma separated tokens in a status line.
+    pub tokens: usize,
+    pub fields: FieldMap,
+    /// The format of the newer firmware, told apart by the version.
+    pub extended: Option<ExtendedFormat>,
+    /// Plausible boiler temperatures in Celsius.
+    pub steam_temperature: RangeInclusive<i64>,
+    /// Plausible heat exchanger temperatures in Celsius.
+    pub hx_temperature: RangeInclusive<i64>,
+    /// Plausible boost mode countdowns.
+    pub countdown_boost_mode: RangeInclusive<i64>,
+}
+
+// "C1.19,116,124,095,0560,0,0"
+const MARAX_FIELDS: FieldMap = FieldMap {
+    mode: 0,
+    steam_temperature: 1,
+    target_steam_temperature: 2,
+    hx_temperature: 3,
+    countdown_boost_mode: 4,
+    heating_element_on: 5,
+    pump_on: 6,
+};
+
+// "C2.03,116,124,095,0560,0,0,00,0"
+const MARAX_EXTENDED: ExtendedFormat = ExtendedFormat {
+    version_prefix: "2.",
+    tokens: 9,
+    shot_timer: 7,
+    water_low: 8,
+};
+
+const MARAX_V1: ProfileSpec = ProfileSpec {
+    tokens: 7,
+    fields: MARAX_FIELDS,
+    extended: Some(MARAX_EXTENDED),
+    steam_temperature: 0..=160,
+    hx_temperature: 0..=130,
+    countdown_boost_mode: 0..=9999,
+};
+
+const MARAX_V2: ProfileSpec = ProfileSpec {
+    tokens: 7,
+    fields: MARAX_FIELDS,
+    extended: Some(MARAX_EXTENDED),
+    steam_temperature: 0..=165,
+    hx_temperature: 0..=135,
+    countdown_boost_mode: 0..=9999,
+};
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// The original Mara X.
+    #[default]
+    #[serde(rename = "marax-v1")]
+    MaraXV1,
+    /// Mara X V2.
+    #[serde(rename = "marax-v2")]
+    MaraXV2,
+}
+
+impl Profile {
+    pub fn spec(&self) -> &'static ProfileSpec {
+        match self {
+            Profile::MaraXV1 => &MARAX_V1,
+            Profile::MaraXV2 => &MARAX_V2,
+        }
+    }
+
+    /// How the status lines of the machine are parsed.
+    pub fn protocol(&self) -> &'static dyn MachineProtocol {
+        self.spec()
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "marax-v1" => Ok(Profile::MaraXV1),
+            "marax-v2" => Ok(Profile::MaraXV2),
+            _ => Err(format!("unknown machine profile \"{}\"", s)),
+        }
+    }
+}
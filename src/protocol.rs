@@ -0,0 +1,127 @@
+//! The typed status of a machine, and the interface for parsing its status
+//! lines into it.
+
+use serde::{Serialize, Serializer};
+
+use std::{convert::TryFrom, error::Error, fmt, str::FromStr};
+
+#[derive(Serialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Coffee,
+    Steam,
+}
+
+/// The firmware version of the machine, as in "1.19" from the mode token
+/// "C1.19".
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Firmware {
+    pub major: u8,
+    pub minor: u8,
+    /// How many digits the machine wrote the minor version with, so that it
+    /// is shown the same way, as in "1.06" or "1.6".
+    pub minor_digits: u8,
+}
+
+impl FromStr for Firmware {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("bad firmware version \"{}\"", s);
+        let (major, minor) = s.split_once('.').ok_or_else(bad)?;
+        Ok(Firmware {
+            major: major.parse().map_err(|_| bad())?,
+            minor: minor.parse().map_err(|_| bad())?,
+            minor_digits: u8::try_from(minor.len()).map_err(|_| bad())?,
+        })
+    }
+}
+
+impl fmt::Display for Firmware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = usize::from(self.minor_digits);
+        write!(f, "{}.{:0width$}", self.major, self.minor, width = width)
+    }
+}
+
+impl Serialize for Firmware {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Values only the newer firmware sends.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExtendedStatus {
+    /// The shot timer of the machine itself, in seconds.
+    pub shot_timer: i64,
+    pub water_low: bool,
+}
+
+/// One status line from the machine. The metrics, the display and the HTTP
+/// API all show the latest one.
+#[derive(Serialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MachineStatus {
+    pub mode: Mode,
+    /// None if the version in the mode token can't be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<Firmware>,
+    pub steam_temperature: i64,
+    pub target_steam_temperature: i64,
+    pub hx_temperature: i64,
+    pub countdown_boost_mode: i64,
+    pub heating_element_on: bool,
+    pub pump_on: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extended: Option<ExtendedStatus>,
+}
+
+impl fmt::Display for MachineStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let on_off = |on| if on { "on" } else { "off" };
+        write!(
+            f,
+            "{} mode, steam {}/{} °C, HX {} °C, boost {}, heating {}, pump {}",
+            match self.mode {
+                Mode::Coffee => "coffee",
+                Mode::Steam => "steam",
+            },
+            self.steam_temperature,
+            self.target_steam_temperature,
+            self.hx_temperature,
+            self.countdown_boost_mode,
+            on_off(self.heating_element_on),
+            on_off(self.pump_on)
+        )?;
+        if let Some(extended) = self.extended {
+            write!(
+                f,
+                ", machine timer {} s, water {}",
+                extended.shot_timer,
+                if extended.water_low { "low" } else { "ok" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A value of a status line which is well formed, but can't be right, like
+/// from a corrupted line.
+#[derive(Debug)]
+pub struct OutOfRange(pub &'static str);
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parse error: {} out of range", self.0)
+    }
+}
+
+impl Error for OutOfRange {}
+
+/// The status line format of a machine. Machines with other controllers
+/// can be supported by implementing this, and selecting the implementation
+/// by the machine profile.
+pub trait MachineProtocol: Send + Sync {
+    fn parse(&self, line: &str) -> Result<MachineStatus, Box<dyn Error>>;
+}
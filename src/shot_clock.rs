@@ -0,0 +1,219 @@
+//! Timing the shot from the status lines instead of the display refresh.
+
+use tokio::time::{Duration, Instant};
+
+/// When the pump was started and stopped, recorded as the status lines
+/// arrive. The display only samples the elapsed time, so a slow refresh
+/// doesn't change the shot time.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ShotClock {
+    started: Option<Instant>,
+    stopped: Option<Instant>,
+    preinfusion_ended: Option<Instant>,
+    first_drip: Option<Instant>,
+    /// When the pump was first seen off while it was running, and on how
+    /// many lines in a row since.
+    off_since: Option<Instant>,
+    off_samples: u32,
+    /// The shot was started by hand, and the pump doesn't stop it.
+    manual: bool,
+}
+
+impl ShotClock {
+    /// Record a status line read at `at`. The pump has to be off on
+    /// `off_samples` lines in a row before the shot stops, at the first of
+    /// them, so that a single bad reading doesn't end it. Returns true if
+    /// the line started a shot.
+    pub fn update(&mut self, pump_on: bool, at: Instant, off_samples: u32) -> bool {
+        if self.manual && self.is_running() {
+            return false;
+        }
+        match (pump_on, self.is_running()) {
+            (true, false) => {
+                self.start(at, false);
+                return true;
+            }
+            (true, true) => {
+                self.off_since = None;
+                self.off_samples = 0;
+            }
+            (false, true) => {
+                let since = *self.off_since.get_or_insert(at);
+                self.off_samples += 1;
+                if self.off_samples >= off_samples {
+                    self.stopped = Some(since);
+                }
+            }
+            (false, false) => {}
+        }
+        false
+    }
+
+    fn start(&mut self, at: Instant, manual: bool) {
+        self.started = Some(at);
+        self.stopped = None;
+        self.preinfusion_ended = None;
+        self.first_drip = None;
+        self.off_since = None;
+        self.off_samples = 0;
+        self.manual = manual;
+    }
+
+    /// Stop a running shot at `at` without waiting for more lines, or when
+    /// the pump was first seen off if that was earlier. A shot started by
+    /// hand keeps running.
+    pub fn stop(&mut self, at: Instant) {
+        if self.is_running() && !self.manual {
+            self.stopped = Some(self.off_since.unwrap_or(at));
+        }
+    }
+
+    /// Start the timer by hand, without the pump. Returns false if a shot
+    /// is running already.
+    pub fn start_manual(&mut self, at: Instant) -> bool {
+        if self.is_running() {
+            return false;
+        }
+        self.start(at, true);
+        true
+    }
+
+    /// Stop the running shot by hand, if it was started by hand. A shot of
+    /// the pump stops only with the pump.
+    pub fn stop_manual(&mut self, at: Instant) {
+        if self.manual && self.is_running() {
+            self.stopped = Some(at);
+        }
+    }
+
+    /// Stop a shot started by hand `limit` after its start, as nothing else
+    /// stops it at the longest time the display shows.
+    pub fn stop_manual_after(&mut self, limit: Duration) {
+        if let (true, Some(started)) = (self.manual, self.started) {
+            if self.elapsed() >= limit {
+                self.stop_manual(started + limit);
+            }
+        }
+    }
+
+    /// Mark the end of the pre-infusion at `at`, unless it has been marked
+    /// already.
+    pub fn end_preinfusion(&mut self, at: Instant) {
+        if self.is_running() && self.preinfusion_ended.is_none() {
+            self.preinfusion_ended = Some(at);
+        }
+    }
+
+    /// Mark the pre-infusion ended `duration` after the start of the shot,
+    /// once that much time has passed.
+    pub fn end_preinfusion_after(&mut self, duration: Duration) {
+        if let Some(started) = self.started {
+            if self.elapsed() >= duration {
+                self.end_preinfusion(started + duration);
+            }
+        }
+    }
+
+    /// Mark the first drip from the portafilter at `at`, unless it has been
+    /// marked already.
+    pub fn mark_first_drip(&mut self, at: Instant) {
+        if self.is_running() && self.first_drip.is_none() {
+            self.first_drip = Some(at);
+        }
+    }
+
+    /// Time from the start of the shot to the first drip, once it has been
+    /// marked.
+    pub fn first_drip(&self) -> Option<Duration> {
+        let started = self.started?;
+        let first_drip = self.first_drip?;
+        Some(first_drip.saturating_duration_since(started))
+    }
+
+    /// How long the pre-infusion took, once it has ended.
+    pub fn preinfusion(&self) -> Option<Duration> {
+        let started = self.started?;
+        let ended = self.preinfusion_ended?;
+        Some(ended.saturating_duration_since(started))
+    }
+
+    /// Whether the latest shot was started by hand.
+    pub fn is_manual(&self) -> bool {
+        self.manual
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some() && self.stopped.is_none()
+    }
+
+    /// Time since the pump was started, or the length of the shot once it
+    /// has stopped.
+    pub fn elapsed(&self) -> Duration {
+        match (self.started, self.stopped) {
+            (Some(started), Some(stopped)) => stopped.saturating_duration_since(started),
+            (Some(started), None) => started.elapsed(),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_shot_stops_at_limit() {
+        let start = Instant::now();
+        let limit = Duration::from_secs(60);
+        let mut clock = ShotClock::default();
+        assert!(clock.start_manual(start - limit * 2));
+        assert!(!clock.update(true, start, 1));
+
+        clock.stop_manual_after(limit);
+        assert!(!clock.is_running());
+        assert_eq!(clock.elapsed(), limit);
+
+        // The pump starts the next shot.
+        assert!(clock.update(true, start, 1));
+        assert!(clock.is_running());
+    }
+
+    #[test]
+    fn pump_shot_is_not_stopped_by_hand() {
+        let start = Instant::now();
+        let mut clock = ShotClock::default();
+        assert!(clock.update(true, start, 1));
+        clock.stop_manual(start + Duration::from_secs(5));
+        assert!(clock.is_running());
+        assert!(!clock.start_manual(start + Duration::from_secs(6)));
+
+        // The pump line after the button doesn't start a second shot.
+        assert!(!clock.update(true, start + Duration::from_secs(7), 1));
+        assert!(!clock.update(false, start + Duration::from_secs(30), 1));
+        assert!(!clock.is_running());
+        assert_eq!(clock.elapsed(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn pump_is_ignored_during_manual_shot() {
+        let start = Instant::now();
+        let mut clock = ShotClock::default();
+        assert!(clock.start_manual(start));
+        assert!(!clock.update(true, start + Duration::from_secs(1), 1));
+        assert!(!clock.update(false, start + Duration::from_secs(2), 1));
+        assert!(clock.is_running());
+        clock.stop_manual(start + Duration::from_secs(3));
+        assert!(!clock.is_running());
+        assert_eq!(clock.elapsed(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn pump_shot_runs_past_limit() {
+        let start = Instant::now();
+        let limit = Duration::from_secs(60);
+        let mut clock = ShotClock::default();
+        assert!(clock.update(true, start - limit * 2, 1));
+        clock.stop_manual_after(limit);
+        assert!(clock.is_running());
+    }
+}
@@ -0,0 +1,129 @@
+//! A pretend Mara X, for trying the program out without a machine.
+
+// The machine starts from the room temperature.
+const ROOM_TEMPERATURE: f32 = 25.0;
+// The steam boiler target temperature in the coffee mode.
+const TARGET_STEAM_TEMPERATURE: f32 = 124.0;
+// Steam boiler temperature change per second with the heating element on
+// and off, and the extra cooling while the pump runs.
+const HEATING_RATE: f32 = 0.5;
+const COOLING_RATE: f32 = 0.05;
+const PUMP_COOLING_RATE: f32 = 0.15;
+// The heat exchanger settles at this share of the steam temperature, and is
+// cooled by the fresh water while the pump runs.
+const HX_SHARE: f32 = 0.76;
+const HX_PUMP_COOLING_RATE: f32 = 0.4;
+// Length of the boost mode after the first heat-up, in seconds.
+const BOOST_SECONDS: u32 = 1500;
+// Time from the end of one pump run to the start of the next one.
+const PUMP_PAUSE: u32 = 90;
+// Shots last from 20 to 35 seconds, and every fourth pump run is a short
+// cooling flush instead.
+const SHOT_SECONDS: std::ops::RangeInclusive<u32> = 20..=35;
+const FLUSH_SECONDS: u32 = 4;
+const FLUSH_EVERY: u32 = 4;
+
+/// The state of the pretend machine, moved on one second at a time.
+pub struct Simulator {
+    steam_temperature: f32,
+    hx_temperature: f32,
+    heating_element_on: bool,
+    boost_countdown: u32,
+    warmed_up: bool,
+    /// Seconds left of the running pump run, or until the next one.
+    pump_left: u32,
+    pump_on: bool,
+    pump_runs: u32,
+    /// State of the random number generator of the shot lengths.
+    seed: u32,
+}
+
+impl Simulator {
+    /// A machine which has just been switched on.
+    pub fn new() -> Self {
+        Self {
+            steam_temperature: ROOM_TEMPERATURE,
+            hx_temperature: ROOM_TEMPERATURE,
+            heating_element_on: true,
+            boost_countdown: 0,
+            warmed_up: false,
+            pump_left: PUMP_PAUSE,
+            pump_on: false,
+            pump_runs: 0,
+            seed: 0x2545_f491,
+        }
+    }
+
+    fn random(&mut self) -> u32 {
+        // xorshift32
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+
+    fn next_pump_run(&mut self) -> u32 {
+        self.pump_runs += 1;
+        if self.pump_runs.is_multiple_of(FLUSH_EVERY) {
+            FLUSH_SECONDS
+        } else {
+            let span = SHOT_SECONDS.end() - SHOT_SECONDS.start() + 1;
+            SHOT_SECONDS.start() + self.random() % span
+        }
+    }
+
+    /// Move the machine on by a second.
+    fn step(&mut self) {
+        // The thermostat keeps the steam boiler within a couple of degrees
+        // below the target.
+        if self.steam_temperature >= TARGET_STEAM_TEMPERATURE {
+            self.heating_element_on = false;
+            if !self.warmed_up {
+                self.warmed_up = true;
+                self.boost_countdown = BOOST_SECONDS;
+            }
+        } else if self.steam_temperature < TARGET_STEAM_TEMPERATURE - 2.0 {
+            self.heating_element_on = true;
+        }
+        if self.heating_element_on {
+            self.steam_temperature += HEATING_RATE;
+        } else {
+            self.steam_temperature -= COOLING_RATE;
+        }
+        self.boost_countdown = self.boost_countdown.saturating_sub(1);
+
+        let hx_target = (self.steam_temperature * HX_SHARE).max(ROOM_TEMPERATURE);
+        self.hx_temperature += (hx_target - self.hx_temperature) * 0.05;
+
+        // The shots are pulled only once the machine has warmed up.
+        if self.warmed_up {
+            self.pump_left = self.pump_left.saturating_sub(1);
+            if self.pump_left == 0 {
+                self.pump_on = !self.pump_on;
+                self.pump_left = if self.pump_on {
+                    self.next_pump_run()
+                } else {
+                    PUMP_PAUSE
+                };
+            }
+        }
+        if self.pump_on {
+            self.steam_temperature -= PUMP_COOLING_RATE;
+            self.hx_temperature -= HX_PUMP_COOLING_RATE;
+        }
+    }
+
+    /// The status line for the next second.
+    pub fn next_line(&mut self) -> String {
+        self.step();
+        format!(
+            "C1.19,{:03},{:03},{:03},{:04},{},{}",
+            self.steam_temperature.round() as i64,
+            TARGET_STEAM_TEMPERATURE as i64,
+            self.hx_temperature.round() as i64,
+            self.boost_countdown,
+            self.heating_element_on as u8,
+            self.pump_on as u8
+        )
+    }
+}
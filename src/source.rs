@@ -0,0 +1,539 @@
+use bytes::BytesMut;
+use futures::sink::SinkExt;
+use futures::stream::{self, Stream, StreamExt};
+
+use std::{
+    convert::TryInto,
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    mem,
+    os::unix::{io::FromRawFd, net},
+    path::Path,
+    pin::Pin,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::time;
+use tokio_serial::{DataBits, FlowControl, Parity, SerialPortBuilderExt, StopBits};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::config::{self, SerialConfig};
+use crate::protocol::MachineProtocol;
+use crate::simulator::Simulator;
+
+/// A stream of status lines coming from the Mara X (or something pretending
+/// to be one).
+pub type LineStream = Pin<Box<dyn Stream<Item = io::Result<String>> + Send>>;
+
+// The status lines are about 30 bytes long. Data without a line ending for
+// much longer than that is noise, and it's thrown away instead of being
+// buffered for ever.
+const MAX_LINE_LENGTH: usize = 256;
+
+// Serial port codec implementation
+struct LineCodec;
+
+impl Decoder for LineCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // The Mara X ends the lines with "\r\n". Noise on the line, at
+        // startup in particular, can also give bytes which aren't valid
+        // UTF-8; they are left for the parser to skip instead of failing the
+        // whole stream.
+        while let Some(n) = src.as_ref().iter().position(|b| *b == b'\n') {
+            let line = src.split_to(n + 1);
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.is_empty() {
+                return Ok(Some(line.to_string()));
+            }
+        }
+        if src.len() > MAX_LINE_LENGTH {
+            src.clear();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no line ending in {} bytes", MAX_LINE_LENGTH),
+            ));
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<String> for LineCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: String, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.extend_from_slice(item.as_bytes());
+        Ok(())
+    }
+}
+
+/// Read status lines from `io`, and send the keep-alive data of `config` to
+/// it if there is an interval for it.
+fn lines<T>(io: T, config: &SerialConfig) -> LineStream
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let framed = LineCodec.framed(io);
+    if config.keepalive_seconds == 0 {
+        return Box::pin(framed);
+    }
+
+    let interval = time::interval(time::Duration::from_secs(config.keepalive_seconds.into()));
+    let keepalive = config.keepalive_data.clone();
+    Box::pin(stream::unfold(
+        (framed, interval, keepalive),
+        |(mut framed, mut interval, keepalive)| async move {
+            loop {
+                tokio::select! {
+                    line = framed.next() => {
+                        return line.map(|line| (line, (framed, interval, keepalive)));
+                    }
+                    _ = interval.tick() => {
+                        if let Err(e) = framed.send(keepalive.clone()).await {
+                            return Some((Err(e), (framed, interval, keepalive)));
+                        }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Read status lines from the Mara X serial port.
+pub fn serial(config: &SerialConfig) -> Result<LineStream, Box<dyn Error>> {
+    let data_bits = match config.data_bits {
+        5 => DataBits::Five,
+        6 => DataBits::Six,
+        7 => DataBits::Seven,
+        _ => DataBits::Eight,
+    };
+    let parity = match config.parity {
+        config::Parity::None => Parity::None,
+        config::Parity::Odd => Parity::Odd,
+        config::Parity::Even => Parity::Even,
+    };
+    let stop_bits = match config.stop_bits {
+        2 => StopBits::Two,
+        _ => StopBits::One,
+    };
+    let flow_control = match config.flow_control {
+        config::FlowControl::None => FlowControl::None,
+        config::FlowControl::Software => FlowControl::Software,
+        config::FlowControl::Hardware => FlowControl::Hardware,
+    };
+    let mut serial_port = tokio_serial::new(&config.device, config.baud)
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .flow_control(flow_control)
+        .open_native_async()?;
+    serial_port.set_exclusive(false)?;
+    Ok(lines(serial_port, config))
+}
+
+/// Read status lines from a serial port bridged over TCP, like ser2net or an
+/// ESP8266 streaming the UART.
+pub async fn tcp(address: &str, config: &SerialConfig) -> io::Result<LineStream> {
+    let stream = TcpStream::connect(address).await?;
+    Ok(lines(stream, config))
+}
+
+// Address of an RFCOMM socket, struct sockaddr_rc in <bluetooth/rfcomm.h>.
+#[repr(C)]
+struct SockaddrRc {
+    rc_family: libc::sa_family_t,
+    rc_bdaddr: [u8; 6],
+    rc_channel: u8,
+}
+
+// From <bluetooth/bluetooth.h>, which the libc crate doesn't have.
+const BTPROTO_RFCOMM: libc::c_int = 3;
+
+// The serial port profile of the HC-05 and HC-06 modules is on this channel.
+const DEFAULT_RFCOMM_CHANNEL: u8 = 1;
+
+/// Parse a Bluetooth address like "98:D3:31:F5:12:34", optionally followed
+/// by the RFCOMM channel as in "98:D3:31:F5:12:34/2".
+fn parse_bluetooth_address(address: &str) -> Result<SockaddrRc, String> {
+    let (device, channel) = match address.split_once('/') {
+        Some((device, channel)) => match channel.parse() {
+            Ok(channel @ 1..=30) => (device, channel),
+            _ => return Err(format!("bad RFCOMM channel \"{}\"", channel)),
+        },
+        None => (address, DEFAULT_RFCOMM_CHANNEL),
+    };
+    let bytes: Vec<u8> = device
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("bad Bluetooth address \"{}\"", device))?;
+    let mut bdaddr: [u8; 6] = bytes
+        .try_into()
+        .map_err(|_| format!("bad Bluetooth address \"{}\"", device))?;
+    // The address is stored with the last byte first.
+    bdaddr.reverse();
+    Ok(SockaddrRc {
+        rc_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        rc_bdaddr: bdaddr,
+        rc_channel: channel,
+    })
+}
+
+// Open an RFCOMM connection. This blocks until the connection is made or
+// fails, which can take seconds.
+fn connect_rfcomm(address: &SockaddrRc) -> io::Result<net::UnixStream> {
+    // SAFETY: the socket is closed by the UnixStream once created, and the
+    // address is a valid sockaddr_rc of the size given.
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+            BTPROTO_RFCOMM,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Only reading and writing are needed, which work the same for all
+        // stream sockets.
+        let stream = net::UnixStream::from_raw_fd(fd);
+        let result = libc::connect(
+            fd,
+            address as *const SockaddrRc as *const libc::sockaddr,
+            mem::size_of::<SockaddrRc>() as libc::socklen_t,
+        );
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stream)
+    }
+}
+
+/// Read status lines from a Bluetooth serial module, like an HC-05 on the
+/// Mara X UART, without binding it to an rfcomm device first.
+pub async fn bluetooth(address: &str, config: &SerialConfig) -> io::Result<LineStream> {
+    let address = parse_bluetooth_address(address).map_err(io::Error::other)?;
+    let stream = tokio::task::spawn_blocking(move || connect_rfcomm(&address)).await??;
+    stream.set_nonblocking(true)?;
+    Ok(lines(UnixStream::from_std(stream)?, config))
+}
+
+/// The `serial.device` which makes the serial port to be looked for among
+/// all of them.
+pub const AUTO_DEVICE: &str = "auto";
+
+/// Start of a `serial.device` which is a TCP address instead of a serial
+/// port, as in "tcp://192.168.1.20:23".
+pub const TCP_PREFIX: &str = "tcp://";
+
+/// Start of a `serial.device` which is the USB serial adapter with the given
+/// vendor and product IDs, as in "usb:0403:6001", wherever it's plugged in.
+pub const USB_PREFIX: &str = "usb:";
+
+/// Start of a `serial.device` which is a Bluetooth serial module instead of
+/// a serial port, as in "bt://98:D3:31:F5:12:34".
+pub const BLUETOOTH_PREFIX: &str = "bt://";
+
+// A TCP or Bluetooth connection can die without being closed, so it's opened
+// again if nothing arrives for this long.
+const TCP_SILENCE: time::Duration = time::Duration::from_secs(30);
+
+// How often to look for an unplugged serial port to come back.
+const HOTPLUG_POLL: time::Duration = time::Duration::from_secs(1);
+
+// How long to listen to each serial port when looking for the Mara X.
+const DISCOVERY_PROBE: time::Duration = time::Duration::from_secs(2);
+
+/// Find the serial port the Mara X is connected to, by listening to each
+/// port in turn until one sends a valid status line.
+pub async fn discover(
+    config: &SerialConfig,
+    protocol: &dyn MachineProtocol,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let ports = tokio_serial::available_ports()?;
+    for port in ports {
+        let candidate = SerialConfig {
+            device: port.port_name,
+            ..config.clone()
+        };
+        let mut lines = match serial(&candidate) {
+            Ok(lines) => lines,
+            Err(_) => continue,
+        };
+        let deadline = time::Instant::now() + DISCOVERY_PROBE;
+        while let Ok(Some(Ok(line))) = time::timeout_at(deadline, lines.next()).await {
+            if protocol.parse(&line).is_ok() {
+                println!("Found the Mara X at {}", candidate.device);
+                return Ok(candidate.device);
+            }
+        }
+    }
+    Err("no serial port sends Mara X status lines".into())
+}
+
+/// Parse the vendor and product IDs of a USB device, like "0403:6001".
+fn parse_usb_id(id: &str) -> Result<(u16, u16), String> {
+    let parse = |id: &str| u16::from_str_radix(id, 16).ok();
+    match id.split_once(':') {
+        Some((vendor, product)) => match (parse(vendor), parse(product)) {
+            (Some(vendor), Some(product)) => Ok((vendor, product)),
+            _ => Err(format!("bad USB ID \"{}\"", id)),
+        },
+        None => Err(format!("bad USB ID \"{}\"", id)),
+    }
+}
+
+/// Find the serial port of the USB adapter with the IDs, from the devices
+/// the tty devices belong to in sysfs.
+fn find_usb(vendor: u16, product: u16) -> Option<String> {
+    let read_id = |path: &Path| {
+        let id = fs::read_to_string(path).ok()?;
+        u16::from_str_radix(id.trim(), 16).ok()
+    };
+    for entry in fs::read_dir("/sys/class/tty").ok()?.flatten() {
+        let device = match fs::canonicalize(entry.path().join("device")) {
+            Ok(device) => device,
+            Err(_) => continue,
+        };
+        // The IDs are in the USB device, a couple of levels up from the
+        // interface the tty is on.
+        let usb = device.ancestors().find(|d| d.join("idVendor").exists());
+        if let Some(usb) = usb {
+            if read_id(&usb.join("idVendor")) == Some(vendor)
+                && read_id(&usb.join("idProduct")) == Some(product)
+            {
+                return Some(format!("/dev/{}", entry.file_name().to_string_lossy()));
+            }
+        }
+    }
+    None
+}
+
+/// Whether the serial port of `config` is unplugged. Only a device path
+/// or a USB adapter can be told to be missing.
+fn device_missing(config: &SerialConfig) -> bool {
+    if let Some(id) = config.device.strip_prefix(USB_PREFIX) {
+        return match parse_usb_id(id) {
+            Ok((vendor, product)) => find_usb(vendor, product).is_none(),
+            Err(_) => false,
+        };
+    }
+    config.device.starts_with('/') && !Path::new(&config.device).exists()
+}
+
+/// Wait for `wait` before opening the serial port again, or until it's
+/// plugged back in if it was unplugged.
+async fn wait_for_device(config: &SerialConfig, wait: time::Duration) {
+    let deadline = time::Instant::now() + wait;
+    if !device_missing(config) {
+        time::sleep_until(deadline).await;
+        return;
+    }
+    while time::Instant::now() < deadline {
+        time::sleep(HOTPLUG_POLL).await;
+        if !device_missing(config) {
+            println!("{} is back", config.device);
+            return;
+        }
+    }
+}
+
+/// Open the status line stream `config` tells: a serial port, a TCP or
+/// Bluetooth connection, or the serial port found by `discover()`.
+pub async fn open(
+    config: &SerialConfig,
+    protocol: &dyn MachineProtocol,
+) -> Result<LineStream, String> {
+    if let Some(address) = config.device.strip_prefix(TCP_PREFIX) {
+        return tcp(address, config)
+            .await
+            .map_err(|e| format!("failed to connect to {}: {}", address, e));
+    }
+    if let Some(address) = config.device.strip_prefix(BLUETOOTH_PREFIX) {
+        return bluetooth(address, config)
+            .await
+            .map_err(|e| format!("failed to connect to {}: {}", address, e));
+    }
+    let device = if config.device == AUTO_DEVICE {
+        discover(config, protocol)
+            .await
+            .map_err(|e| e.to_string())?
+    } else if let Some(id) = config.device.strip_prefix(USB_PREFIX) {
+        let (vendor, product) = parse_usb_id(id)?;
+        find_usb(vendor, product).ok_or(format!("no USB serial adapter {} plugged in", id))?
+    } else {
+        config.device.clone()
+    };
+    let found = SerialConfig {
+        device,
+        ..config.clone()
+    };
+    serial(&found).map_err(|e| format!("failed to open {}: {}", found.device, e))
+}
+
+// How long to wait before opening the serial port again after an error, at
+// first and at most. The wait doubles on each failure in a row.
+const SERIAL_RETRY_MIN: time::Duration = time::Duration::from_secs(1);
+const SERIAL_RETRY_MAX: time::Duration = time::Duration::from_secs(60);
+
+fn next_retry(retry: Option<time::Duration>) -> Option<time::Duration> {
+    Some(retry.map_or(SERIAL_RETRY_MIN, |wait| (wait * 2).min(SERIAL_RETRY_MAX)))
+}
+
+/// Read status lines from the Mara X serial port like `serial()`, but keep
+/// going if the port can't be opened or goes away: the error is passed on
+/// and the port is opened again, waiting longer after each failed attempt.
+/// With the `auto` device, the port is looked for again every time. An
+/// unplugged serial port is opened as soon as it's plugged back in. A TCP or
+/// Bluetooth connection is also opened again after a while without any data.
+pub fn serial_reconnecting(
+    config: SerialConfig,
+    protocol: &'static dyn MachineProtocol,
+) -> LineStream {
+    type State = (SerialConfig, Option<LineStream>, Option<time::Duration>);
+
+    Box::pin(stream::unfold(
+        (config, None, None),
+        move |(config, port, retry): State| async move {
+            let mut port = match port {
+                Some(port) => port,
+                None => {
+                    if let Some(wait) = retry {
+                        wait_for_device(&config, wait).await;
+                    }
+                    match open(&config, protocol).await {
+                        Ok(port) => port,
+                        Err(message) => {
+                            let state = (config, None, next_retry(retry));
+                            return Some((Err(io::Error::other(message)), state));
+                        }
+                    }
+                }
+            };
+
+            // The line codec gives up after an error, so the port is opened
+            // again after any error as well as after it has been closed.
+            let network = config.device.starts_with(TCP_PREFIX)
+                || config.device.starts_with(BLUETOOTH_PREFIX);
+            let next = if network {
+                time::timeout(TCP_SILENCE, port.next())
+                    .await
+                    .unwrap_or_else(|_| {
+                        let message = format!("no data from {}", config.device);
+                        Some(Err(io::Error::other(message)))
+                    })
+            } else {
+                port.next().await
+            };
+            match next {
+                Some(Ok(line)) => Some((Ok(line), (config, Some(port), None))),
+                Some(Err(e)) => {
+                    let e = if device_missing(&config) {
+                        io::Error::other(format!("{} was unplugged", config.device))
+                    } else {
+                        e
+                    };
+                    Some((Err(e), (config, None, next_retry(retry))))
+                }
+                None => {
+                    let e = io::Error::other(format!("{} was closed", config.device));
+                    Some((Err(e), (config, None, next_retry(retry))))
+                }
+            }
+        },
+    ))
+}
+
+/// Generate status lines of a machine which warms up and then has a shot
+/// pulled every couple of minutes.
+pub fn simulate() -> LineStream {
+    let interval = time::interval(time::Duration::from_secs(1));
+
+    Box::pin(stream::unfold(
+        (interval, Simulator::new()),
+        |(mut interval, mut simulator)| async move {
+            interval.tick().await;
+            Some((Ok(simulator.next_line()), (interval, simulator)))
+        },
+    ))
+}
+
+/// Append every line read from `lines` to the file at `path`, after the
+/// time it was received in the RFC 3339 format and a space.
+pub fn record(lines: LineStream, path: &Path) -> Result<LineStream, Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let path = path.to_path_buf();
+
+    Ok(Box::pin(lines.inspect(move |line| {
+        if let Ok(line) = line {
+            let now = chrono::Local::now().to_rfc3339();
+            if let Err(e) = writeln!(file, "{} {}", now, line) {
+                println!("Failed to write to {}: {}", path.display(), e);
+            }
+        }
+    })))
+}
+
+/// Split a line of a recording into the time it was received and the status
+/// line. The lines of plain serial logs have no times.
+fn recorded_line(line: &str) -> (Option<chrono::DateTime<chrono::FixedOffset>>, &str) {
+    match line.split_once(' ') {
+        Some((time, rest)) => match chrono::DateTime::parse_from_rfc3339(time) {
+            Ok(time) => (Some(time), rest),
+            Err(_) => (None, line),
+        },
+        None => (None, line),
+    }
+}
+
+/// Feed status lines from a recorded serial log. The lines of a recording
+/// made with `record()` are fed with the delays they were received with,
+/// and the lines of a plain log one per `interval`. Either is sped up
+/// `speed` times.
+pub fn replay(
+    path: &Path,
+    interval: time::Duration,
+    speed: f64,
+) -> Result<LineStream, Box<dyn Error>> {
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err("the replay speed must be more than 0".into());
+    }
+    // A raw capture of the serial port can have the same garbage in it as
+    // the port itself.
+    let contents =
+        fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let contents = String::from_utf8_lossy(&contents);
+
+    // The delay before each line.
+    let mut lines: Vec<(time::Duration, String)> = Vec::new();
+    let mut previous: Option<chrono::DateTime<chrono::FixedOffset>> = None;
+    for line in contents.lines().filter(|l| !l.is_empty()) {
+        let (received, line) = recorded_line(line);
+        let delay = match (previous, received) {
+            _ if lines.is_empty() => time::Duration::ZERO,
+            (Some(previous), Some(received)) => (received - previous)
+                .to_std()
+                .unwrap_or(time::Duration::ZERO),
+            _ => interval,
+        };
+        previous = received;
+        lines.push((delay.div_f64(speed), line.to_string()));
+    }
+
+    Ok(Box::pin(stream::unfold(
+        lines.into_iter(),
+        |mut lines| async move {
+            let (delay, line) = lines.next()?;
+            time::sleep(delay).await;
+            Some((Ok(line), lines))
+        },
+    )))
+}
@@ -0,0 +1,62 @@
+//! Telling when the machine has settled and is ready for a shot.
+
+use std::collections::VecDeque;
+
+use tokio::time::{Duration, Instant};
+
+use crate::config::ReadyConfig;
+
+/// Follows the steam boiler temperature and the heating element to tell
+/// when the temperature has stayed close to the target long enough, with
+/// the heating element mostly off.
+pub struct StabilityTracker {
+    /// When the temperature last came within the band.
+    in_band_since: Option<Instant>,
+    /// Heating element states from the stable period.
+    heating: VecDeque<(Instant, bool)>,
+}
+
+impl StabilityTracker {
+    pub fn new() -> Self {
+        Self {
+            in_band_since: None,
+            heating: VecDeque::new(),
+        }
+    }
+
+    /// Add a status reading, and return true if the machine is ready.
+    pub fn update(
+        &mut self,
+        config: &ReadyConfig,
+        steam_temperature: i64,
+        target_steam_temperature: i64,
+        heating_element_on: bool,
+    ) -> bool {
+        let now = Instant::now();
+        let window = Duration::from_secs(config.stable_seconds.into());
+
+        let band = i64::from(config.band_degrees);
+        if (steam_temperature - target_steam_temperature).abs() <= band {
+            self.in_band_since.get_or_insert(now);
+        } else {
+            self.in_band_since = None;
+        }
+
+        while let Some(&(time, _)) = self.heating.front() {
+            if now.duration_since(time) <= window {
+                break;
+            }
+            self.heating.pop_front();
+        }
+        self.heating.push_back((now, heating_element_on));
+
+        // The status lines come at a steady pace, so counting them gives
+        // the share of the time.
+        let on = self.heating.iter().filter(|&&(_, on)| on).count();
+        let duty_ok = on * 100 <= self.heating.len() * config.max_heating_percent as usize;
+        let stable = self
+            .in_band_since
+            .is_some_and(|since| now.duration_since(since) >= window);
+        stable && duty_ok
+    }
+}
@@ -0,0 +1,259 @@
+//! What the timer is doing, as one state instead of a set of flags. The
+//! serial loop, the manual timer and the signals move it on their events,
+//! and the display loop of the machine follows it.
+
+use tokio::sync::watch;
+
+/// The state of the shot timer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// Waiting for the pump.
+    Idle,
+    /// The pump is running, but not yet for long enough to be a shot.
+    Flushing,
+    /// The pump has run for long enough to be a shot.
+    Brewing,
+    /// The pump has stopped, and the final time is on the screen.
+    Holding,
+    /// No status lines from the machine for a while.
+    Stale,
+    /// The program is exiting, and nothing starts any more.
+    Exiting,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Flushing => "flushing",
+            State::Brewing => "brewing",
+            State::Holding => "holding",
+            State::Stale => "stale",
+            State::Exiting => "exiting",
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self, State::Flushing | State::Brewing)
+    }
+}
+
+/// Things that move the timer from one state to another.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The shot clock started, with the pump or by hand.
+    PumpStarted,
+    /// The pump has run for the minimum shot time.
+    MinShotTimeReached,
+    PumpStopped,
+    /// The final screen of the pump run is done.
+    HoldEnded,
+    DataLost,
+    DataRestored,
+    /// The program was told to exit.
+    Exit,
+}
+
+/// Moves between the states on the events. Losing the data doesn't stop a
+/// running timer by itself, since the timer can also be run by hand, so
+/// whether the data is stale is kept apart from the state.
+#[derive(Debug, Copy, Clone)]
+pub struct StateMachine {
+    state: State,
+    stale: bool,
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self {
+            state: State::Idle,
+            stale: false,
+        }
+    }
+}
+
+impl StateMachine {
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Handle `event`, and return true if the state changed.
+    pub fn handle(&mut self, event: Event) -> bool {
+        let previous = self.state;
+        self.state = match (event, self.state) {
+            (_, State::Exiting) => State::Exiting,
+            (Event::Exit, _) => State::Exiting,
+            (Event::PumpStarted, state) if !state.is_running() => State::Flushing,
+            (Event::MinShotTimeReached, State::Flushing) => State::Brewing,
+            (Event::PumpStopped, state) if state.is_running() => State::Holding,
+            (Event::HoldEnded, State::Holding) => self.resting(),
+            (Event::DataLost, state) => {
+                self.stale = true;
+                match state {
+                    State::Idle => State::Stale,
+                    state => state,
+                }
+            }
+            (Event::DataRestored, state) => {
+                self.stale = false;
+                match state {
+                    State::Stale => State::Idle,
+                    state => state,
+                }
+            }
+            (_, state) => state,
+        };
+        self.state != previous
+    }
+
+    /// Whether the display loop has something else to do than to wait: a
+    /// pump run to time, or the exit.
+    pub fn is_busy(&self) -> bool {
+        self.state.is_running() || self.state == State::Exiting
+    }
+
+    /// The state when nothing is going on.
+    fn resting(&self) -> State {
+        if self.stale {
+            State::Stale
+        } else {
+            State::Idle
+        }
+    }
+}
+
+/// Move the timer state machine on `event`, and tell the watchers if the
+/// state changed.
+pub fn transition(state: &watch::Sender<StateMachine>, event: Event) {
+    state.send_if_modified(|machine| {
+        let changed = machine.handle(event);
+        if changed {
+            println!("Timer {}", machine.state().name());
+        }
+        changed
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(events: &[Event]) -> StateMachine {
+        let mut machine = StateMachine::default();
+        for &event in events {
+            machine.handle(event);
+        }
+        machine
+    }
+
+    #[test]
+    fn shot() {
+        let mut machine = StateMachine::default();
+        assert_eq!(machine.state(), State::Idle);
+        assert!(machine.handle(Event::PumpStarted));
+        assert_eq!(machine.state(), State::Flushing);
+        assert!(machine.handle(Event::MinShotTimeReached));
+        assert_eq!(machine.state(), State::Brewing);
+        assert!(machine.handle(Event::PumpStopped));
+        assert_eq!(machine.state(), State::Holding);
+        assert!(machine.handle(Event::HoldEnded));
+        assert_eq!(machine.state(), State::Idle);
+    }
+
+    #[test]
+    fn flush() {
+        let machine = run(&[Event::PumpStarted, Event::PumpStopped]);
+        assert_eq!(machine.state(), State::Holding);
+    }
+
+    #[test]
+    fn repeated_start_is_ignored() {
+        let mut machine = run(&[Event::PumpStarted, Event::MinShotTimeReached]);
+        assert!(!machine.handle(Event::PumpStarted));
+        assert_eq!(machine.state(), State::Brewing);
+    }
+
+    #[test]
+    fn new_shot_interrupts_hold() {
+        let machine = run(&[Event::PumpStarted, Event::PumpStopped, Event::PumpStarted]);
+        assert_eq!(machine.state(), State::Flushing);
+    }
+
+    #[test]
+    fn events_out_of_place_are_ignored() {
+        let mut machine = StateMachine::default();
+        assert!(!machine.handle(Event::PumpStopped));
+        assert!(!machine.handle(Event::HoldEnded));
+        assert!(!machine.handle(Event::MinShotTimeReached));
+        assert_eq!(machine.state(), State::Idle);
+    }
+
+    #[test]
+    fn stale_when_idle() {
+        let mut machine = run(&[Event::DataLost]);
+        assert_eq!(machine.state(), State::Stale);
+        assert!(machine.is_stale());
+        assert!(machine.handle(Event::DataRestored));
+        assert_eq!(machine.state(), State::Idle);
+        assert!(!machine.is_stale());
+    }
+
+    #[test]
+    fn data_lost_during_shot() {
+        let mut machine = run(&[Event::PumpStarted, Event::DataLost]);
+        assert_eq!(machine.state(), State::Flushing);
+        machine.handle(Event::PumpStopped);
+        assert_eq!(machine.state(), State::Holding);
+        machine.handle(Event::HoldEnded);
+        assert_eq!(machine.state(), State::Stale);
+    }
+
+    #[test]
+    fn data_restored_during_hold() {
+        let machine = run(&[
+            Event::PumpStarted,
+            Event::DataLost,
+            Event::PumpStopped,
+            Event::DataRestored,
+            Event::HoldEnded,
+        ]);
+        assert_eq!(machine.state(), State::Idle);
+    }
+
+    #[test]
+    fn shot_started_while_stale() {
+        let machine = run(&[Event::DataLost, Event::PumpStarted]);
+        assert_eq!(machine.state(), State::Flushing);
+        assert!(machine.is_stale());
+    }
+
+    #[test]
+    fn exit_ends_a_hold() {
+        let mut machine = run(&[Event::PumpStarted, Event::PumpStopped]);
+        assert!(!machine.is_busy());
+        assert!(machine.handle(Event::Exit));
+        assert_eq!(machine.state(), State::Exiting);
+        assert!(machine.is_busy());
+    }
+
+    #[test]
+    fn nothing_starts_after_exit() {
+        let mut machine = run(&[Event::Exit]);
+        assert!(!machine.handle(Event::PumpStarted));
+        assert!(!machine.handle(Event::DataLost));
+        assert!(!machine.handle(Event::HoldEnded));
+        assert_eq!(machine.state(), State::Exiting);
+    }
+
+    #[test]
+    fn exit_during_shot() {
+        let mut machine = run(&[Event::PumpStarted, Event::Exit]);
+        assert_eq!(machine.state(), State::Exiting);
+        assert!(!machine.handle(Event::PumpStopped));
+        assert_eq!(machine.state(), State::Exiting);
+    }
+}
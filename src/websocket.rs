@@ -0,0 +1,174 @@
+//! The WebSocket (RFC 6455) of the live updates: the opening handshake, and
+//! pushing text messages to the client. The framing, and answering the pings
+//! and the closing frame of the client, are left to tungstenite.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use futures::{SinkExt, StreamExt};
+
+use hyper::{header, Body, Request, Response, StatusCode};
+
+use sha1::{Digest, Sha1};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+use tokio_tungstenite::tungstenite::protocol::{Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+
+// Appended to the key of the client for the accept header.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// The client has nothing to say but short control frames, so anything
+// longer closes the connection.
+const MAX_PAYLOAD: usize = 1024;
+
+/// The value of the accept header for the key of the client.
+fn accept_key(key: &[u8]) -> String {
+    STANDARD.encode(Sha1::new().chain_update(key).chain_update(GUID).finalize())
+}
+
+/// The answer to a request to open a WebSocket, or an error response if it
+/// isn't one.
+pub fn handshake(req: &Request<Body>) -> Result<Response<Body>, (StatusCode, &'static str)> {
+    let headers = req.headers();
+    let has = |name: header::HeaderName, value: &str| {
+        headers.get_all(name).iter().any(|v| {
+            v.to_str()
+                .is_ok_and(|v| v.split(',').any(|v| v.trim().eq_ignore_ascii_case(value)))
+        })
+    };
+    if !has(header::UPGRADE, "websocket") || !has(header::CONNECTION, "upgrade") {
+        return Err((StatusCode::UPGRADE_REQUIRED, "a WebSocket is needed"));
+    }
+    if headers
+        .get(header::SEC_WEBSOCKET_VERSION)
+        .map(|v| v.as_bytes())
+        != Some(b"13")
+    {
+        return Err((StatusCode::BAD_REQUEST, "unsupported WebSocket version"));
+    }
+    let key = match headers.get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) => key.as_bytes(),
+        None => return Err((StatusCode::BAD_REQUEST, "no WebSocket key")),
+    };
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept_key(key))
+        .body(Body::empty())
+        .unwrap())
+}
+
+/// Send the messages from `messages` to the client as text frames over the
+/// upgraded `socket`, until either side closes the connection.
+pub async fn send(
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+    mut messages: mpsc::Receiver<String>,
+) -> Result<(), Error> {
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_PAYLOAD),
+        max_frame_size: Some(MAX_PAYLOAD),
+        ..WebSocketConfig::default()
+    };
+    let socket = WebSocketStream::from_raw_socket(socket, Role::Server, Some(config)).await;
+    let (mut sink, mut frames) = socket.split();
+
+    loop {
+        tokio::select! {
+            message = messages.recv() => match message {
+                Some(message) => sink.send(Message::Text(message)).await?,
+                None => break,
+            },
+            // Reading answers the pings and the closing frame, and nothing
+            // else from the client matters.
+            frame = frames.next() => match frame {
+                Some(Ok(_)) => {}
+                Some(Err(Error::ConnectionClosed)) | None => return Ok(()),
+                Some(Err(e)) => return Err(e),
+            },
+        }
+    }
+    sink.close().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::duplex;
+
+    #[test]
+    fn accept_key_of_rfc() {
+        // The example of RFC 6455.
+        assert_eq!(
+            accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn handshake_needs_upgrade() {
+        let req = Request::get("/api/v1/stream").body(Body::empty()).unwrap();
+        let (status, _) = handshake(&req).unwrap_err();
+        assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+
+        let req = Request::get("/api/v1/stream")
+            .header(header::UPGRADE, "websocket")
+            .header(header::CONNECTION, "keep-alive, Upgrade")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .body(Body::empty())
+            .unwrap();
+        let response = handshake(&req).unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    }
+
+    async fn client(socket: tokio::io::DuplexStream) -> WebSocketStream<tokio::io::DuplexStream> {
+        WebSocketStream::from_raw_socket(socket, Role::Client, None).await
+    }
+
+    #[tokio::test]
+    async fn messages_and_close() {
+        let (server, client_socket) = duplex(4096);
+        let (messages_tx, messages) = mpsc::channel(4);
+        let server = tokio::spawn(send(server, messages));
+        let mut client = client(client_socket).await;
+
+        messages_tx.send("first".to_string()).await.unwrap();
+        let message = client.next().await.unwrap().unwrap();
+        assert_eq!(message, Message::Text("first".to_string()));
+
+        client.send(Message::Ping(b"ping".to_vec())).await.unwrap();
+        let message = client.next().await.unwrap().unwrap();
+        assert_eq!(message, Message::Pong(b"ping".to_vec()));
+
+        client.close(None).await.unwrap();
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn oversize_frame_closes() {
+        let (server, client_socket) = duplex(4096);
+        let (_messages_tx, messages) = mpsc::channel(4);
+        let server = tokio::spawn(send(server, messages));
+        let mut client = client(client_socket).await;
+
+        let _ = client.send(Message::Binary(vec![0; MAX_PAYLOAD + 1])).await;
+        assert!(server.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn end_of_messages_closes() {
+        let (server, client_socket) = duplex(4096);
+        let (messages_tx, messages) = mpsc::channel(4);
+        let server = tokio::spawn(send(server, messages));
+        let mut client = client(client_socket).await;
+
+        drop(messages_tx);
+        assert!(matches!(client.next().await, Some(Ok(Message::Close(_)))));
+        assert!(server.await.unwrap().is_ok());
+    }
+}